@@ -0,0 +1,93 @@
+// Compares the MemTable backends across common workloads.
+//
+// Run with `cargo bench --bench mem_table_backends`. Each workload is timed
+//	over a fixed number of operations and reported in nanoseconds per
+//	operation.
+use std::hint::black_box;
+use std::time::Instant;
+
+use db_ngn_memtable::mem_table::{IterOptions, MemTable};
+use db_ngn_memtable::mem_table_backend::{new_backend, BackendKind, MemTableBackend};
+
+const OPS: usize = 50_000;
+const VALUE: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit";
+
+
+// Generates keys in ascending order
+fn sequential_keys() -> Vec<Vec<u8>> {
+	(0..OPS).map(|i| format!("key{:010}", i).into_bytes()).collect()
+}
+
+// Generates keys in a scattered but repeatable order
+fn random_keys() -> Vec<Vec<u8>> {
+	let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+	(0..OPS).map(|_| {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		format!("key{:010}", state % (OPS as u64 * 10)).into_bytes()
+	}).collect()
+}
+
+fn fill(kind: BackendKind, keys: &[Vec<u8>]) -> Box<dyn MemTableBackend> {
+	let mut table = new_backend(kind);
+	for (ts, key) in keys.iter().enumerate() {
+		table.set(key, VALUE, ts as u128);
+	}
+	table
+}
+
+fn report(workload: &str, kind: BackendKind, start: Instant, ops: usize) {
+	let per_op = start.elapsed().as_nanos() / ops as u128;
+	println!("{:<20} {:<6} {:>8} ns/op", workload, format!("{:?}", kind), per_op);
+}
+
+fn main() {
+	let sequential = sequential_keys();
+	let random = random_keys();
+
+	for kind in [BackendKind::Vec, BackendKind::BTree] {
+		let start = Instant::now();
+		black_box(fill(kind, &sequential));
+		report("sequential insert", kind, start, OPS);
+
+		let start = Instant::now();
+		black_box(fill(kind, &random));
+		report("random insert", kind, start, OPS);
+
+		let table = fill(kind, &random);
+		let start = Instant::now();
+		for key in random.iter() {
+			black_box(table.get(key));
+		}
+		report("point get", kind, start, OPS);
+
+		let start = Instant::now();
+		let mut scanned = 0;
+		for _ in 0..100 {
+			scanned += table.iter().take(OPS / 100).count();
+		}
+		report("range scan", kind, start, scanned);
+
+		let mut table = new_backend(kind);
+		let start = Instant::now();
+		for (ts, key) in random.iter().enumerate() {
+			match ts % 4 {
+				0 | 1 => { table.set(key, VALUE, ts as u128); },
+				2 => { black_box(table.get(key)); },
+				_ => table.delete(key, ts as u128),
+			}
+		}
+		report("mixed", kind, start, OPS);
+	}
+
+	// Bounded scans are only available on the Vec backed MemTable
+	let mut table = MemTable::new();
+	for (ts, key) in random.iter().enumerate() {
+		table.set(key, VALUE, ts as u128);
+	}
+	let options = IterOptions::new().lower(b"key0000100000").upper(b"key0000200000");
+	let start = Instant::now();
+	let scanned = (0..100).map(|_| table.iter_with_options(&options).count()).sum();
+	report("bounded range scan", BackendKind::Vec, start, scanned);
+}
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use db_ngn_memtable::rocksdb::parse_write_batch;
+
+// Parses arbitrary bytes as a RocksDB WriteBatch
+fuzz_target!(|data: &[u8]| {
+	let _ = parse_write_batch(data);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use db_ngn_memtable::wal_iterator::WALIterator;
+
+// Reads arbitrary bytes as a WAL file
+fuzz_target!(|data: &[u8]| {
+	for entry in WALIterator::from_reader(data) {
+		assert!(entry.value.is_some() != entry.deleted);
+	}
+});
@@ -0,0 +1,399 @@
+use std::env;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use db_ngn_memtable::clock::{Clock, SystemClock};
+use db_ngn_memtable::flush_sink::dump_jsonl;
+use db_ngn_memtable::mem_table::{IterOptions, MemTable};
+use db_ngn_memtable::resp::{self, RespHandler};
+use db_ngn_memtable::utils::lock_dir;
+use db_ngn_memtable::wal::{segment_catalog, VerifyReport, WAL};
+
+
+const USAGE: &str = "\
+usage: dbngn <dir> [command]
+       dbngn <dir> check
+       dbngn <dir> repair
+       dbngn <dir> recover
+       dbngn <dir> compact
+
+Opens the WAL directory and runs one command. If no command is given,
+commands are read from stdin, one per line. The directory is read without
+being locked or changed until a command writes to it, when it is locked
+and recovered, merging its WAL files.
+
+check reads every WAL file in the directory without recovering it, and
+prints the entries, timestamps and corruptions found in each.
+
+repair truncates each WAL file in the directory after its last valid
+record, dropping any corrupt or partly written records and all after them.
+
+recover recovers the directory, merging its WAL files into one, and
+prints the records recovered.
+
+compact recovers the directory like recover, and prints the WAL files
+and bytes before and after they were merged.
+
+commands:
+  put <key> [value]     sets the value of a key, which is empty if not
+                        given
+  get <key>             prints the value of a key
+  delete <key>          deletes a key
+  scan [start [end]]    prints the live keys in [start, end)
+  dump [start [end]]    prints the entries in [start, end), including
+                        tombstones, as JSON lines
+  stats                 prints the records and sizes of the MemTable
+  flush                 writes buffered WAL writes to the file
+  verify                checks the WAL files and the MemTable replayed from them
+  resp <addr>           serves the Redis protocol (RESP) on an address,
+                        such as 127.0.0.1:6379, until stopped";
+
+
+// A WAL directory opened by the CLI, which is only opened for writing once
+//	a command writes to it
+struct Session {
+	dir: PathBuf,
+	wal: Option<WAL>,
+	table: MemTable,
+}
+
+
+fn main() -> ExitCode {
+	let args: Vec<String> = env::args().skip(1).collect();
+	let dir = match args.first() {
+		Some(dir) if dir != "-h" && dir != "--help" => dir,
+		_ => {
+			eprintln!("{}", USAGE);
+			return ExitCode::FAILURE;
+		},
+	};
+
+	if args.len() == 2 && args[1] == "check" {
+		return check(Path::new(dir));
+	}
+	if args.len() == 2 && args[1] == "repair" {
+		return repair(Path::new(dir));
+	}
+	if args.len() == 2 && (args[1] == "recover" || args[1] == "compact") {
+		return recover(Path::new(dir), args[1] == "compact");
+	}
+
+	if let [_, command, addr] = args.as_slice() {
+		if command == "resp" {
+			return match WAL::from_dir(Path::new(dir)) {
+				Ok((wal, table)) => serve_resp(addr, wal, table),
+				Err(e) => {
+					eprintln!("dbngn: can't open {}: {}", dir, e);
+					ExitCode::FAILURE
+				},
+			};
+		}
+	}
+
+	// Reads are served without locking or changing the directory, so it can
+	//	be inspected while open in another process
+	let table = match WAL::from_dir_read_only(Path::new(dir)) {
+		Ok(table) => table,
+		Err(e) => {
+			eprintln!("dbngn: can't read {}: {}", dir, e);
+			return ExitCode::FAILURE;
+		},
+	};
+	let mut session = Session { dir: PathBuf::from(dir), wal: None, table };
+
+	let mut status = ExitCode::SUCCESS;
+	if args.len() > 1 {
+		let command: Vec<&str> = args[1..].iter().map(|a| a.as_str()).collect();
+		if !run_and_print(&mut session, &command) {
+			status = ExitCode::FAILURE;
+		}
+	} else {
+		let stdin = io::stdin();
+		for line in stdin.lock().lines() {
+			let line = match line {
+				Ok(line) => line,
+				Err(_) => break,
+			};
+			let command: Vec<&str> = line.split_whitespace().collect();
+			if !command.is_empty() {
+				run_and_print(&mut session, &command);
+			}
+		}
+	}
+
+	if let Some(Err(e)) = session.wal.map(WAL::close) {
+		eprintln!("dbngn: can't close the WAL: {}", e);
+		status = ExitCode::FAILURE;
+	}
+	status
+}
+
+
+// Recovers a directory, merging its WAL files into one, and prints the
+//	records recovered or, if compacting, the files and bytes merged
+fn recover(dir: &Path, compact: bool) -> ExitCode {
+	let before = match segment_sizes(dir) {
+		Ok(sizes) => sizes,
+		Err(e) => {
+			eprintln!("dbngn: can't read {}: {}", dir.display(), e);
+			return ExitCode::FAILURE;
+		},
+	};
+	let recovered = WAL::from_dir(dir).and_then(|(wal, table)| {
+		let last_sequence = wal.last_sequence();
+		wal.close()?;
+		Ok((table, last_sequence, segment_sizes(dir)?))
+	});
+	let (table, last_sequence, after) = match recovered {
+		Ok(recovered) => recovered,
+		Err(e) => {
+			eprintln!("dbngn: can't recover {}: {}", dir.display(), e);
+			return ExitCode::FAILURE;
+		},
+	};
+
+	if compact {
+		println!("WAL files: {} -> {}", before.len(), after.len());
+		println!("WAL bytes: {} -> {}", before.iter().sum::<u64>(), after.iter().sum::<u64>());
+	} else {
+		let stats = table.stats();
+		println!("live entries: {}", stats.live_entries);
+		println!("tombstones: {}", stats.tombstones);
+		println!("range tombstones: {}", table.range_tombstones().len());
+		println!("last sequence: {}", last_sequence);
+	}
+	ExitCode::SUCCESS
+}
+
+// Gets the size of each WAL file in a directory
+fn segment_sizes(dir: &Path) -> io::Result<Vec<u64>> {
+	segment_catalog(dir)?.iter()
+		.map(|segment| Ok(segment.path.metadata()?.len()))
+		.collect()
+}
+
+
+// Prints a report of each WAL file in the directory, failing if any are
+//	corrupt
+fn check(dir: &Path) -> ExitCode {
+	let reports = match WAL::verify_dir(dir) {
+		Ok(reports) => reports,
+		Err(e) => {
+			eprintln!("dbngn: can't check {}: {}", dir.display(), e);
+			return ExitCode::FAILURE;
+		},
+	};
+
+	let mut status = ExitCode::SUCCESS;
+	for report in reports {
+		let timestamps = match (report.first_timestamp, report.last_timestamp) {
+			(Some(first), Some(last)) => format!("{}..={}", first, last),
+			_ => "-".to_owned(),
+		};
+		println!("{}	{} entries	{}", report.segment.path.display(), report.entries, timestamps);
+		for corruption in report.corruptions.iter() {
+			println!("  corrupt at offset {}: {}", corruption.offset, corruption.reason);
+			status = ExitCode::FAILURE;
+		}
+	}
+	status
+}
+
+
+// Truncates each WAL file of a directory after its last valid record,
+//	holding the directory lock so no WAL is open while it is repaired
+fn repair(dir: &Path) -> ExitCode {
+	let repaired = lock_dir(dir).and_then(|_lock| {
+		segment_catalog(dir)?.into_iter()
+			.map(|segment| Ok((WAL::repair(&segment.path)?, segment)))
+			.collect::<io::Result<Vec<_>>>()
+	});
+	let reports = match repaired {
+		Ok(reports) => reports,
+		Err(e) => {
+			eprintln!("dbngn: can't repair {}: {}", dir.display(), e);
+			return ExitCode::FAILURE;
+		},
+	};
+
+	for (report, segment) in reports {
+		println!(
+			"{}	{} entries kept	{} bytes dropped",
+			segment.path.display(),
+			report.entries_kept,
+			report.bytes_dropped,
+		);
+		for corruption in report.corruptions.iter() {
+			println!("  corrupt at offset {}: {}", corruption.offset, corruption.reason);
+		}
+	}
+	ExitCode::SUCCESS
+}
+
+
+// Serves the Redis protocol on an address until accepting a connection
+//	fails
+fn serve_resp(addr: &str, wal: WAL, table: MemTable) -> ExitCode {
+	let listener = match TcpListener::bind(addr) {
+		Ok(listener) => listener,
+		Err(e) => {
+			eprintln!("dbngn: can't listen on {}: {}", addr, e);
+			return ExitCode::FAILURE;
+		},
+	};
+	eprintln!("dbngn: serving RESP on {}", addr);
+
+	if let Err(e) = resp::serve(listener, RespHandler::new(wal, table)) {
+		eprintln!("dbngn: {}", e);
+	}
+	ExitCode::FAILURE
+}
+
+
+// Runs a command, printing its output or error. Returns false on error
+fn run_and_print(session: &mut Session, command: &[&str]) -> bool {
+	match run(session, command) {
+		Ok(output) => {
+			let mut stdout = io::stdout().lock();
+			for line in output {
+				let _ = writeln!(stdout, "{}", line);
+			}
+			true
+		},
+		Err(e) => {
+			eprintln!("dbngn: {}", e);
+			false
+		},
+	}
+}
+
+
+// Opens the WAL of the session for writing, recovering the directory into
+//	the MemTable. The WAL holds the directory lock until it is closed, so
+//	another process can't write to the directory meanwhile
+fn writable(session: &mut Session) -> Result<&mut WAL, String> {
+	if session.wal.is_none() {
+		let (wal, table) = WAL::from_dir(&session.dir)
+			.map_err(|e| format!("can't open {} for writing: {}", session.dir.display(), e))?;
+		session.table = table;
+		session.wal = Some(wal);
+	}
+	Ok(session.wal.as_mut().unwrap())
+}
+
+// Runs a command against the session, returning the lines to print
+fn run(session: &mut Session, command: &[&str]) -> Result<Vec<String>, String> {
+	let now = SystemClock.now();
+	match command {
+		["put", key, value @ ..] if value.len() <= 1 => {
+			let value = value.first().map_or(&[][..], |v| v.as_bytes());
+			writable(session)?.set(key.as_bytes(), value, now).map_err(|e| e.to_string())?;
+			session.table.set(key.as_bytes(), value, now);
+			Ok(Vec::new())
+		},
+		["get", key] => match session.table.get(key.as_bytes()).and_then(|e| e.value.as_ref()) {
+			Some(value) => Ok(vec![String::from_utf8_lossy(value).into_owned()]),
+			None => Err(format!("{} not found", key)),
+		},
+		["delete", key] => {
+			writable(session)?.delete(key.as_bytes(), now).map_err(|e| e.to_string())?;
+			session.table.delete(key.as_bytes(), now);
+			Ok(Vec::new())
+		},
+		["scan", bounds @ ..] if bounds.len() <= 2 => {
+			let mut options = IterOptions::new();
+			if let Some(lower) = bounds.first() {
+				options = options.lower(lower.as_bytes());
+			}
+			if let Some(upper) = bounds.get(1) {
+				options = options.upper(upper.as_bytes());
+			}
+			Ok(session.table.iter_with_options(&options)
+				.map(|e| format!(
+					"{}\t{}",
+					String::from_utf8_lossy(&e.key),
+					String::from_utf8_lossy(e.value.as_deref().unwrap_or_default()),
+				))
+				.collect())
+		},
+		["dump", bounds @ ..] if bounds.len() <= 2 => {
+			let mut options = IterOptions::new().include_tombstones(true);
+			if let Some(lower) = bounds.first() {
+				options = options.lower(lower.as_bytes());
+			}
+			if let Some(upper) = bounds.get(1) {
+				options = options.upper(upper.as_bytes());
+			}
+			let mut dump = Vec::new();
+			dump_jsonl(&session.table, &options, &mut dump).map_err(|e| e.to_string())?;
+			Ok(String::from_utf8_lossy(&dump).lines().map(String::from).collect())
+		},
+		["stats"] => {
+			let stats = session.table.stats();
+			let mut output = vec![
+				format!("live entries: {}", stats.live_entries),
+				format!("tombstones: {}", stats.tombstones),
+				format!("range tombstones: {}", session.table.range_tombstones().len()),
+				format!("key bytes: {}", stats.key_bytes),
+				format!("value bytes: {}", stats.value_bytes),
+				format!("meta bytes: {}", stats.meta_bytes),
+				format!("size: {}", session.table.size()),
+				format!("average entry size: {}", stats.average_entry_size),
+			];
+			// The WAL is only open once the session has written to it
+			if let Some(wal) = session.wal.as_ref() {
+				output.extend([
+					format!("last sequence: {}", wal.last_sequence()),
+					format!("WAL bytes written: {}", wal.io_stats().log_bytes),
+					format!("write amplification: {:.2}", wal.io_stats().write_amplification()),
+					format!("WAL append p99: {:?}", wal.latency_report().append.p99),
+					format!("WAL flush p99: {:?}", wal.latency_report().flush.p99),
+				]);
+			}
+			Ok(output)
+		},
+		["flush"] => {
+			if let Some(wal) = session.wal.as_mut() {
+				wal.flush().map_err(|e| e.to_string())?;
+			}
+			Ok(Vec::new())
+		},
+		["verify"] => {
+			// The WAL files are checked and replayed without opening the
+			//	directory for writing, once any writes of the session are
+			//	flushed to them
+			if let Some(wal) = session.wal.as_mut() {
+				wal.flush().map_err(|e| e.to_string())?;
+			}
+			let segments = WAL::verify_dir(&session.dir).map_err(|e| e.to_string())?;
+			let corruptions: Vec<String> = segments.iter()
+				.flat_map(|report| report.corruptions.iter().map(move |corruption| format!(
+					"{} corrupt at offset {}: {}",
+					report.segment.path.display(),
+					corruption.offset,
+					corruption.reason,
+				)))
+				.collect();
+			if !corruptions.is_empty() {
+				return Err(corruptions.join("\n"));
+			}
+			let replayed = WAL::from_dir_read_only(&session.dir).map_err(|e| e.to_string())?;
+			let report = VerifyReport::compare(&replayed, &session.table);
+			if report.is_consistent() {
+				return Ok(vec![format!("ok: {} records", report.wal_entries)]);
+			}
+			let mut output = vec![format!(
+				"mismatch: {} records in the WAL, {} in the MemTable",
+				report.wal_entries,
+				report.mem_table_entries,
+			)];
+			output.extend(report.mismatched_keys.iter().map(|k| String::from_utf8_lossy(k).into_owned()));
+			Err(output.join("\n"))
+		},
+		_ => Err(format!("unknown command: {}\n\n{}", command.join(" "), USAGE)),
+	}
+}
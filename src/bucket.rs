@@ -0,0 +1,137 @@
+use crate::mem_table::{prefix_end, IterOptions, MemTable, MemTableEntry, MemTableIter, SetOutcome};
+
+
+/// A Bucket is a handle to the keys of a MemTable which start with a
+///   prefix, giving each tenant of a shared MemTable its own keyspace.
+///
+/// Keys are prefixed as they are written and the prefix is stripped from
+///   the keys read back, so a Bucket can't reach records outside of its
+///   prefix. Writes to a WAL must use the full key, given by `full_key`.
+pub struct Bucket<'a> {
+	table: &'a mut MemTable,
+	prefix: Vec<u8>,
+}
+
+
+/// An iterator over the live entries of a Bucket in key order, yielding
+///   each key without the prefix of the Bucket alongside its entry.
+pub struct BucketIter<'a> {
+	entries: MemTableIter<'a>,
+	prefix_len: usize,
+}
+
+
+impl<'a> Bucket<'a> {
+	pub fn new(table: &'a mut MemTable, prefix: &[u8]) -> Bucket<'a> {
+		Bucket { table, prefix: prefix.to_vec() }
+	}
+
+	pub fn prefix(&self) -> &[u8] {
+		&self.prefix
+	}
+
+	// Gets the key a key of the Bucket is stored under in the MemTable
+	pub fn full_key(&self, key: &[u8]) -> Vec<u8> {
+		let mut full_key = Vec::with_capacity(self.prefix.len() + key.len());
+		full_key.extend_from_slice(&self.prefix);
+		full_key.extend_from_slice(key);
+		full_key
+	}
+
+	pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> SetOutcome {
+		self.table.set(&self.full_key(key), value, timestamp)
+	}
+
+	pub fn get(&self, key: &[u8]) -> Option<&MemTableEntry> {
+		self.table.get(&self.full_key(key))
+	}
+
+	pub fn delete(&mut self, key: &[u8], timestamp: u128) -> SetOutcome {
+		self.table.delete(&self.full_key(key), timestamp)
+	}
+
+	// Deletes the keys of the Bucket in the range `[start, end)`
+	pub fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128) {
+		self.table.delete_range(&self.full_key(start), &self.full_key(end), timestamp);
+	}
+
+	// Iterates over the live entries of the Bucket
+	pub fn iter(&self) -> BucketIter<'_> {
+		self.range(b"", b"")
+	}
+
+	// Iterates over the live entries of the Bucket whose keys are in the
+	//	range `[lower, upper)`, where an empty upper bound is the end of the
+	//	Bucket
+	pub fn range(&self, lower: &[u8], upper: &[u8]) -> BucketIter<'_> {
+		let mut options = IterOptions::new().lower(&self.full_key(lower));
+		let upper = match upper.is_empty() {
+			true => prefix_end(&self.prefix),
+			false => Some(self.full_key(upper)),
+		};
+		if let Some(upper) = upper {
+			options = options.upper(&upper);
+		}
+
+		BucketIter {
+			entries: self.table.iter_with_options(&options),
+			prefix_len: self.prefix.len(),
+		}
+	}
+
+	// Gets the number of live entries in the Bucket
+	pub fn len(&self) -> usize {
+		self.iter().count()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.iter().next().is_none()
+	}
+}
+
+
+impl<'a> Iterator for BucketIter<'a> {
+	type Item = (&'a [u8], &'a MemTableEntry);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let entry = self.entries.next()?;
+		Some((&entry.key[self.prefix_len..], entry))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::bucket::Bucket;
+	use crate::mem_table::MemTable;
+
+	#[test]
+	fn test_bucket() {
+		let mut table = MemTable::new();
+		table.set(b"users", b"Outside", 0);
+		table.set(b"users0", b"Outside", 0);
+
+		let mut users = Bucket::new(&mut table, b"users/");
+		users.set(b"Monday", b"Rejoice", 0);
+		users.set(b"Tuesday", b"Celebrate", 10);
+		users.set(b"Wednesday", b"Work", 15);
+		users.delete(b"Tuesday", 21);
+		assert_eq!(users.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Rejoice");
+		assert!(users.get(b"Tuesday").unwrap().deleted);
+		assert!(users.get(b"s0").is_none());
+
+		let keys: Vec<&[u8]> = users.iter().map(|(k, _)| k).collect();
+		assert_eq!(keys, vec![b"Monday" as &[u8], b"Wednesday"]);
+		let keys: Vec<&[u8]> = users.range(b"N", b"").map(|(k, _)| k).collect();
+		assert_eq!(keys, vec![b"Wednesday" as &[u8]]);
+		assert_eq!(users.len(), 2);
+
+		users.delete_range(b"", b"\xff", 30);
+		assert!(users.is_empty());
+		assert_eq!(users.full_key(b"Monday"), b"users/Monday");
+
+		// Keys outside the prefix are untouched
+		assert!(!table.get(b"users").unwrap().deleted);
+		assert!(!table.get(b"users0").unwrap().deleted);
+	}
+}
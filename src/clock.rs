@@ -0,0 +1,248 @@
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+
+/// A Clock assigns the timestamps given to writes and WAL files.
+///
+/// Timestamps are u128 values which order the operations performed on the
+///   MemTable. Injecting a Clock rather than reading the system time keeps
+///   timestamps consistent across the engine and tests deterministic.
+///
+/// WAL files are named and expired by `physical_micros`, the wall-clock
+///   time the timestamps of the clock are based on, which is the same as
+///   `now` for clocks whose timestamps are in microseconds.
+pub trait Clock {
+	fn now(&self) -> u128;
+
+	// Gets the current time in microseconds since the UNIX epoch, without
+	//	advancing the clock
+	fn physical_micros(&self) -> u128 {
+		self.now()
+	}
+}
+
+
+/// The SystemClock reads the wall-clock time in microseconds since the
+///   UNIX epoch.
+///
+/// Timestamps may repeat or go backwards if the system time is changed.
+pub struct SystemClock;
+
+
+/// The MonotonicClock reads the system time but never returns a timestamp
+///   less than or equal to one it has returned before.
+pub struct MonotonicClock {
+	last: Mutex<u128>,
+}
+
+
+/// A Hybrid Logical Clock (HLC) combines the physical time with a logical
+///   counter so timestamps are strictly increasing, stay close to the
+///   wall-clock time, and respect the ordering of timestamps received from
+///   other nodes.
+///
+/// The physical time in microseconds is held in the upper 64 bits of a
+///   timestamp and the logical counter in the lower 64 bits.
+pub struct HybridLogicalClock {
+	last: Mutex<(u64, u64)>,
+}
+
+
+/// The MockClock returns a timestamp which is only changed explicitly, for
+///   use in tests.
+pub struct MockClock {
+	now: Mutex<u128>,
+}
+
+
+impl Clock for SystemClock {
+	fn now(&self) -> u128 {
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_micros()
+	}
+}
+
+
+impl MonotonicClock {
+	pub fn new() -> MonotonicClock {
+		MonotonicClock { last: Mutex::new(0) }
+	}
+}
+
+impl Default for MonotonicClock {
+	fn default() -> MonotonicClock {
+		MonotonicClock::new()
+	}
+}
+
+impl Clock for MonotonicClock {
+	fn now(&self) -> u128 {
+		let mut last = self.last.lock().unwrap();
+		*last = SystemClock.now().max(*last + 1);
+		*last
+	}
+}
+
+
+impl HybridLogicalClock {
+	pub fn new() -> HybridLogicalClock {
+		HybridLogicalClock { last: Mutex::new((0, 0)) }
+	}
+
+	// Merges a timestamp received from another node into the clock,
+	//	returning a timestamp which is greater than both it and any timestamp
+	//	previously returned
+	pub fn update(&self, remote: u128) -> u128 {
+		let (remote_physical, remote_logical) = HybridLogicalClock::split(remote);
+		let physical = HybridLogicalClock::physical_now();
+
+		let mut last = self.last.lock().unwrap();
+		let max_physical = physical.max(last.0).max(remote_physical);
+		let logical = if max_physical == last.0 && max_physical == remote_physical {
+			last.1.max(remote_logical) + 1
+		} else if max_physical == last.0 {
+			last.1 + 1
+		} else if max_physical == remote_physical {
+			remote_logical + 1
+		} else {
+			0
+		};
+		*last = (max_physical, logical);
+
+		HybridLogicalClock::join(max_physical, logical)
+	}
+
+	// Gets the physical time in microseconds held in a HLC timestamp
+	pub fn physical(timestamp: u128) -> u64 {
+		HybridLogicalClock::split(timestamp).0
+	}
+
+	// Gets the logical counter held in a HLC timestamp
+	pub fn logical(timestamp: u128) -> u64 {
+		HybridLogicalClock::split(timestamp).1
+	}
+
+	fn physical_now() -> u64 {
+		SystemClock.now() as u64
+	}
+
+	fn split(timestamp: u128) -> (u64, u64) {
+		((timestamp >> 64) as u64, timestamp as u64)
+	}
+
+	fn join(physical: u64, logical: u64) -> u128 {
+		((physical as u128) << 64) | logical as u128
+	}
+}
+
+impl Default for HybridLogicalClock {
+	fn default() -> HybridLogicalClock {
+		HybridLogicalClock::new()
+	}
+}
+
+impl Clock for HybridLogicalClock {
+	fn now(&self) -> u128 {
+		let physical = HybridLogicalClock::physical_now();
+
+		let mut last = self.last.lock().unwrap();
+		if physical > last.0 {
+			*last = (physical, 0);
+		} else {
+			last.1 += 1;
+		}
+
+		HybridLogicalClock::join(last.0, last.1)
+	}
+
+	fn physical_micros(&self) -> u128 {
+		let last = self.last.lock().unwrap();
+		HybridLogicalClock::physical_now().max(last.0) as u128
+	}
+}
+
+
+impl MockClock {
+	// Creates a MockClock returning the given timestamp
+	pub fn new(now: u128) -> MockClock {
+		MockClock { now: Mutex::new(now) }
+	}
+
+	// Sets the timestamp returned by the clock
+	pub fn set(&self, now: u128) {
+		*self.now.lock().unwrap() = now;
+	}
+
+	// Moves the timestamp returned by the clock forwards
+	pub fn advance(&self, by: u128) {
+		*self.now.lock().unwrap() += by;
+	}
+}
+
+impl Clock for MockClock {
+	fn now(&self) -> u128 {
+		*self.now.lock().unwrap()
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::clock::{Clock, HybridLogicalClock, MockClock, MonotonicClock};
+
+	#[test]
+	fn test_monotonic_clock_increases() {
+		let clock = MonotonicClock::new();
+
+		let mut last = clock.now();
+		for _ in 0..1000 {
+			let now = clock.now();
+			assert!(now > last);
+			last = now;
+		}
+	}
+
+	#[test]
+	fn test_hlc_increases() {
+		let clock = HybridLogicalClock::new();
+
+		let mut last = clock.now();
+		for _ in 0..1000 {
+			let now = clock.now();
+			assert!(now > last);
+			last = now;
+		}
+	}
+
+	#[test]
+	fn test_hlc_update_from_future() {
+		let clock = HybridLogicalClock::new();
+		let local = clock.now();
+
+		// A remote timestamp an hour ahead of the local clock
+		let remote = ((HybridLogicalClock::physical(local) as u128 + 3_600_000_000) << 64) | 5;
+		let merged = clock.update(remote);
+		assert_eq!(HybridLogicalClock::physical(merged), HybridLogicalClock::physical(remote));
+		assert_eq!(HybridLogicalClock::logical(merged), 6);
+
+		assert!(clock.now() > merged);
+		// The physical time follows the remote timestamp, not the logical
+		//	counter in the lower bits
+		assert_eq!(clock.physical_micros(), HybridLogicalClock::physical(remote) as u128);
+	}
+
+	#[test]
+	fn test_mock_clock() {
+		let clock = MockClock::new(10);
+		assert_eq!(clock.now(), 10);
+
+		clock.advance(5);
+		assert_eq!(clock.now(), 15);
+
+		clock.set(3);
+		assert_eq!(clock.now(), 3);
+	}
+}
@@ -0,0 +1,154 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::wal::WalSegment;
+
+
+/// An EventListener is told when a WAL directory is recovered, when its WAL
+///   files are merged and retired, and when a FlushTimer flushes a MemTable,
+///   so that caches can be invalidated and metrics recorded.
+///
+/// Listeners are registered for recovery in `RecoveryOptions::listeners`
+///   and for flushes with `FlushTimer::start_with_listeners`. Callbacks are
+///   made on the thread doing the work, so they should return quickly. Each
+///   callback does nothing by default.
+pub trait EventListener: Send + Sync {
+	// Called once the WAL files of a directory have been replayed and merged
+	//	into a new WAL file, which later writes are appended to
+	fn on_wal_rotate(&self, _merged: &[WalSegment], _wal: &Path) {}
+
+	// Called once a merged WAL file is deleted, after it is passed to any
+	//	ArchiveSink. Files kept in the archive are retired once they expire
+	fn on_segment_retired(&self, _segment: &WalSegment) {}
+
+	// Called once a WAL directory has been recovered and its merged files
+	//	retired
+	fn on_recovery_complete(&self, _info: &RecoveryInfo) {}
+
+	// Called before the records of a MemTable are passed to a FlushSink
+	fn on_flush_begin(&self, _info: &FlushInfo) {}
+
+	// Called once the FlushSink has returned, with its error if it failed
+	fn on_flush_complete(&self, _info: &FlushInfo, _error: Option<&io::Error>) {}
+}
+
+
+/// RecoveryInfo describes a recovered WAL directory, as given to
+///   `EventListener::on_recovery_complete`.
+///
+/// `segments` is the number of WAL files merged, and `entries` the number
+///   of records, including tombstones, in the recovered MemTable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveryInfo {
+	pub wal: PathBuf,
+	pub segments: usize,
+	pub entries: usize,
+	pub last_sequence: u64,
+}
+
+
+/// FlushInfo describes a MemTable being flushed by a FlushTimer, as given to
+///   the flush callbacks of an EventListener.
+///
+/// `entries` is the number of records, including tombstones, and `bytes`
+///   the size of the MemTable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlushInfo {
+	pub entries: usize,
+	pub bytes: usize,
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{create_dir, remove_dir_all};
+	use std::io;
+	use std::path::{Path, PathBuf};
+	use std::sync::{Arc, Mutex};
+	use std::thread;
+	use std::time::Duration;
+	use rand::Rng;
+
+	use crate::clock::SystemClock;
+	use crate::event_listener::{EventListener, FlushInfo, RecoveryInfo};
+	use crate::flush_sink::{FlushSink, FlushTimer};
+	use crate::mem_table::MemTable;
+	use crate::wal::{RecoveryOptions, RetentionPolicy, WalSegment, WAL};
+
+	// A listener recording the events it is told of
+	#[derive(Default)]
+	struct RecordingListener {
+		events: Mutex<Vec<String>>,
+	}
+
+	impl EventListener for RecordingListener {
+		fn on_wal_rotate(&self, merged: &[WalSegment], _wal: &Path) {
+			self.events.lock().unwrap().push(format!("rotate {}", merged.len()));
+		}
+
+		fn on_segment_retired(&self, segment: &WalSegment) {
+			self.events.lock().unwrap().push(format!("retire {}", segment.timestamp));
+		}
+
+		fn on_recovery_complete(&self, info: &RecoveryInfo) {
+			self.events.lock().unwrap().push(format!("recover {} {}", info.segments, info.entries));
+		}
+
+		fn on_flush_begin(&self, info: &FlushInfo) {
+			self.events.lock().unwrap().push(format!("flush {}", info.entries));
+		}
+
+		fn on_flush_complete(&self, info: &FlushInfo, error: Option<&io::Error>) {
+			self.events.lock().unwrap().push(format!("flushed {} {}", info.entries, error.is_none()));
+		}
+	}
+
+	// A sink discarding the tables flushed to it
+	struct NullSink;
+
+	impl FlushSink for NullSink {
+		fn flush(&mut self, _table: &MemTable) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn test_recovery_events() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		for (timestamp, key) in [(1, b"Monday"), (2, b"Friday")] {
+			let mut wal = WAL::from_path(&dir.join(format!("{}.wal", timestamp))).unwrap();
+			wal.set(key, b"Rejoice", 0).unwrap();
+			wal.close().unwrap();
+		}
+
+		let listener = Arc::new(RecordingListener::default());
+		let recovery = RecoveryOptions { listeners: vec![listener.clone()], ..RecoveryOptions::default() };
+		let (wal, _) = WAL::from_dir_with_recovery(&dir, &recovery, &RetentionPolicy::default(), None, &SystemClock).unwrap();
+		wal.close().unwrap();
+		assert_eq!(*listener.events.lock().unwrap(), vec!["rotate 2", "retire 1", "retire 2", "recover 2 2"]);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_flush_events() {
+		let table = Arc::new(Mutex::new(MemTable::new()));
+		table.lock().unwrap().set(b"Monday", b"Rejoice", 0);
+
+		let listener = Arc::new(RecordingListener::default());
+		let timer = FlushTimer::start_with_listeners(
+			Arc::clone(&table),
+			Box::new(NullSink),
+			Duration::from_millis(10),
+			vec![listener.clone()],
+		);
+		while timer.flushes() == 0 {
+			thread::sleep(Duration::from_millis(5));
+		}
+		timer.stop().unwrap();
+		assert_eq!(*listener.events.lock().unwrap(), vec!["flush 1", "flushed 1 true"]);
+	}
+}
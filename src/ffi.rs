@@ -0,0 +1,239 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use crate::clock::{Clock, SystemClock};
+use crate::mem_table::MemTable;
+use crate::wal::WAL;
+
+
+// C bindings to a MemTable backed by a WAL, built with the `ffi` feature.
+//
+// A table is opened from a directory with `memtable_open` and must be
+//   released with `memtable_close`. Keys and values are passed as pointers
+//   to bytes with their lengths, and are copied before the call returns.
+//   Values returned by `memtable_get` are owned by the caller and must be
+//   released with `memtable_free_buffer`.
+//
+// Every function other than `memtable_open` and `memtable_free_buffer`
+//   returns one of the status codes below. Writes are timestamped with the
+//   system time.
+pub const MEMTABLE_OK: i32 = 0;
+pub const MEMTABLE_NOT_FOUND: i32 = 1;
+pub const MEMTABLE_INVALID_ARGUMENT: i32 = -1;
+pub const MEMTABLE_IO_ERROR: i32 = -2;
+
+
+/// A MemTable and the WAL its writes are recorded to, opened through the
+///   C bindings.
+pub struct MemTableHandle {
+	wal: WAL,
+	table: MemTable,
+}
+
+
+/// Opens the WAL directory at a NUL terminated path, recovering its
+///   MemTable. Returns null if the directory can't be opened.
+///
+/// # Safety
+///
+/// `dir` must be null or point to a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn memtable_open(dir: *const c_char) -> *mut MemTableHandle {
+	if dir.is_null() {
+		return ptr::null_mut();
+	}
+	let dir = match CStr::from_ptr(dir).to_str() {
+		Ok(dir) => dir,
+		Err(_) => return ptr::null_mut(),
+	};
+
+	match WAL::from_dir(Path::new(dir)) {
+		Ok((wal, table)) => Box::into_raw(Box::new(MemTableHandle { wal, table })),
+		Err(_) => ptr::null_mut(),
+	}
+}
+
+/// Flushes the WAL and releases a table opened with `memtable_open`.
+///
+/// # Safety
+///
+/// `handle` must have been returned by `memtable_open` and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn memtable_close(handle: *mut MemTableHandle) -> i32 {
+	if handle.is_null() {
+		return MEMTABLE_INVALID_ARGUMENT;
+	}
+	let handle = Box::from_raw(handle);
+	status(handle.wal.close())
+}
+
+/// Sets the value of a key.
+///
+/// # Safety
+///
+/// `handle` must be open, and `key` and `value` must point to `key_len`
+///   and `value_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn memtable_set(
+	handle: *mut MemTableHandle,
+	key: *const u8,
+	key_len: usize,
+	value: *const u8,
+	value_len: usize,
+) -> i32 {
+	let (handle, key, value) = match (handle.as_mut(), bytes(key, key_len), bytes(value, value_len)) {
+		(Some(handle), Some(key), Some(value)) => (handle, key, value),
+		_ => return MEMTABLE_INVALID_ARGUMENT,
+	};
+
+	// The recovered MemTable has no capacity, so the write is always applied
+	let timestamp = SystemClock.now();
+	if let Err(e) = handle.wal.set(key, value, timestamp) {
+		return status(Err(e));
+	}
+	handle.table.set(key, value, timestamp);
+	MEMTABLE_OK
+}
+
+/// Gets the value of a key, storing a buffer holding it and its length in
+///   `value` and `value_len`. Returns `MEMTABLE_NOT_FOUND` if the key has no
+///   value.
+///
+/// # Safety
+///
+/// `handle` must be open, `key` must point to `key_len` readable bytes, and
+///   `value` and `value_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn memtable_get(
+	handle: *const MemTableHandle,
+	key: *const u8,
+	key_len: usize,
+	value: *mut *mut u8,
+	value_len: *mut usize,
+) -> i32 {
+	let (handle, key) = match (handle.as_ref(), bytes(key, key_len)) {
+		(Some(handle), Some(key)) if !value.is_null() && !value_len.is_null() => (handle, key),
+		_ => return MEMTABLE_INVALID_ARGUMENT,
+	};
+
+	match handle.table.get(key).and_then(|e| e.value.as_ref()) {
+		Some(found) => {
+			let buffer = found.clone().into_boxed_slice();
+			*value_len = buffer.len();
+			*value = Box::into_raw(buffer) as *mut u8;
+			MEMTABLE_OK
+		},
+		None => MEMTABLE_NOT_FOUND,
+	}
+}
+
+/// Deletes a key.
+///
+/// # Safety
+///
+/// `handle` must be open and `key` must point to `key_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn memtable_delete(
+	handle: *mut MemTableHandle,
+	key: *const u8,
+	key_len: usize,
+) -> i32 {
+	let (handle, key) = match (handle.as_mut(), bytes(key, key_len)) {
+		(Some(handle), Some(key)) => (handle, key),
+		_ => return MEMTABLE_INVALID_ARGUMENT,
+	};
+
+	let timestamp = SystemClock.now();
+	if let Err(e) = handle.wal.delete(key, timestamp) {
+		return status(Err(e));
+	}
+	handle.table.delete(key, timestamp);
+	MEMTABLE_OK
+}
+
+/// Writes the buffered writes of the WAL to its file.
+///
+/// # Safety
+///
+/// `handle` must be open.
+#[no_mangle]
+pub unsafe extern "C" fn memtable_flush(handle: *mut MemTableHandle) -> i32 {
+	match handle.as_mut() {
+		Some(handle) => status(handle.wal.flush()),
+		None => MEMTABLE_INVALID_ARGUMENT,
+	}
+}
+
+/// Releases a buffer returned by `memtable_get`.
+///
+/// # Safety
+///
+/// `buffer` and `len` must have been returned by `memtable_get` and not yet
+///   released.
+#[no_mangle]
+pub unsafe extern "C" fn memtable_free_buffer(buffer: *mut u8, len: usize) {
+	if !buffer.is_null() {
+		drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer, len)));
+	}
+}
+
+
+// Borrows the bytes passed to a binding, which may be null if empty
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+	match (data.is_null(), len) {
+		(true, 0) => Some(&[]),
+		(true, _) => None,
+		(false, _) => Some(slice::from_raw_parts(data, len)),
+	}
+}
+
+fn status(result: std::io::Result<()>) -> i32 {
+	match result {
+		Ok(()) => MEMTABLE_OK,
+		Err(_) => MEMTABLE_IO_ERROR,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::ffi::CString;
+	use std::fs::{create_dir, remove_dir_all};
+	use std::path::PathBuf;
+	use std::ptr;
+	use rand::Rng;
+
+	use crate::ffi::*;
+
+	#[test]
+	fn test_ffi_round_trip() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+		let path = CString::new(dir.to_str().unwrap()).unwrap();
+
+		unsafe {
+			let handle = memtable_open(path.as_ptr());
+			assert!(!handle.is_null());
+			assert_eq!(memtable_set(handle, b"Monday".as_ptr(), 6, b"Rejoice".as_ptr(), 7), MEMTABLE_OK);
+			assert_eq!(memtable_delete(handle, b"Tuesday".as_ptr(), 7), MEMTABLE_OK);
+			assert_eq!(memtable_close(handle), MEMTABLE_OK);
+
+			let handle = memtable_open(path.as_ptr());
+			let mut value = ptr::null_mut();
+			let mut value_len = 0;
+			assert_eq!(memtable_get(handle, b"Monday".as_ptr(), 6, &mut value, &mut value_len), MEMTABLE_OK);
+			assert_eq!(std::slice::from_raw_parts(value, value_len), b"Rejoice");
+			memtable_free_buffer(value, value_len);
+
+			assert_eq!(memtable_get(handle, b"Tuesday".as_ptr(), 7, &mut value, &mut value_len), MEMTABLE_NOT_FOUND);
+			assert_eq!(memtable_set(handle, ptr::null(), 3, ptr::null(), 0), MEMTABLE_INVALID_ARGUMENT);
+			assert_eq!(memtable_close(handle), MEMTABLE_OK);
+		}
+
+		remove_dir_all(&dir).unwrap();
+	}
+}
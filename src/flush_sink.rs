@@ -0,0 +1,532 @@
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::event_listener::{EventListener, FlushInfo};
+use crate::mem_table::{EntryKind, IterOptions, MemTable, MemTableEntry};
+
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+
+/// A FlushSink receives the contents of a MemTable when it is flushed.
+///
+/// Sinks allow a full MemTable to be written in formats other than the
+///   engine's own, so that other tools can consume it directly.
+pub trait FlushSink {
+	fn flush(&mut self, table: &MemTable) -> io::Result<()>;
+}
+
+
+/// A CompactionFilter decides what happens to each live entry of a MemTable
+///   as it is flushed through a FilteredSink.
+///
+/// Filters let applications drop or rewrite records while they are written
+///   out, such as expiring values by their contents, erasing the records of
+///   deleted users, or migrating the format of values.
+pub trait CompactionFilter {
+	fn filter(&mut self, entry: &MemTableEntry) -> FilterDecision;
+}
+
+
+/// The decision of a CompactionFilter for an entry.
+///
+/// `Remove` replaces the entry with a tombstone at its timestamp, so older
+///   records of its key held outside of the MemTable stay deleted.
+///   `ChangeValue` replaces the value of the entry, keeping its timestamp
+///   and metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilterDecision {
+	Keep,
+	Remove,
+	ChangeValue(Vec<u8>),
+}
+
+
+/// A FilteredSink applies a CompactionFilter to each flushed MemTable and
+///   passes the filtered MemTable on to another FlushSink.
+///
+/// Tombstones and range tombstones are passed on unfiltered. The flushed
+///   MemTable itself is left unchanged.
+pub struct FilteredSink<F: CompactionFilter, S: FlushSink> {
+	filter: F,
+	sink: S,
+}
+
+
+/// A SnapshotSink writes each flushed MemTable to a snapshot file, in the
+///   format of `MemTable::export`.
+pub struct SnapshotSink {
+	path: PathBuf,
+}
+
+
+/// A CsvSink writes the records of each flushed MemTable as CSV rows with
+///   the columns `key,value,timestamp,deleted`, in key order.
+///
+/// Keys and values are written as UTF-8, replacing invalid sequences, and
+///   quoted when they hold a comma, quote or line break. Tombstones have an
+///   empty value. Range tombstones are not written.
+pub struct CsvSink<W: Write> {
+	dest: W,
+	// Whether the header row has been written
+	header_written: bool,
+}
+
+
+/// A JsonLinesSink writes the entries of each flushed MemTable as JSON
+///   lines, in the format of `dump_jsonl`, including tombstones.
+pub struct JsonLinesSink<W: Write> {
+	dest: W,
+}
+
+
+/// A FlushTimer flushes a shared MemTable to a FlushSink every
+///   `flush_interval` if it holds any records, regardless of its size, so
+///   tables receiving few writes are still persisted within a bounded time.
+///
+/// The records flushed are moved out of the MemTable, which keeps its
+///   capacity and policies. If the sink fails they are merged back so the
+///   next flush retries them, and the error is kept for `stop`. The WAL
+///   covering the flushed records is left to the caller to retire.
+pub struct FlushTimer {
+	stop: Option<mpsc::Sender<()>>,
+	thread: Option<JoinHandle<()>>,
+	state: Arc<FlushState>,
+}
+
+
+// The progress of a FlushTimer, shared with its thread
+struct FlushState {
+	flushes: AtomicU64,
+	error: Mutex<Option<io::Error>>,
+}
+
+
+impl SnapshotSink {
+	pub fn new(path: PathBuf) -> SnapshotSink {
+		SnapshotSink { path }
+	}
+}
+
+impl FlushSink for SnapshotSink {
+	fn flush(&mut self, table: &MemTable) -> io::Result<()> {
+		table.export(&self.path)
+	}
+}
+
+
+impl<F: CompactionFilter, S: FlushSink> FilteredSink<F, S> {
+	pub fn new(filter: F, sink: S) -> FilteredSink<F, S> {
+		FilteredSink { filter, sink }
+	}
+
+	// Unwraps the FilteredSink, returning the sink it passes tables on to
+	pub fn into_inner(self) -> S {
+		self.sink
+	}
+}
+
+impl<F: CompactionFilter, S: FlushSink> FlushSink for FilteredSink<F, S> {
+	fn flush(&mut self, table: &MemTable) -> io::Result<()> {
+		// Range tombstones are applied first, so they mask only the records
+		//	they already masked
+		let mut filtered = MemTable::new();
+		for tombstone in table.range_tombstones() {
+			filtered.delete_range(&tombstone.start, &tombstone.end, tombstone.timestamp);
+		}
+
+		for entry in table.iter_with_tombstones() {
+			if entry.deleted {
+				filtered.delete(&entry.key, entry.timestamp);
+				continue;
+			}
+			let value = entry.value.as_deref().unwrap_or_default();
+			let meta = entry.meta.as_deref();
+			match self.filter.filter(entry) {
+				FilterDecision::Keep => filtered.set_with_meta(&entry.key, value, meta, entry.timestamp),
+				FilterDecision::Remove => filtered.delete(&entry.key, entry.timestamp),
+				FilterDecision::ChangeValue(value) => filtered.set_with_meta(&entry.key, &value, meta, entry.timestamp),
+			};
+		}
+
+		self.sink.flush(&filtered)
+	}
+}
+
+
+impl<W: Write> CsvSink<W> {
+	pub fn new(dest: W) -> CsvSink<W> {
+		CsvSink { dest, header_written: false }
+	}
+
+	// Unwraps the CsvSink, returning the destination
+	pub fn into_inner(self) -> W {
+		self.dest
+	}
+}
+
+impl<W: Write> FlushSink for CsvSink<W> {
+	// The header is only written before the first table, so several tables
+	//	can be flushed to the same destination
+	fn flush(&mut self, table: &MemTable) -> io::Result<()> {
+		if !self.header_written {
+			writeln!(self.dest, "key,value,timestamp,deleted")?;
+			self.header_written = true;
+		}
+
+		for entry in table.iter_with_tombstones() {
+			writeln!(
+				self.dest,
+				"{},{},{},{}",
+				csv_field(&entry.key),
+				csv_field(entry.value.as_deref().unwrap_or_default()),
+				entry.timestamp,
+				entry.kind() == EntryKind::Tombstone,
+			)?;
+		}
+		self.dest.flush()
+	}
+}
+
+
+impl<W: Write> JsonLinesSink<W> {
+	pub fn new(dest: W) -> JsonLinesSink<W> {
+		JsonLinesSink { dest }
+	}
+
+	// Unwraps the JsonLinesSink, returning the destination
+	pub fn into_inner(self) -> W {
+		self.dest
+	}
+}
+
+impl<W: Write> FlushSink for JsonLinesSink<W> {
+	fn flush(&mut self, table: &MemTable) -> io::Result<()> {
+		dump_jsonl(table, &IterOptions::new().include_tombstones(true), &mut self.dest)?;
+		self.dest.flush()
+	}
+}
+
+
+// Writes the entries of a MemTable visited with the options as JSON lines,
+//	returning the number written, so the state of two MemTables can be
+//	compared with standard tools.
+//
+// Each line is an object with the fields `key`, `value`, `timestamp` and
+//	`deleted`, in key order. Keys and values which are valid UTF-8 are
+//	written as strings, and others are written base64 encoded in the fields
+//	`key_base64` and `value_base64`. Tombstones have a null value.
+//	Timestamps are written as numbers, which may lose precision in readers
+//	which parse them as doubles. Range tombstones are not written
+pub fn dump_jsonl<W: Write>(table: &MemTable, options: &IterOptions, dest: &mut W) -> io::Result<u64> {
+	let mut count = 0;
+	for entry in table.iter_with_options(options) {
+		let value = match entry.value.as_deref() {
+			Some(value) => json_bytes_field("value", value),
+			None => String::from("\"value\":null"),
+		};
+		writeln!(
+			dest,
+			"{{{},{},\"timestamp\":{},\"deleted\":{}}}",
+			json_bytes_field("key", &entry.key),
+			value,
+			entry.timestamp,
+			entry.kind() == EntryKind::Tombstone,
+		)?;
+		count += 1;
+	}
+	Ok(count)
+}
+
+
+impl FlushTimer {
+	// Starts a thread flushing the MemTable to the sink every interval,
+	//	which stops once the FlushTimer is stopped or dropped
+	pub fn start(
+		table: Arc<Mutex<MemTable>>,
+		sink: Box<dyn FlushSink + Send>,
+		flush_interval: Duration,
+	) -> FlushTimer {
+		FlushTimer::start_with_listeners(table, sink, flush_interval, Vec::new())
+	}
+
+	// Starts a FlushTimer like `start`, telling the listeners as each flush
+	//	begins and completes
+	pub fn start_with_listeners(
+		table: Arc<Mutex<MemTable>>,
+		mut sink: Box<dyn FlushSink + Send>,
+		flush_interval: Duration,
+		listeners: Vec<Arc<dyn EventListener>>,
+	) -> FlushTimer {
+		let state = Arc::new(FlushState { flushes: AtomicU64::new(0), error: Mutex::new(None) });
+		let thread_state = Arc::clone(&state);
+		let (stop, stopped) = mpsc::channel::<()>();
+		let thread = thread::spawn(move || {
+			while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(flush_interval) {
+				let flushed = {
+					let mut table = table.lock().unwrap();
+					if table.is_empty() && table.range_tombstones().is_empty() {
+						continue;
+					}
+					table.split_off(b"")
+				};
+				let info = FlushInfo { entries: flushed.len(), bytes: flushed.size() };
+				for listener in listeners.iter() {
+					listener.on_flush_begin(&info);
+				}
+				let result = sink.flush(&flushed);
+				for listener in listeners.iter() {
+					listener.on_flush_complete(&info, result.as_ref().err());
+				}
+				match result {
+					Ok(()) => { thread_state.flushes.fetch_add(1, Ordering::AcqRel); },
+					Err(e) => {
+						thread_state.error.lock().unwrap().get_or_insert(e);
+						table.lock().unwrap().merge_from(flushed);
+					},
+				}
+			}
+		});
+
+		FlushTimer { stop: Some(stop), thread: Some(thread), state }
+	}
+
+	// Gets the number of MemTables flushed so far
+	pub fn flushes(&self) -> u64 {
+		self.state.flushes.load(Ordering::Acquire)
+	}
+
+	// Stops the thread, returning the first error it had when flushing
+	pub fn stop(mut self) -> io::Result<()> {
+		self.join();
+		match self.state.error.lock().unwrap().take() {
+			Some(e) => Err(e),
+			None => Ok(()),
+		}
+	}
+
+	fn join(&mut self) {
+		drop(self.stop.take());
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+impl Drop for FlushTimer {
+	fn drop(&mut self) {
+		self.join();
+	}
+}
+
+
+// Formats bytes as a CSV field, quoting it if needed
+fn csv_field(bytes: &[u8]) -> String {
+	let field = String::from_utf8_lossy(bytes);
+	if field.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.into_owned()
+	}
+}
+
+// Formats bytes as a JSON field, named with a `_base64` suffix if the
+//	bytes aren't valid UTF-8
+fn json_bytes_field(name: &str, bytes: &[u8]) -> String {
+	match std::str::from_utf8(bytes) {
+		Ok(text) => format!("\"{}\":{}", name, json_string(text)),
+		Err(_) => format!("\"{}_base64\":\"{}\"", name, base64(bytes)),
+	}
+}
+
+fn json_string(text: &str) -> String {
+	let mut quoted = String::with_capacity(text.len() + 2);
+	quoted.push('"');
+	for c in text.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			'\r' => quoted.push_str("\\r"),
+			'\t' => quoted.push_str("\\t"),
+			c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+			c => quoted.push(c),
+		}
+	}
+	quoted.push('"');
+	quoted
+}
+
+// Encodes bytes as padded base64
+fn base64(bytes: &[u8]) -> String {
+	let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+	for chunk in bytes.chunks(3) {
+		let group = chunk.iter().enumerate().fold(0u32, |group, (i, b)| group | (*b as u32) << (16 - 8 * i));
+		for i in 0..4 {
+			match i <= chunk.len() {
+				true => encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i)) as usize & 0x3f] as char),
+				false => encoded.push('='),
+			}
+		}
+	}
+	encoded
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::sync::{Arc, Mutex};
+	use std::thread;
+	use std::time::Duration;
+
+	use crate::flush_sink::{base64, dump_jsonl, CompactionFilter, CsvSink, FilterDecision, FilteredSink, FlushSink, FlushTimer, JsonLinesSink};
+	use crate::mem_table::{IterOptions, MemTable, MemTableEntry};
+
+	// A sink keeping the keys of each table flushed, which fails while
+	//	`fail` is set
+	struct KeySink {
+		flushed: Arc<Mutex<Vec<Vec<Vec<u8>>>>>,
+		fail: Arc<Mutex<bool>>,
+	}
+
+	impl FlushSink for KeySink {
+		fn flush(&mut self, table: &MemTable) -> io::Result<()> {
+			if *self.fail.lock().unwrap() {
+				return Err(io::Error::other("sink unavailable"));
+			}
+			self.flushed.lock().unwrap().push(table.iter().map(|e| e.key.clone()).collect());
+			Ok(())
+		}
+	}
+
+	// A filter removing the keys starting with `tmp/` and upper casing the
+	//	values of the others
+	struct UpperCaseFilter;
+
+	impl CompactionFilter for UpperCaseFilter {
+		fn filter(&mut self, entry: &MemTableEntry) -> FilterDecision {
+			match entry.key.starts_with(b"tmp/") {
+				true => FilterDecision::Remove,
+				false => FilterDecision::ChangeValue(entry.value.as_ref().unwrap().to_ascii_uppercase()),
+			}
+		}
+	}
+
+	// A sink checking a filtered table keeps the metadata and range
+	//	tombstones of the table in `test_filtered_sink`
+	struct MetaSink;
+
+	impl FlushSink for MetaSink {
+		fn flush(&mut self, table: &MemTable) -> io::Result<()> {
+			assert_eq!(table.get(b"Monday").unwrap().meta.as_deref(), Some(&b"v1"[..]));
+			assert_eq!(table.range_tombstones().len(), 1);
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn test_filtered_sink() {
+		let mut table = MemTable::new();
+		table.set_with_meta(b"Monday", b"Rejoice", Some(b"v1"), 0);
+		table.set(b"tmp/Monday", b"Blues", 5);
+		table.delete(b"Friday", 21);
+		table.set(b"Sunday", b"Rest", 10);
+		table.delete_range(b"S", b"T", 30);
+
+		let mut sink = FilteredSink::new(UpperCaseFilter, JsonLinesSink::new(Vec::new()));
+		sink.flush(&table).unwrap();
+		let dump = String::from_utf8(sink.into_inner().into_inner()).unwrap();
+		assert_eq!(dump, "\
+			{\"key\":\"Friday\",\"value\":null,\"timestamp\":21,\"deleted\":true}\n\
+			{\"key\":\"Monday\",\"value\":\"REJOICE\",\"timestamp\":0,\"deleted\":false}\n\
+			{\"key\":\"Sunday\",\"value\":null,\"timestamp\":30,\"deleted\":true}\n\
+			{\"key\":\"tmp/Monday\",\"value\":null,\"timestamp\":5,\"deleted\":true}\n");
+
+		// The flushed MemTable is unchanged, and the filtered one keeps its
+		//	metadata and range tombstones
+		assert_eq!(table.get(b"Monday").unwrap().value.as_deref(), Some(&b"Rejoice"[..]));
+		FilteredSink::new(UpperCaseFilter, MetaSink).flush(&table).unwrap();
+	}
+
+	#[test]
+	fn test_csv_sink() {
+		let mut table = MemTable::new();
+		table.set(b"Monday", b"Rejoice, \"Blues\"", 0);
+		table.set(b"Tuesday", b"Celebrate", 10);
+		table.delete(b"Friday", 21);
+
+		let mut sink = CsvSink::new(Vec::new());
+		sink.flush(&table).unwrap();
+		sink.flush(&MemTable::new()).unwrap();
+
+		let csv = String::from_utf8(sink.into_inner()).unwrap();
+		assert_eq!(csv, "key,value,timestamp,deleted\n\
+			Friday,,21,true\n\
+			Monday,\"Rejoice, \"\"Blues\"\"\",0,false\n\
+			Tuesday,Celebrate,10,false\n");
+	}
+
+	#[test]
+	fn test_dump_jsonl() {
+		let mut table = MemTable::new();
+		table.set(b"Monday", b"Rejoice, \"Blues\"\n", 0);
+		table.set(b"Tuesday", b"\xff\x00", 10);
+		table.delete(b"Friday", 21);
+
+		let mut dump = Vec::new();
+		let options = IterOptions::new().lower(b"G");
+		assert_eq!(dump_jsonl(&table, &options, &mut dump).unwrap(), 2);
+		assert_eq!(String::from_utf8(dump).unwrap(), "\
+			{\"key\":\"Monday\",\"value\":\"Rejoice, \\\"Blues\\\"\\n\",\"timestamp\":0,\"deleted\":false}\n\
+			{\"key\":\"Tuesday\",\"value_base64\":\"/wA=\",\"timestamp\":10,\"deleted\":false}\n");
+
+		// Tombstones are written when flushed
+		let mut sink = JsonLinesSink::new(Vec::new());
+		sink.flush(&table).unwrap();
+		let dump = String::from_utf8(sink.into_inner()).unwrap();
+		assert_eq!(dump.lines().next().unwrap(), "{\"key\":\"Friday\",\"value\":null,\"timestamp\":21,\"deleted\":true}");
+		assert_eq!(dump.lines().count(), 3);
+
+		assert_eq!(base64(b""), "");
+		assert_eq!(base64(b"Mon"), "TW9u");
+		assert_eq!(base64(b"Monday"), "TW9uZGF5");
+		assert_eq!(base64(b"Rejoice"), "UmVqb2ljZQ==");
+	}
+
+	#[test]
+	fn test_flush_timer() {
+		let table = Arc::new(Mutex::new(MemTable::with_capacity(1024, 16)));
+		let flushed = Arc::new(Mutex::new(Vec::new()));
+		let fail = Arc::new(Mutex::new(true));
+		let sink = KeySink { flushed: Arc::clone(&flushed), fail: Arc::clone(&fail) };
+		let timer = FlushTimer::start(Arc::clone(&table), Box::new(sink), Duration::from_millis(10));
+
+		// An empty table is not flushed
+		thread::sleep(Duration::from_millis(50));
+		assert_eq!(timer.flushes(), 0);
+
+		// Records are kept while the sink fails
+		table.lock().unwrap().set(b"Monday", b"Rejoice", 0);
+		thread::sleep(Duration::from_millis(50));
+		assert_eq!(table.lock().unwrap().len(), 1);
+
+		*fail.lock().unwrap() = false;
+		while timer.flushes() == 0 {
+			thread::sleep(Duration::from_millis(5));
+		}
+		assert_eq!(*flushed.lock().unwrap(), vec![vec![b"Monday".to_vec()]]);
+		let table = table.lock().unwrap();
+		assert!(table.is_empty());
+		assert_eq!(table.capacity().entries, 16);
+		drop(table);
+
+		assert_eq!(timer.stop().unwrap_err().to_string(), "sink unavailable");
+	}
+}
@@ -0,0 +1,225 @@
+// The byte layouts of the files written by the engine, as data, so other
+//   implementations and future versions can check their compatibility.
+//
+// All integers are little-endian. A WAL file is a sequence of blocks of
+//   `WAL_BLOCK_SIZE` bytes holding fragments laid out as `WAL_FRAGMENT`,
+//   whose data joins into records laid out by their kind. A snapshot file
+//   is laid out as `SNAPSHOT_HEADER`, the entries, the range tombstones and
+//   a CRC-32C of every byte before it.
+//
+// The golden files are written by this version for a fixed sequence of
+//   operations, described with each, and must be read back unchanged by
+//   later versions.
+
+use crate::log_format;
+
+
+/// A Field describes one field of a layout, in the order it is written.
+///
+/// `size` is the length of the field in bytes, or None if it has a
+///   variable length, given by an earlier field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field {
+	pub name: &'static str,
+	pub size: Option<usize>,
+}
+
+
+const fn fixed(name: &'static str, size: usize) -> Field {
+	Field { name, size: Some(size) }
+}
+
+const fn variable(name: &'static str) -> Field {
+	Field { name, size: None }
+}
+
+
+// The WAL has no version field, so its layout is versioned here
+pub const WAL_VERSION: u32 = 1;
+pub const WAL_BLOCK_SIZE: usize = log_format::BLOCK_SIZE;
+
+pub const WAL_FRAGMENT: &[Field] = &[
+	fixed("crc", 4),
+	fixed("length", 2),
+	fixed("type", 1),
+	variable("data"),
+];
+
+// The kind of each WAL record, stored after its first length
+pub const SET_KIND: u8 = 0;
+pub const DELETE_KIND: u8 = 1;
+pub const DELETE_RANGE_KIND: u8 = 2;
+pub const SET_WITH_META_KIND: u8 = 3;
+pub const BATCH_KIND: u8 = 4;
+
+pub const WAL_SET: &[Field] = &[
+	fixed("key_len", 8),
+	fixed("kind", 1),
+	fixed("value_len", 8),
+	variable("key"),
+	variable("value"),
+	fixed("timestamp", 16),
+];
+
+pub const WAL_SET_WITH_META: &[Field] = &[
+	fixed("key_len", 8),
+	fixed("kind", 1),
+	fixed("value_len", 8),
+	fixed("meta_len", 8),
+	variable("key"),
+	variable("value"),
+	variable("meta"),
+	fixed("timestamp", 16),
+];
+
+pub const WAL_DELETE: &[Field] = &[
+	fixed("key_len", 8),
+	fixed("kind", 1),
+	variable("key"),
+	fixed("timestamp", 16),
+];
+
+pub const WAL_DELETE_RANGE: &[Field] = &[
+	fixed("start_len", 8),
+	fixed("kind", 1),
+	fixed("end_len", 8),
+	variable("start"),
+	variable("end"),
+	fixed("timestamp", 16),
+];
+
+// A batch is followed by `count` records, each prefixed by its length
+pub const WAL_BATCH: &[Field] = &[
+	fixed("count", 8),
+	fixed("kind", 1),
+];
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"MEMTABLE";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// The flags of a snapshot entry
+pub const SNAPSHOT_DELETED_FLAG: u8 = 1;
+pub const SNAPSHOT_VALUE_FLAG: u8 = 2;
+pub const SNAPSHOT_META_FLAG: u8 = 4;
+
+pub const SNAPSHOT_HEADER: &[Field] = &[
+	fixed("magic", 8),
+	fixed("version", 4),
+	fixed("count", 8),
+];
+
+// The value and meta lengths are only present if set in the flags
+pub const SNAPSHOT_ENTRY: &[Field] = &[
+	fixed("key_len", 8),
+	fixed("flags", 1),
+	fixed("value_len", 8),
+	fixed("meta_len", 8),
+	variable("key"),
+	variable("value"),
+	variable("meta"),
+	fixed("timestamp", 16),
+];
+
+pub const SNAPSHOT_RANGE_TOMBSTONE: &[Field] = &[
+	fixed("start_len", 8),
+	fixed("end_len", 8),
+	variable("start"),
+	variable("end"),
+	fixed("timestamp", 16),
+];
+
+
+// A WAL holding, in order: a set of Monday to Rejoice at 0, a set of
+//	Tuesday to Celebrate with metadata `v2` at 10, a delete of Friday at 21,
+//	a delete of the range [A, B) at 30, and a batch of a set of Sunday to
+//	Rest at 40 and a delete of Monday at 41
+pub const GOLDEN_WAL: &[u8] = include_bytes!("../golden/wal_v1.log");
+
+// A snapshot of the MemTable recovered from GOLDEN_WAL
+pub const GOLDEN_SNAPSHOT: &[u8] = include_bytes!("../golden/snapshot_v1.snapshot");
+
+
+// Gets the number of bytes of a layout's fixed size fields
+pub fn fixed_len(layout: &[Field]) -> usize {
+	layout.iter().filter_map(|f| f.size).sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{read, remove_file};
+	use std::path::PathBuf;
+	use rand::Rng;
+
+	use crate::format::{fixed_len, GOLDEN_SNAPSHOT, GOLDEN_WAL, WAL_DELETE, WAL_DELETE_RANGE, WAL_FRAGMENT, WAL_SET, WAL_SET_WITH_META};
+	use crate::log_format::HEADER_SIZE;
+	use crate::log_storage::MemoryStorage;
+	use crate::mem_table::MemTable;
+	use crate::wal::{MemWAL, WalOptions, WalRecord, WAL};
+	use crate::wal_iterator::WALIterator;
+
+	// Writes the operations held in the golden WAL
+	fn write_golden_wal(wal: &mut WAL) {
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.append(&WalRecord::Set { key: b"Tuesday", value: b"Celebrate", meta: Some(b"v2"), timestamp: 10 }).unwrap();
+		wal.delete(b"Friday", 21).unwrap();
+		wal.delete_range(b"A", b"B", 30).unwrap();
+		wal.append_many(&[
+			WalRecord::Set { key: b"Sunday", value: b"Rest", meta: None, timestamp: 40 },
+			WalRecord::Delete { key: b"Monday", timestamp: 41 },
+		]).unwrap();
+	}
+
+	#[test]
+	fn test_layouts() {
+		assert_eq!(fixed_len(WAL_FRAGMENT), HEADER_SIZE);
+		assert_eq!(fixed_len(WAL_SET), 33);
+		assert_eq!(fixed_len(WAL_SET_WITH_META), 41);
+		assert_eq!(fixed_len(WAL_DELETE), 25);
+		assert_eq!(fixed_len(WAL_DELETE_RANGE), 33);
+
+		// The first fragment of the golden WAL holds a set of Monday
+		let record_len = fixed_len(WAL_SET) + b"Monday".len() + b"Rejoice".len();
+		assert_eq!(u16::from_le_bytes([GOLDEN_WAL[4], GOLDEN_WAL[5]]) as usize, record_len);
+	}
+
+	#[test]
+	fn test_golden_wal() {
+		let storage = MemoryStorage::new();
+		let mut wal = WAL::from_storage(Box::new(storage.clone()), WalOptions::default()).unwrap();
+		write_golden_wal(&mut wal);
+		wal.close().unwrap();
+		assert_eq!(storage.contents(), GOLDEN_WAL);
+
+		let entries: Vec<_> = WALIterator::from_reader(GOLDEN_WAL)
+			.map(|e| (e.key, e.value, e.meta, e.timestamp, e.deleted, e.range_end))
+			.collect();
+		assert_eq!(entries, vec![
+			(b"Monday".to_vec(), Some(b"Rejoice".to_vec()), None, 0, false, None),
+			(b"Tuesday".to_vec(), Some(b"Celebrate".to_vec()), Some(b"v2".to_vec()), 10, false, None),
+			(b"Friday".to_vec(), None, None, 21, true, None),
+			(b"A".to_vec(), None, None, 30, true, Some(b"B".to_vec())),
+			(b"Sunday".to_vec(), Some(b"Rest".to_vec()), None, 40, false, None),
+			(b"Monday".to_vec(), None, None, 41, true, None),
+		]);
+	}
+
+	#[test]
+	fn test_golden_snapshot() {
+		let mem_wal = MemWAL::new();
+		let (mut wal, _) = mem_wal.open(WalOptions::default()).unwrap();
+		write_golden_wal(&mut wal);
+		wal.close().unwrap();
+		let (_, table) = mem_wal.open(WalOptions::default()).unwrap();
+
+		let path = PathBuf::from(format!("./{}.snapshot", rand::thread_rng().gen::<u32>()));
+		table.export(&path).unwrap();
+		assert_eq!(read(&path).unwrap(), GOLDEN_SNAPSHOT);
+
+		std::fs::write(&path, GOLDEN_SNAPSHOT).unwrap();
+		let imported = MemTable::import(&path).unwrap();
+		assert!(imported.iter_with_tombstones().eq(table.iter_with_tombstones()));
+		assert_eq!(imported.range_tombstones(), table.range_tombstones());
+		remove_file(&path).unwrap();
+	}
+}
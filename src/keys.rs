@@ -0,0 +1,188 @@
+// Encodings of keys whose byte-wise ordering matches the ordering of the
+//   values they encode, so range scans over a MemTable return the values in
+//   order.
+//
+// Integers are big-endian, with the sign bit of signed integers flipped so
+//   negative values sort first. Floats flip the sign bit of positive values
+//   and every bit of negative values, ordering them as numbers with -0.0
+//   before 0.0 and NaNs at either end by their sign.
+//
+// A composite key joins several components, each with its 0x00 bytes
+//   escaped as 0x00 0xFF and terminated by 0x00 0x01. Keys are then ordered
+//   by their first component, then their second and so on, and the keys
+//   which start with some components share the prefix given by
+//   `composite_prefix`.
+
+
+const SIGN_BIT: u64 = 1 << 63;
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+
+pub fn encode_u64(value: u64) -> [u8; 8] {
+	value.to_be_bytes()
+}
+
+pub fn decode_u64(bytes: &[u8]) -> Option<u64> {
+	Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+pub fn encode_i64(value: i64) -> [u8; 8] {
+	(value as u64 ^ SIGN_BIT).to_be_bytes()
+}
+
+pub fn decode_i64(bytes: &[u8]) -> Option<i64> {
+	Some((decode_u64(bytes)? ^ SIGN_BIT) as i64)
+}
+
+pub fn encode_f64(value: f64) -> [u8; 8] {
+	let bits = value.to_bits();
+	match bits & SIGN_BIT {
+		0 => bits ^ SIGN_BIT,
+		_ => !bits,
+	}.to_be_bytes()
+}
+
+pub fn decode_f64(bytes: &[u8]) -> Option<f64> {
+	let bits = decode_u64(bytes)?;
+	Some(f64::from_bits(match bits & SIGN_BIT {
+		0 => !bits,
+		_ => bits ^ SIGN_BIT,
+	}))
+}
+
+// Parses a UUID written as 32 hex digits, optionally separated by hyphens,
+//	into its 16 bytes, which order as the text does
+pub fn encode_uuid(uuid: &str) -> Option<[u8; 16]> {
+	let digits: Vec<u8> = uuid.bytes().filter(|b| *b != b'-').collect();
+	if digits.len() != 32 {
+		return None;
+	}
+
+	let mut bytes = [0; 16];
+	for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+		*byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+	}
+	Some(bytes)
+}
+
+// Formats the bytes of a UUID in its hyphenated lower case form
+pub fn decode_uuid(bytes: &[u8]) -> Option<String> {
+	let bytes: &[u8; 16] = bytes.try_into().ok()?;
+	let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+	Some(format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..]))
+}
+
+
+// Encodes components into a composite key
+pub fn encode_composite(components: &[&[u8]]) -> Vec<u8> {
+	composite_prefix(components)
+}
+
+// Decodes the components of a composite key, or None if it is malformed
+pub fn decode_composite(mut key: &[u8]) -> Option<Vec<Vec<u8>>> {
+	let mut components = Vec::new();
+	while !key.is_empty() {
+		let mut component = Vec::new();
+		loop {
+			match key {
+				[ESCAPE, TERMINATOR, rest @ ..] => {
+					key = rest;
+					break;
+				},
+				[ESCAPE, ESCAPED_ZERO, rest @ ..] => {
+					component.push(0);
+					key = rest;
+				},
+				[ESCAPE, ..] | [] => return None,
+				[byte, rest @ ..] => {
+					component.push(*byte);
+					key = rest;
+				},
+			}
+		}
+		components.push(component);
+	}
+
+	Some(components)
+}
+
+// Gets the prefix shared by every composite key whose leading components
+//	are the given components
+pub fn composite_prefix(components: &[&[u8]]) -> Vec<u8> {
+	let len = components.iter().map(|c| c.len() + 2).sum();
+	let mut key = Vec::with_capacity(len);
+	for component in components {
+		for byte in component.iter() {
+			match *byte {
+				ESCAPE => key.extend_from_slice(&[ESCAPE, ESCAPED_ZERO]),
+				byte => key.push(byte),
+			}
+		}
+		key.extend_from_slice(&[ESCAPE, TERMINATOR]);
+	}
+	key
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::keys::{
+		composite_prefix, decode_composite, decode_f64, decode_i64, decode_uuid, encode_composite,
+		encode_f64, encode_i64, encode_u64, encode_uuid,
+	};
+
+	// Asserts the encodings of ordered values are ordered
+	fn assert_ordered<T, E: Ord>(values: &[T], encode: impl Fn(&T) -> E) {
+		for pair in values.windows(2) {
+			assert!(encode(&pair[0]) < encode(&pair[1]));
+		}
+	}
+
+	#[test]
+	fn test_numbers() {
+		assert_ordered(&[0, 1, 255, 256, u64::MAX], |v| encode_u64(*v));
+		assert_ordered(&[i64::MIN, -256, -1, 0, 1, 255, i64::MAX], |v| encode_i64(*v));
+		assert_ordered(
+			&[f64::NEG_INFINITY, -1e10, -1.5, -0.0, 0.0, 1e-300, 1.5, 1e10, f64::INFINITY],
+			|v| encode_f64(*v),
+		);
+
+		for value in [i64::MIN, -1, 0, i64::MAX] {
+			assert_eq!(decode_i64(&encode_i64(value)), Some(value));
+		}
+		for value in [f64::NEG_INFINITY, -1.5, -0.0, 0.0, 1.5] {
+			assert_eq!(decode_f64(&encode_f64(value)).unwrap().to_bits(), value.to_bits());
+		}
+		assert_eq!(decode_i64(b"short"), None);
+	}
+
+	#[test]
+	fn test_uuid() {
+		let uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+		let bytes = encode_uuid(uuid).unwrap();
+		assert_eq!(bytes[0], 0x67);
+		assert_eq!(decode_uuid(&bytes).unwrap(), uuid);
+		assert_eq!(encode_uuid("67e5504410b1426f9247bb680e5fe0c8"), Some(bytes));
+		assert_eq!(encode_uuid("67e55044"), None);
+		assert_eq!(encode_uuid("zze55044-10b1-426f-9247-bb680e5fe0c8"), None);
+	}
+
+	#[test]
+	fn test_composite() {
+		let key = encode_composite(&[b"users", b"a\x00b", b""]);
+		assert_eq!(key, b"users\x00\x01a\x00\xffb\x00\x01\x00\x01");
+		assert_eq!(decode_composite(&key), Some(vec![b"users".to_vec(), b"a\x00b".to_vec(), Vec::new()]));
+		assert_eq!(decode_composite(b"users"), None);
+		assert_eq!(decode_composite(b"a\x00\x02"), None);
+
+		// Keys order by component, so a shorter component sorts first
+		assert_ordered(
+			&[&[b"a" as &[u8], b"z"][..], &[b"a\x00", b"a"], &[b"ab", b"a"], &[b"b"]],
+			|c| encode_composite(c),
+		);
+		assert!(encode_composite(&[b"users", b"Monday"]).starts_with(&composite_prefix(&[b"users"])));
+		assert!(!encode_composite(&[b"users2"]).starts_with(&composite_prefix(&[b"users"])));
+	}
+}
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+
+// Values below this are counted exactly, larger ones in buckets
+const EXACT_NANOS: u64 = 16;
+// The number of buckets each power of two above EXACT_NANOS is split into
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = EXACT_NANOS as usize + (64 - 4) * SUB_BUCKETS;
+
+
+/// A LatencyHistogram records durations to report their percentiles.
+///
+/// Durations are counted in buckets which split each power of two of
+///   nanoseconds into 8, like an HDR histogram, so percentiles are within
+///   12.5% of the durations recorded while the histogram stays a fixed
+///   size however many are recorded.
+#[derive(Clone, Debug)]
+pub struct LatencyHistogram {
+	counts: Vec<u64>,
+	count: u64,
+	max: Duration,
+}
+
+
+/// A LatencySummary holds the percentiles of the durations recorded in a
+///   LatencyHistogram, which are zero if none were recorded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+	pub count: u64,
+	pub p50: Duration,
+	pub p95: Duration,
+	pub p99: Duration,
+	pub max: Duration,
+}
+
+
+impl LatencyHistogram {
+	pub fn new() -> LatencyHistogram {
+		LatencyHistogram { counts: vec![0; BUCKETS], count: 0, max: Duration::ZERO }
+	}
+
+	pub fn record(&mut self, latency: Duration) {
+		let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+		self.counts[bucket_of(nanos)] += 1;
+		self.count += 1;
+		self.max = self.max.max(latency);
+	}
+
+	// Gets the number of durations recorded
+	pub fn count(&self) -> u64 {
+		self.count
+	}
+
+	pub fn max(&self) -> Duration {
+		self.max
+	}
+
+	// Gets the duration which the given fraction of the recorded durations
+	//	are at most, rounded up to the end of its bucket but never beyond the
+	//	largest duration recorded
+	pub fn percentile(&self, fraction: f64) -> Duration {
+		if self.count == 0 {
+			return Duration::ZERO;
+		}
+
+		let rank = ((fraction.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+		let mut seen = 0;
+		for (bucket, count) in self.counts.iter().enumerate() {
+			seen += count;
+			if seen >= rank {
+				return Duration::from_nanos(bucket_end(bucket)).min(self.max);
+			}
+		}
+		self.max
+	}
+
+	pub fn summary(&self) -> LatencySummary {
+		LatencySummary {
+			count: self.count,
+			p50: self.percentile(0.50),
+			p95: self.percentile(0.95),
+			p99: self.percentile(0.99),
+			max: self.max,
+		}
+	}
+}
+
+impl Default for LatencyHistogram {
+	fn default() -> LatencyHistogram {
+		LatencyHistogram::new()
+	}
+}
+
+
+// Gets the bucket counting a number of nanoseconds
+fn bucket_of(nanos: u64) -> usize {
+	if nanos < EXACT_NANOS {
+		return nanos as usize;
+	}
+	let exponent = 63 - nanos.leading_zeros();
+	let sub_bucket = (nanos >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+	EXACT_NANOS as usize + (exponent as usize - 4) * SUB_BUCKETS + sub_bucket
+}
+
+// Gets the largest number of nanoseconds counted by a bucket
+fn bucket_end(bucket: usize) -> u64 {
+	if bucket < EXACT_NANOS as usize {
+		return bucket as u64;
+	}
+	let exponent = ((bucket - EXACT_NANOS as usize) / SUB_BUCKETS) as u32 + 4;
+	let sub_bucket = ((bucket - EXACT_NANOS as usize) % SUB_BUCKETS) as u64;
+	let width = 1u64 << (exponent - SUB_BUCKET_BITS);
+	((SUB_BUCKETS as u64 + sub_bucket) * width).saturating_add(width - 1)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use crate::latency::{bucket_end, bucket_of, LatencyHistogram, LatencySummary};
+
+	#[test]
+	fn test_buckets() {
+		for nanos in [0, 15, 16, 17, 100, 1023, 1024, 1_000_000, u64::MAX] {
+			let bucket = bucket_of(nanos);
+			assert!(bucket_end(bucket) >= nanos);
+			assert!(bucket == 0 || bucket_end(bucket - 1) < nanos);
+		}
+		assert_eq!(bucket_end(bucket_of(u64::MAX)), u64::MAX);
+	}
+
+	#[test]
+	fn test_latency_histogram() {
+		let mut histogram = LatencyHistogram::new();
+		assert_eq!(histogram.summary(), LatencySummary::default());
+
+		for micros in 1..=100 {
+			histogram.record(Duration::from_micros(micros));
+		}
+		histogram.record(Duration::from_millis(50));
+
+		let summary = histogram.summary();
+		assert_eq!(summary.count, 101);
+		assert_eq!(summary.max, Duration::from_millis(50));
+		// Percentiles are within a bucket of the durations recorded
+		assert!(summary.p50 >= Duration::from_micros(51) && summary.p50 < Duration::from_micros(58));
+		assert!(summary.p99 >= Duration::from_micros(100) && summary.p99 < Duration::from_micros(113));
+		assert_eq!(histogram.percentile(1.0), Duration::from_millis(50));
+	}
+}
@@ -1,4 +1,51 @@
-mod mem_table;
-mod utils;
-mod wal;
-mod wal_iterator;
\ No newline at end of file
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod bucket;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod event_listener;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod flush_sink;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod keys;
+#[cfg(feature = "std")]
+pub mod latency;
+#[cfg(feature = "std")]
+pub mod log_format;
+#[cfg(feature = "std")]
+pub mod log_storage;
+pub mod mem_table;
+#[cfg(feature = "std")]
+pub mod mem_table_backend;
+#[cfg(feature = "std")]
+pub mod memory_budget;
+#[cfg(feature = "std")]
+pub mod migrate;
+#[cfg(feature = "std")]
+pub mod prefix_table;
+#[cfg(feature = "std")]
+pub mod resp;
+#[cfg(feature = "std")]
+pub mod rocksdb;
+#[cfg(feature = "std")]
+pub mod sharded_mem_table;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod subscription;
+#[cfg(feature = "std")]
+pub mod typed_table;
+#[cfg(feature = "std")]
+pub mod utils;
+#[cfg(feature = "std")]
+pub mod wal;
+#[cfg(feature = "std")]
+pub mod wal_iterator;
@@ -0,0 +1,588 @@
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::mem;
+
+use crate::utils::{crc32c, crc32c_extend};
+
+
+// The WAL is stored as a sequence of 32KB blocks.
+//
+// Each record is written as one or more fragments, split so no fragment
+//   crosses a block boundary. If fewer than `HEADER_SIZE` bytes remain in a
+//   block they are zero-filled and the next fragment starts a new block.
+//
+// +-----------+-------------+-----------+--...--+
+// | CRC (4B)  | Length (2B) | Type (1B) | Data  |
+// +-----------+-------------+-----------+--...--+
+//
+// CRC = CRC-32C of the Type and Data
+// Length = Length of the Data
+// Type = Whether the fragment holds a FULL record, or the FIRST, a MIDDLE
+//   or the LAST part of a record
+// Data = The bytes of the record held in the fragment
+//
+// A reader which finds a corrupt fragment skips the rest of its block, so
+//   corruption loses at most the records touching one block.
+//
+// Logs written before the block format hold their records back to back
+//   without framing, and are still read. The length of each unframed
+//   record is found from the lengths at its start, which follow the record
+//   layout described in the wal_iterator module:
+//
+// +---------------+---------------+-----------------+-...-+--...--+-----------------+
+// | Key Size (8B) | Tombstone(1B) | Value Size (8B) | Key | Value | Timestamp (16B) |
+// +---------------+---------------+-----------------+-...-+--...--+-----------------+
+//
+// where deletes, with a Tombstone of 1, have no Value Size or Value. The
+//   two are told apart by the first bytes of the log. A framed log starts
+//   with a FULL or FIRST fragment, whose type is its 7th byte, so its first
+//   8 bytes read as a Key Size of at least 2^48, which no unframed log
+//   holds. A log whose first block is all zeros, such as one preallocated
+//   before any record is written, is framed.
+pub const BLOCK_SIZE: usize = 32 * 1024;
+pub const HEADER_SIZE: usize = 7;
+
+pub const ZERO_TYPE: u8 = 0;
+pub const FULL_TYPE: u8 = 1;
+pub const FIRST_TYPE: u8 = 2;
+pub const MIDDLE_TYPE: u8 = 3;
+pub const LAST_TYPE: u8 = 4;
+
+
+/// A LogWriter splits records into checksummed fragments within blocks
+///   and writes them to its destination.
+pub struct LogWriter<W: Write> {
+	dest: W,
+	// The offset of the next write within the current block
+	block_offset: usize,
+	// The number of bytes written to the destination, including headers
+	//	and the padding at the end of blocks
+	bytes_written: u64,
+}
+
+
+/// A LogReader reads the records written by a LogWriter, skipping blocks
+///   which are corrupt.
+pub struct LogReader<R: Read> {
+	src: R,
+	block: Vec<u8>,
+	block_len: usize,
+	// The offset of the next fragment within the current block
+	offset: usize,
+	// The offset of the current block within the log
+	block_start: u64,
+	// The offset within the log of the first fragment of the last record
+	record_offset: u64,
+	// The offset and reason of each fragment skipped as corrupt, until taken
+	corruptions: Vec<(u64, &'static str)>,
+	// The error which ended reading from the source early, until taken
+	read_error: Option<io::Error>,
+	eof: bool,
+	framing: Framing,
+}
+
+
+// How the records of a log are laid out, which is found once its first
+//	block is read
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Framing {
+	Unknown,
+	Blocks,
+	Unframed,
+}
+
+
+// The result of reading one fragment
+enum Fragment {
+	Data(u8, usize, usize),
+	Corrupt,
+	Eof,
+}
+
+
+impl<W: Write> LogWriter<W> {
+	// Creates a LogWriter appending to a destination which already holds
+	//	`dest_len` bytes of the log
+	pub fn new(dest: W, dest_len: u64) -> LogWriter<W> {
+		LogWriter {
+			dest,
+			block_offset: (dest_len % BLOCK_SIZE as u64) as usize,
+			bytes_written: 0,
+		}
+	}
+
+	// Writes a record, fragmenting it across blocks as needed
+	pub fn add_record(&mut self, record: &[u8]) -> io::Result<()> {
+		let mut left = record;
+		let mut begin = true;
+		loop {
+			let leftover = BLOCK_SIZE - self.block_offset;
+			if leftover < HEADER_SIZE {
+				// Fill the rest of the block with zeros and switch to a new one
+				self.dest.write_all(&[0; HEADER_SIZE][..leftover])?;
+				self.bytes_written += leftover as u64;
+				self.block_offset = 0;
+			}
+
+			let available = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+			let fragment_len = left.len().min(available);
+			let end = fragment_len == left.len();
+			let fragment_type = match (begin, end) {
+				(true, true) => FULL_TYPE,
+				(true, false) => FIRST_TYPE,
+				(false, true) => LAST_TYPE,
+				(false, false) => MIDDLE_TYPE,
+			};
+
+			self.emit_fragment(fragment_type, &left[..fragment_len])?;
+			left = &left[fragment_len..];
+			begin = false;
+			if end {
+				return Ok(());
+			}
+		}
+	}
+
+	// Gets the number of bytes written by the LogWriter, including fragment
+	//	headers and block padding
+	pub fn bytes_written(&self) -> u64 {
+		self.bytes_written
+	}
+
+	// Gets a reference to the destination
+	pub fn get_ref(&self) -> &W {
+		&self.dest
+	}
+
+	// Gets a mutable reference to the destination
+	pub fn get_mut(&mut self) -> &mut W {
+		&mut self.dest
+	}
+
+	// Unwraps the LogWriter, returning the destination
+	pub fn into_inner(self) -> W {
+		self.dest
+	}
+
+	fn emit_fragment(&mut self, fragment_type: u8, data: &[u8]) -> io::Result<()> {
+		let crc = crc32c_extend(crc32c(&[fragment_type]), data);
+
+		self.dest.write_all(&crc.to_le_bytes())?;
+		self.dest.write_all(&(data.len() as u16).to_le_bytes())?;
+		self.dest.write_all(&[fragment_type])?;
+		self.dest.write_all(data)?;
+		self.block_offset += HEADER_SIZE + data.len();
+		self.bytes_written += (HEADER_SIZE + data.len()) as u64;
+
+		Ok(())
+	}
+}
+
+
+impl<R: Read> LogReader<R> {
+	pub fn new(src: R) -> LogReader<R> {
+		LogReader {
+			src,
+			block: vec![0; BLOCK_SIZE],
+			block_len: 0,
+			offset: 0,
+			block_start: 0,
+			record_offset: 0,
+			corruptions: Vec::new(),
+			read_error: None,
+			eof: false,
+			framing: Framing::Unknown,
+		}
+	}
+
+	// Reads the next record from the log.
+	//
+	// Returns None once the end of the log is reached. A record which is
+	//	cut short by the end of the log is not returned
+	pub fn read_record(&mut self) -> Option<Vec<u8>> {
+		if self.framing == Framing::Unknown {
+			self.detect_framing();
+		}
+		if self.framing == Framing::Unframed {
+			return self.read_unframed_record();
+		}
+
+		let mut record = Vec::new();
+		let mut in_record = false;
+		loop {
+			match self.read_fragment() {
+				Fragment::Eof => return None,
+				Fragment::Corrupt => {
+					// Drop any partially read record
+					record.clear();
+					in_record = false;
+				},
+				Fragment::Data(fragment_type, start, end) => {
+					if fragment_type == FULL_TYPE || fragment_type == FIRST_TYPE {
+						self.record_offset = self.block_start + (start - HEADER_SIZE) as u64;
+					}
+					let data = &self.block[start..end];
+					match fragment_type {
+						FULL_TYPE => return Some(data.to_vec()),
+						FIRST_TYPE => {
+							record.clear();
+							record.extend_from_slice(data);
+							in_record = true;
+						},
+						MIDDLE_TYPE if in_record => record.extend_from_slice(data),
+						LAST_TYPE if in_record => {
+							record.extend_from_slice(data);
+							return Some(record);
+						},
+						// The start of this record was lost to corruption
+						_ => {
+							record.clear();
+							in_record = false;
+						},
+					}
+				},
+			}
+		}
+	}
+
+	// Gets the offset within the log of the record last returned by
+	//	`read_record`
+	pub fn last_record_offset(&self) -> u64 {
+		self.record_offset
+	}
+
+	// Gets the offset within the log of the next fragment to be read, which
+	//	is the end of the record last returned by `read_record`
+	pub fn position(&self) -> u64 {
+		self.block_start + self.offset as u64
+	}
+
+	// Takes the offset and reason of each fragment skipped as corrupt since
+	//	the last call
+	pub fn take_corruptions(&mut self) -> Vec<(u64, &'static str)> {
+		mem::take(&mut self.corruptions)
+	}
+
+	// Takes the error which ended reading from the source, if reading ended
+	//	before the end of the log
+	pub fn take_read_error(&mut self) -> Option<io::Error> {
+		self.read_error.take()
+	}
+
+	// Gets whether the log was written without framing, before the block
+	//	format, which is known once a record has been read
+	pub fn is_unframed(&self) -> bool {
+		self.framing == Framing::Unframed
+	}
+
+	// Moves the reader to an offset within the log, which should be the
+	//	offset of a record such as one given by `last_record_offset`.
+	//
+	// The block holding the offset is read from its start, so the offsets
+	//	of later records stay correct
+	pub fn seek(&mut self, offset: u64) -> io::Result<()>
+	where
+		R: Seek,
+	{
+		if self.framing == Framing::Unknown {
+			self.detect_framing();
+		}
+		if self.framing == Framing::Unframed {
+			self.src.seek(SeekFrom::Start(offset))?;
+			self.block_start = offset;
+			self.block_len = 0;
+			self.offset = 0;
+			self.eof = false;
+			return Ok(());
+		}
+
+		let block_start = offset - offset % BLOCK_SIZE as u64;
+		self.src.seek(SeekFrom::Start(block_start))?;
+		self.block_start = block_start;
+		self.block_len = 0;
+		self.offset = 0;
+		self.eof = false;
+		if self.read_block() {
+			self.offset = ((offset - block_start) as usize).min(self.block_len);
+		}
+		Ok(())
+	}
+
+	// Reads the first block of the log to find how its records are laid out
+	fn detect_framing(&mut self) {
+		self.framing = Framing::Blocks;
+		if self.read_block() && is_unframed(&self.block[..self.block_len]) {
+			self.framing = Framing::Unframed;
+		}
+	}
+
+	// Reads the next record of an unframed log. A record of an unknown kind
+	//	ends the log, as the records after it can't be found
+	fn read_unframed_record(&mut self) -> Option<Vec<u8>> {
+		let start = self.position();
+		let mut record = Vec::new();
+		if self.read_unframed(9, &mut record) < 9 {
+			return None;
+		}
+		let value_size_len = match record[8] {
+			0 | 2 => 8,
+			1 => 0,
+			_ => {
+				self.corruptions.push((start, "unframed record has an unknown kind"));
+				self.offset = self.block_len;
+				self.eof = true;
+				return None;
+			},
+		};
+		if self.read_unframed(value_size_len, &mut record) < value_size_len {
+			return None;
+		}
+
+		let key_len = u64::from_le_bytes(record[0..8].try_into().unwrap());
+		let value_len = match value_size_len {
+			8 => u64::from_le_bytes(record[9..17].try_into().unwrap()),
+			_ => 0,
+		};
+		let len = key_len.saturating_add(value_len).saturating_add(16);
+		if self.read_unframed(len, &mut record) < len {
+			return None;
+		}
+		self.record_offset = start;
+		Some(record)
+	}
+
+	// Reads up to `len` bytes of an unframed log onto a buffer, taking the
+	//	bytes left in the block first, and returns the number read. The
+	//	buffer only grows as bytes are read, so a corrupt length can't
+	//	exhaust memory
+	fn read_unframed(&mut self, len: u64, buf: &mut Vec<u8>) -> u64 {
+		let buffered = (self.block_len - self.offset).min(usize::try_from(len).unwrap_or(usize::MAX));
+		buf.extend_from_slice(&self.block[self.offset..self.offset + buffered]);
+		self.offset += buffered;
+
+		let mut read = buffered as u64;
+		if read < len && !self.eof {
+			let start = buf.len();
+			let result = (&mut self.src).take(len - read).read_to_end(buf);
+			// The bytes read from the source follow those of the block
+			self.block_start += (buf.len() - start) as u64;
+			read += (buf.len() - start) as u64;
+			if let Err(e) = result {
+				self.read_error = Some(e);
+			}
+		}
+		if read < len {
+			self.eof = true;
+		}
+		read
+	}
+
+	// Reads the next fragment, loading the next block when the current one is
+	//	exhausted
+	fn read_fragment(&mut self) -> Fragment {
+		loop {
+			if self.block_len - self.offset < HEADER_SIZE {
+				if self.eof || !self.read_block() {
+					return Fragment::Eof;
+				}
+				continue;
+			}
+
+			let header = &self.block[self.offset..self.offset + HEADER_SIZE];
+			let crc = u32::from_le_bytes(header[0..4].try_into().unwrap());
+			let length = u16::from_le_bytes(header[4..6].try_into().unwrap()) as usize;
+			let fragment_type = header[6];
+
+			if fragment_type == ZERO_TYPE && length == 0 {
+				// The rest of the block is padding
+				self.offset = self.block_len;
+				continue;
+			}
+
+			let start = self.offset + HEADER_SIZE;
+			let end = start + length;
+			let fragment_offset = self.block_start + self.offset as u64;
+			if end > self.block_len {
+				self.offset = self.block_len;
+				if self.eof {
+					// The log was cut short while writing this fragment
+					return Fragment::Eof;
+				}
+				self.corruptions.push((fragment_offset, "fragment overruns its block"));
+				return Fragment::Corrupt;
+			}
+
+			let data = &self.block[start..end];
+			if crc32c_extend(crc32c(&[fragment_type]), data) != crc {
+				// The length may be corrupt too, so skip the rest of the block
+				self.offset = self.block_len;
+				self.corruptions.push((fragment_offset, "fragment checksum mismatch"));
+				return Fragment::Corrupt;
+			}
+
+			self.offset = end;
+			return Fragment::Data(fragment_type, start, end);
+		}
+	}
+
+	// Reads the next block from the source, returning false if there is no
+	//	more data. An error reading the source ends the log, and is kept to
+	//	be taken with `take_read_error`
+	fn read_block(&mut self) -> bool {
+		self.block_start += self.block_len as u64;
+		self.block_len = 0;
+		self.offset = 0;
+		while self.block_len < BLOCK_SIZE {
+			match self.src.read(&mut self.block[self.block_len..]) {
+				Ok(0) => {
+					self.eof = true;
+					break;
+				},
+				Ok(n) => self.block_len += n,
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+				Err(e) => {
+					self.read_error = Some(e);
+					self.eof = true;
+					break;
+				},
+			}
+		}
+		self.block_len > 0
+	}
+}
+
+
+// Gets whether the first block of a log holds unframed records, rather than
+//	fragments
+fn is_unframed(block: &[u8]) -> bool {
+	if block.len() < 9 || block.iter().all(|&b| b == 0) {
+		return false;
+	}
+	let key_len = u64::from_le_bytes(block[0..8].try_into().unwrap());
+	key_len < 1 << 32 && block[8] <= 2
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::log_format::{LogReader, LogWriter, BLOCK_SIZE, HEADER_SIZE};
+
+	fn write_log(records: &[Vec<u8>]) -> Vec<u8> {
+		let mut writer = LogWriter::new(Vec::new(), 0);
+		for record in records {
+			writer.add_record(record).unwrap();
+		}
+		writer.get_ref().clone()
+	}
+
+	fn read_log(log: &[u8]) -> Vec<Vec<u8>> {
+		let mut reader = LogReader::new(log);
+		let mut records = Vec::new();
+		while let Some(record) = reader.read_record() {
+			records.push(record);
+		}
+		records
+	}
+
+	#[test]
+	fn test_log_round_trip() {
+		let records = vec![
+			b"Monday".to_vec(),
+			Vec::new(),
+			vec![b'x'; BLOCK_SIZE * 3],
+			b"Tuesday".to_vec(),
+		];
+
+		let log = write_log(&records);
+		assert_eq!(read_log(&log), records);
+
+		let mut reader = LogReader::new(log.as_slice());
+		let offsets: Vec<u64> = (0..4).map(|_| {
+			reader.read_record().unwrap();
+			reader.last_record_offset()
+		}).collect();
+		// The third record is split into four fragments
+		assert_eq!(offsets, vec![0, 13, 20, 20 + BLOCK_SIZE as u64 * 3 + HEADER_SIZE as u64 * 4]);
+	}
+
+	#[test]
+	fn test_log_pads_block_tail() {
+		// Leave fewer than HEADER_SIZE bytes at the end of the first block
+		let first = vec![b'x'; BLOCK_SIZE - HEADER_SIZE - 3];
+		let records = vec![first, b"Monday".to_vec()];
+
+		let log = write_log(&records);
+		assert_eq!(log.len(), BLOCK_SIZE + HEADER_SIZE + 6);
+		assert_eq!(read_log(&log), records);
+	}
+
+	#[test]
+	fn test_log_corrupt_block_skipped() {
+		let records: Vec<Vec<u8>> = (0..3000u32)
+			.map(|i| format!("record {}", i).into_bytes())
+			.collect();
+
+		let mut log = write_log(&records);
+		// Corrupt a byte in the middle of the first block
+		log[BLOCK_SIZE / 2] ^= 0xFF;
+
+		let read = read_log(&log);
+		assert!(read.len() < records.len());
+		assert!(read.len() > records.len() / 2);
+		// Records after the first block are all recovered
+		assert_eq!(read.last(), records.last());
+	}
+
+	#[test]
+	fn test_log_truncated_record() {
+		let records = vec![b"Monday".to_vec(), vec![b'x'; BLOCK_SIZE]];
+
+		let log = write_log(&records);
+		assert_eq!(read_log(&log[..log.len() - 10]), vec![b"Monday".to_vec()]);
+	}
+
+	#[test]
+	fn test_log_unframed() {
+		// A set and a delete written back to back, as before the block format
+		let mut set = Vec::new();
+		set.extend_from_slice(&6u64.to_le_bytes());
+		set.push(0);
+		set.extend_from_slice(&7u64.to_le_bytes());
+		set.extend_from_slice(b"MondayRejoice");
+		set.extend_from_slice(&5u128.to_le_bytes());
+		let mut delete = Vec::new();
+		delete.extend_from_slice(&7u64.to_le_bytes());
+		delete.push(1);
+		delete.extend_from_slice(b"Tuesday");
+		delete.extend_from_slice(&10u128.to_le_bytes());
+		let log = [set.as_slice(), delete.as_slice(), &delete[..20]].concat();
+
+		let mut reader = LogReader::new(log.as_slice());
+		assert_eq!(reader.read_record(), Some(set.clone()));
+		assert!(reader.is_unframed());
+		assert_eq!(reader.read_record(), Some(delete.clone()));
+		assert_eq!((reader.last_record_offset(), reader.position()), (set.len() as u64, (set.len() + delete.len()) as u64));
+		// The record cut short isn't returned
+		assert_eq!(reader.read_record(), None);
+		assert!(reader.take_corruptions().is_empty());
+
+		// A log of zeros is framed, and holds no records
+		let mut reader = LogReader::new(&[0; 100][..]);
+		assert_eq!(reader.read_record(), None);
+		assert!(!reader.is_unframed());
+	}
+
+	#[test]
+	fn test_log_append_resumes_block() {
+		let mut log = write_log(&[vec![b'x'; BLOCK_SIZE - 100]]);
+
+		let mut writer = LogWriter::new(Vec::new(), log.len() as u64);
+		writer.add_record(&[b'y'; 200]).unwrap();
+		log.extend(writer.get_ref());
+
+		assert_eq!(read_log(&log), vec![vec![b'x'; BLOCK_SIZE - 100], vec![b'y'; 200]]);
+	}
+}
@@ -0,0 +1,353 @@
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+
+/// A LogStorage is the device a WAL is written to.
+///
+/// Storage is append-only: bytes are added to the end with `append` and
+///   read back from any offset with `read_at`. Appended bytes are only
+///   durable once `sync` returns.
+///
+/// The WAL writes to a FileStorage by default, but any storage can be used
+///   with `WAL::from_storage`, such as a MemoryStorage in tests or one
+///   backed by an object store or an encrypted device.
+pub trait LogStorage: Send {
+	// Appends bytes to the end of the storage
+	fn append(&mut self, data: &[u8]) -> io::Result<()>;
+
+	// Makes the bytes appended so far durable
+	fn sync(&mut self) -> io::Result<()>;
+
+	// Reads bytes starting at an offset into a buffer, returning the number
+	//	of bytes read, which is 0 at the end of the storage
+	fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+	// Gets the number of bytes held in the storage
+	fn len(&self) -> io::Result<u64>;
+
+	// Returns true if the storage holds no bytes
+	fn is_empty(&self) -> io::Result<bool> {
+		Ok(self.len()? == 0)
+	}
+
+	// Releases any space allocated ahead of the bytes appended, once no more
+	//	will be appended
+	fn trim(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+
+	// Opens a handle which makes the bytes appended to the storage durable
+	//	from another thread, or None if the storage doesn't support one
+	fn sync_handle(&self) -> io::Result<Option<Box<dyn SyncHandle>>> {
+		Ok(None)
+	}
+}
+
+
+/// A SyncHandle makes the bytes appended to a LogStorage durable without
+///   borrowing the storage, so they can be synced from a background thread
+///   while the WAL continues to append.
+pub trait SyncHandle: Send {
+	fn sync(&mut self) -> io::Result<()>;
+}
+
+
+/// A FileStorage stores a log in a file, which must be opened for both
+///   reading and appending.
+///
+/// A FileStorage created with `preallocated` instead writes to a file
+///   opened for reading and writing without appending. The file is
+///   allocated ahead of the bytes appended, so appends don't change its
+///   size. The bytes appended end at the logical length of the storage,
+///   and the zeros after them are released by `trim`.
+pub struct FileStorage {
+	file: File,
+	preallocation: Option<Preallocation>,
+}
+
+
+// The logical length of a preallocated file, and how far it is allocated
+struct Preallocation {
+	len: u64,
+	allocated: u64,
+	// The bytes allocated ahead of the logical length each time the
+	//	allocated space runs out
+	ahead: u64,
+}
+
+
+/// A MemoryStorage stores a log in memory.
+///
+/// Clones share the same bytes, so a clone kept aside can inspect what a
+///   WAL has written.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+	data: Arc<Mutex<Vec<u8>>>,
+}
+
+
+/// A StorageReader reads a LogStorage from the start as a stream of bytes.
+pub struct StorageReader<S> {
+	storage: S,
+	offset: u64,
+}
+
+
+// Adapts a LogStorage to the Write trait so writes can be buffered
+pub(crate) struct StorageWriter(pub(crate) Box<dyn LogStorage>);
+
+
+impl FileStorage {
+	pub fn new(file: File) -> FileStorage {
+		FileStorage { file, preallocation: None }
+	}
+
+	// Creates a FileStorage which appends after the first `len` bytes of a
+	//	file, allocating `ahead` bytes beyond the bytes appended whenever the
+	//	allocated space runs out. Any bytes of the file after `len` are
+	//	overwritten
+	pub fn preallocated(file: File, len: u64, ahead: u64) -> io::Result<FileStorage> {
+		let mut preallocation = Preallocation { len, allocated: file.metadata()?.len(), ahead };
+		if preallocation.allocated < len + ahead {
+			allocate(&file, len + ahead)?;
+			preallocation.allocated = len + ahead;
+		}
+		Ok(FileStorage { file, preallocation: Some(preallocation) })
+	}
+}
+
+impl LogStorage for FileStorage {
+	fn append(&mut self, data: &[u8]) -> io::Result<()> {
+		let preallocation = match self.preallocation.as_mut() {
+			Some(preallocation) => preallocation,
+			None => return self.file.write_all(data),
+		};
+		let end = preallocation.len + data.len() as u64;
+		if end > preallocation.allocated {
+			allocate(&self.file, end + preallocation.ahead)?;
+			preallocation.allocated = end + preallocation.ahead;
+		}
+		write_all_at(&self.file, data, preallocation.len)?;
+		preallocation.len = end;
+		Ok(())
+	}
+
+	// Appends within a preallocated file don't change its size, so only
+	//	its data needs syncing
+	fn sync(&mut self) -> io::Result<()> {
+		match self.preallocation {
+			Some(_) => self.file.sync_data(),
+			None => self.file.sync_all(),
+		}
+	}
+
+	#[cfg(unix)]
+	fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+		let buf = self.within_len(offset, buf);
+		std::os::unix::fs::FileExt::read_at(&self.file, buf, offset)
+	}
+
+	#[cfg(windows)]
+	fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+		let buf = self.within_len(offset, buf);
+		std::os::windows::fs::FileExt::seek_read(&self.file, buf, offset)
+	}
+
+	fn len(&self) -> io::Result<u64> {
+		match self.preallocation.as_ref() {
+			Some(preallocation) => Ok(preallocation.len),
+			None => Ok(self.file.metadata()?.len()),
+		}
+	}
+
+	fn trim(&mut self) -> io::Result<()> {
+		if let Some(preallocation) = self.preallocation.as_mut() {
+			self.file.set_len(preallocation.len)?;
+			preallocation.allocated = preallocation.len;
+		}
+		Ok(())
+	}
+
+	fn sync_handle(&self) -> io::Result<Option<Box<dyn SyncHandle>>> {
+		Ok(Some(Box::new(self.file.try_clone()?)))
+	}
+}
+
+impl FileStorage {
+	// Shortens a buffer so reading into it stops at the logical length of a
+	//	preallocated file
+	fn within_len<'a>(&self, offset: u64, buf: &'a mut [u8]) -> &'a mut [u8] {
+		match self.preallocation.as_ref() {
+			Some(preallocation) => {
+				let left = preallocation.len.saturating_sub(offset);
+				let len = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+				&mut buf[..len]
+			},
+			None => buf,
+		}
+	}
+}
+
+// Allocates the blocks of a file up to a length, extending it with zeros,
+//	so writes within it don't change the file's size or allocate blocks.
+//
+// Filesystems which don't support fallocate have the file extended with
+//	`set_len` instead, which leaves the blocks to be allocated as written
+#[cfg(target_os = "linux")]
+fn allocate(file: &File, len: u64) -> io::Result<()> {
+	use std::os::unix::io::AsRawFd;
+
+	let len = libc::off_t::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+	// SAFETY: the descriptor is owned by the open file
+	match unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len) } {
+		0 => Ok(()),
+		_ => {
+			let e = io::Error::last_os_error();
+			match e.raw_os_error() {
+				Some(libc::EOPNOTSUPP) => file.set_len(len as u64),
+				_ => Err(e),
+			}
+		},
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allocate(file: &File, len: u64) -> io::Result<()> {
+	if file.metadata()?.len() < len {
+		file.set_len(len)?;
+	}
+	Ok(())
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+	std::os::unix::fs::FileExt::write_all_at(file, data, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut data: &[u8], mut offset: u64) -> io::Result<()> {
+	while !data.is_empty() {
+		let n = std::os::windows::fs::FileExt::seek_write(file, data, offset)?;
+		if n == 0 {
+			return Err(io::Error::from(io::ErrorKind::WriteZero));
+		}
+		data = &data[n..];
+		offset += n as u64;
+	}
+	Ok(())
+}
+
+// Only the data is synced, as the file's metadata isn't needed to read the
+//	WAL back
+impl SyncHandle for File {
+	fn sync(&mut self) -> io::Result<()> {
+		self.sync_data()
+	}
+}
+
+
+impl MemoryStorage {
+	pub fn new() -> MemoryStorage {
+		MemoryStorage::default()
+	}
+
+	// Gets a copy of the bytes held in the storage
+	pub fn contents(&self) -> Vec<u8> {
+		self.data.lock().unwrap().clone()
+	}
+}
+
+impl LogStorage for MemoryStorage {
+	fn append(&mut self, data: &[u8]) -> io::Result<()> {
+		self.data.lock().unwrap().extend_from_slice(data);
+		Ok(())
+	}
+
+	fn sync(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+
+	fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+		let data = self.data.lock().unwrap();
+		let start = (offset as usize).min(data.len());
+		let len = buf.len().min(data.len() - start);
+		buf[..len].copy_from_slice(&data[start..start + len]);
+		Ok(len)
+	}
+
+	fn len(&self) -> io::Result<u64> {
+		Ok(self.data.lock().unwrap().len() as u64)
+	}
+
+	fn sync_handle(&self) -> io::Result<Option<Box<dyn SyncHandle>>> {
+		Ok(Some(Box::new(self.clone())))
+	}
+}
+
+impl SyncHandle for MemoryStorage {
+	fn sync(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+
+impl<S: Borrow<dyn LogStorage>> StorageReader<S> {
+	pub fn new(storage: S) -> StorageReader<S> {
+		StorageReader { storage, offset: 0 }
+	}
+}
+
+impl<S: Borrow<dyn LogStorage>> Read for StorageReader<S> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.storage.borrow().read_at(self.offset, buf)?;
+		self.offset += n as u64;
+		Ok(n)
+	}
+}
+
+
+impl Write for StorageWriter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.append(buf)?;
+		Ok(buf.len())
+	}
+
+	// Appended bytes are handed to the storage as they're written, making
+	//	them durable is left to `LogStorage::sync`
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::io::Read;
+
+	use crate::log_storage::{LogStorage, MemoryStorage, StorageReader};
+
+	#[test]
+	fn test_memory_storage() {
+		let mut storage = MemoryStorage::new();
+		let shared = storage.clone();
+
+		storage.append(b"Monday").unwrap();
+		storage.append(b"Rejoice").unwrap();
+		assert_eq!(shared.len().unwrap(), 13);
+		assert_eq!(shared.contents(), b"MondayRejoice");
+
+		let mut buf = [0; 4];
+		assert_eq!(storage.read_at(9, &mut buf).unwrap(), 4);
+		assert_eq!(&buf, b"oice");
+		assert_eq!(storage.read_at(20, &mut buf).unwrap(), 0);
+
+		let mut read = Vec::new();
+		StorageReader::new(&storage as &dyn LogStorage).read_to_end(&mut read).unwrap();
+		assert_eq!(read, b"MondayRejoice");
+	}
+}
@@ -1,3 +1,10 @@
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::ops::Bound;
+use core::ops::RangeBounds;
+
+
 /// A MemTable (memory table) holds a sorted list of MemTableEntries 
 ///   (records)
 ///
@@ -12,6 +19,18 @@ pub struct MemTable {
   entries: Vec<MemTableEntry>,
   // The size of the MemTable in units of bytes
   size: usize,
+  // The maximum size and number of records the MemTable accepts
+  capacity: Capacity,
+  // Deleted key ranges, kept so they can mask older records when flushed
+  range_tombstones: Vec<RangeTombstone>,
+  // The oldest and newest timestamps of any write applied to the MemTable
+  time_bounds: Option<(u128, u128)>,
+  // How writes older than the record of their key are handled
+  stale_writes: StaleWritePolicy,
+  // How writes with the same timestamp as the record of their key are handled
+  conflicts: ConflictPolicy,
+  // The timestamp and key of every record, ordering them by time
+  by_time: BTreeSet<(u128, Vec<u8>)>,
 }
 
 
@@ -23,11 +42,230 @@ pub struct MemTable {
 /// A MemTable entry also contains a timestamp to record the microseconds
 ///   when the write occurred
 /// And finally, a boolean to track tombstones for deleted items
+///
+/// Only tombstones have no value. An empty value is distinct from a
+///   tombstone, so keys can be stored for their presence alone.
+///
+/// Entries which are set may also carry small application-defined metadata,
+///   such as a schema version or the id of the node which wrote them.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MemTableEntry {
   pub key: Vec<u8>,
   pub value: Option<Vec<u8>>,
   pub timestamp: u128,
   pub deleted: bool,
+  pub meta: Option<Vec<u8>>,
+}
+
+
+/// The EntryKind of a MemTableEntry tells whether it sets its key to a
+///   value or is a tombstone deleting it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+  Put,
+  Tombstone,
+}
+
+
+/// A RangeTombstone records the deletion of every key in the range
+///   `[start, end)` at a timestamp.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+  pub start: Vec<u8>,
+  pub end: Vec<u8>,
+  pub timestamp: u128,
+}
+
+
+/// A Capacity is an amount of space in a MemTable, in both bytes and
+///   records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capacity {
+  pub bytes: usize,
+  pub entries: usize,
+}
+
+
+/// MemTableStats break down the records of a MemTable by state and size,
+///   to explain why a MemTable is full.
+///
+/// `average_entry_size` is the size of the MemTable divided by the number
+///   of records, rounded down, or 0 if the MemTable is empty.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemTableStats {
+  pub live_entries: usize,
+  pub tombstones: usize,
+  pub key_bytes: usize,
+  pub value_bytes: usize,
+  pub meta_bytes: usize,
+  pub average_entry_size: usize,
+}
+
+
+/// A RangeEstimate is the approximate number of records in a key range of
+///   a MemTable and their size, as returned by `MemTable::estimate_range`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RangeEstimate {
+  pub entries: usize,
+  pub bytes: usize,
+}
+
+
+/// The result of setting or deleting a key in a MemTable.
+///
+/// `Full` is returned when the MemTable does not have the capacity for the
+///   write, and `Stale` when the write is rejected by the StaleWritePolicy
+///   or ConflictPolicy, or is older than a range tombstone covering its key.
+///   In both cases the MemTable is left unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetOutcome {
+  Inserted,
+  Updated,
+  Full,
+  Stale,
+}
+
+
+/// The state of a key in a MemTable, as returned by `MemTable::contains_key`.
+///
+/// `Tombstoned` keys have been deleted, either by a tombstone of their own
+///   or by a range tombstone, so older records of them held outside of the
+///   MemTable are masked. `Absent` keys have no record in the MemTable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyState {
+  Present,
+  Tombstoned,
+  Absent,
+}
+
+
+/// A StaleWritePolicy decides what happens to a write whose timestamp is
+///   no newer than the record already held for its key, which can happen
+///   when the clocks of writers are skewed.
+///
+/// `Accept` applies the write as given, so the last write applied wins.
+///   `Reject` leaves the record unchanged and returns `SetOutcome::Stale`,
+///   so the write with the newest timestamp wins. `Bump` applies the write
+///   with the timestamp after the record's, so the last write applied wins
+///   and timestamps of a key always increase.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StaleWritePolicy {
+  #[default]
+  Accept,
+  Reject,
+  Bump,
+}
+
+
+/// A ConflictPolicy decides which of two writes to a key with the same
+///   timestamp is kept.
+///
+/// `LastWriteWins` keeps the write applied last, and `FirstWriteWins` keeps
+///   the record already held. Writes are applied to a MemTable in the order
+///   of their WAL sequence, including during recovery, so `LastWriteWins`
+///   also keeps the write with the highest sequence. `Custom` is given the
+///   current record and the incoming write, and returns true to keep the
+///   write.
+///
+/// A write which loses returns `SetOutcome::Stale`. A write which wins is
+///   then handled by the StaleWritePolicy, as any write no newer than the
+///   current record is.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ConflictPolicy {
+  #[default]
+  LastWriteWins,
+  FirstWriteWins,
+  Custom(fn(&MemTableEntry, &MemTableEntry) -> bool),
+}
+
+
+impl ConflictPolicy {
+  // Checks whether a write with the same timestamp as the current record
+  //  of its key loses to the record
+  fn keeps_current(self, current: &MemTableEntry, incoming: &MemTableEntry) -> bool {
+    match self {
+      ConflictPolicy::LastWriteWins => false,
+      ConflictPolicy::FirstWriteWins => true,
+      ConflictPolicy::Custom(incoming_wins) => !incoming_wins(current, incoming),
+    }
+  }
+}
+
+
+impl RangeTombstone {
+  // Returns true if the key is within the range of the tombstone
+  pub fn covers(&self, key: &[u8]) -> bool {
+    self.start.as_slice() <= key && key < self.end.as_slice()
+  }
+}
+
+
+impl MemTableEntry {
+  // Creates an entry setting a key to a value
+  pub fn put(key: &[u8], value: &[u8], timestamp: u128) -> MemTableEntry {
+    MemTableEntry::put_with_meta(key, value, None, timestamp)
+  }
+
+  // Creates an entry setting a key to a value along with metadata
+  pub fn put_with_meta(key: &[u8], value: &[u8], meta: Option<&[u8]>, timestamp: u128) -> MemTableEntry {
+    MemTableEntry {
+      key: key.to_owned(),
+      value: Some(value.to_owned()),
+      timestamp,
+      deleted: false,
+      meta: meta.map(|m| m.to_owned()),
+    }
+  }
+
+  // Creates a tombstone deleting a key, which has no value or metadata
+  pub fn tombstone(key: &[u8], timestamp: u128) -> MemTableEntry {
+    MemTableEntry {
+      key: key.to_owned(),
+      value: None,
+      timestamp,
+      deleted: true,
+      meta: None,
+    }
+  }
+
+  // Gets whether the entry is a put or a tombstone, by whether it is
+  //  deleted, whatever value it holds
+  pub fn kind(&self) -> EntryKind {
+    match self.deleted {
+      true => EntryKind::Tombstone,
+      false => EntryKind::Put,
+    }
+  }
+
+  // Gets the size of the value and metadata held by the entry
+  fn data_len(&self) -> usize {
+    self.value.as_ref().map_or(0, |v| v.len()) + self.meta.as_ref().map_or(0, |m| m.len())
+  }
+}
+
+
+/// IterOptions control which entries of a MemTable are visited by an
+///   iterator.
+///
+/// By default every live entry is visited in key order. Tombstones can be
+///   included (as needed when flushing), the keys can be bounded to the
+///   range `[lower, upper)` and the order can be reversed.
+#[derive(Clone, Debug, Default)]
+pub struct IterOptions {
+  pub include_tombstones: bool,
+  pub lower: Option<Vec<u8>>,
+  pub upper: Option<Vec<u8>>,
+  pub reverse: bool,
+}
+
+
+/// An iterator over the entries of a MemTable in key order, created with
+///   `MemTable::iter_with_options` and its shorthands.
+pub struct MemTableIter<'a> {
+  entries: core::slice::Iter<'a, MemTableEntry>,
+  range_tombstones: &'a [RangeTombstone],
+  include_tombstones: bool,
+  reverse: bool,
 }
 
 
@@ -37,54 +275,184 @@ impl MemTable {
     MemTable {
       entries: Vec::new(),
       size: 0,
+      capacity: Capacity { bytes: usize::MAX, entries: usize::MAX },
+      range_tombstones: Vec::new(),
+      time_bounds: None,
+      stale_writes: StaleWritePolicy::Accept,
+      conflicts: ConflictPolicy::LastWriteWins,
+      by_time: BTreeSet::new(),
     }
   }
 
-  pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) {
-    let entry = MemTableEntry{
-      key: key.to_owned(),
-      value: Some(value.to_owned()),
-      timestamp: timestamp,
-      deleted: false
+  // Sets how writes no newer than the record of their key are handled
+  pub fn with_stale_write_policy(mut self, policy: StaleWritePolicy) -> MemTable {
+    self.stale_writes = policy;
+    self
+  }
+
+  // Sets how writes with the same timestamp as the record of their key are
+  //  handled
+  pub fn with_conflict_policy(mut self, policy: ConflictPolicy) -> MemTable {
+    self.conflicts = policy;
+    self
+  }
+
+  // Creates a new MemTable which accepts records until either the size
+  //  reaches `max_bytes` or the number of records reaches `max_entries`
+  pub fn with_capacity(max_bytes: usize, max_entries: usize) -> MemTable {
+    MemTable {
+      capacity: Capacity { bytes: max_bytes, entries: max_entries },
+      ..MemTable::new()
+    }
+  }
+
+  // Sets the value of a key in the MemTable.
+  //
+  // If the write would take the MemTable beyond its capacity it is not 
+  //  applied and `SetOutcome::Full` is returned, allowing the caller to
+  //  flush the MemTable and retry the write on a new one. A write older
+  //  than a range tombstone covering its key is already deleted, so it is
+  //  dropped and `SetOutcome::Stale` is returned.
+  pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> SetOutcome {
+    self.set_with_meta(key, value, None, timestamp)
+  }
+
+  // Sets the value of a key in the MemTable along with metadata describing
+  //  the write, which is stored alongside the value
+  pub fn set_with_meta(
+    &mut self,
+    key: &[u8],
+    value: &[u8],
+    meta: Option<&[u8]>,
+    timestamp: u128,
+  ) -> SetOutcome {
+    let mut entry = MemTableEntry::put_with_meta(key, value, meta, timestamp);
+    let (index, timestamp) = match self.admit(&entry) {
+      Ok(admitted) => admitted,
+      Err(outcome) => return outcome,
     };
+    entry.timestamp = timestamp;
+    let data_len = entry.data_len();
 
-    match self.get_index(key) {
+    match index {
       Ok(idx) => {
         // If the present entry at the given index contains a value, 
         //  then add differences of new and old value sizes to the MemTable
-        if let Some(curr_val) = self.entries[idx].value.as_ref() {
-          // If the current value is larger this will reduce size 
-          //  by adding a negative value
-          if curr_val.len() > value.len() {
-            self.size -= curr_val.len() - value.len();
-          } else {
-            self.size += value.len() - curr_val.len();
-          }
-        }
+        let curr_len = self.entries[idx].data_len();
+        self.size = self.size - curr_len + data_len;
         // Update the entry at the given location
+        self.reindex(self.entries[idx].timestamp, &entry);
         self.entries[idx] = entry;
+        self.observe_timestamp(timestamp);
+        SetOutcome::Updated
       },
       Err(idx) => {
-        // Increase the size of the MemTable by the size of the:
-        //  key, the value, timestamp and tombstone
-        // The extra size of vectors is not considered here
-        self.size += key.len() + value.len() + 16 + 1;
+        // Increase the size of the MemTable by the size of the record
+        self.size += record_size(key.len(), data_len);
         // Insert an entry into the vector at the given location
+        self.by_time.insert((timestamp, entry.key.clone()));
         self.entries.insert(idx, entry);
+        self.observe_timestamp(timestamp);
+        SetOutcome::Inserted
       }
     }
   }
 
+  // Gets the outcome setting the value of a key would have, without
+  //  changing the MemTable. Inserted or Updated is returned if the write
+  //  would be applied, so it can be checked before the write is logged
+  pub fn would_accept(&self, key: &[u8], value: &[u8], meta: Option<&[u8]>, timestamp: u128) -> SetOutcome {
+    let entry = MemTableEntry::put_with_meta(key, value, meta, timestamp);
+    match self.admit(&entry) {
+      Ok((Ok(_), _)) => SetOutcome::Updated,
+      Ok((Err(_), _)) => SetOutcome::Inserted,
+      Err(outcome) => outcome,
+    }
+  }
+
+  // Checks a write against the range tombstones, the write policies and the
+  //  capacity of the MemTable. Returns the index of its key and the
+  //  timestamp it is applied with, or the outcome of a rejected write
+  fn admit(&self, entry: &MemTableEntry) -> Result<(Result<usize, usize>, u128), SetOutcome> {
+    let key = entry.key.as_slice();
+    if self.range_tombstones.iter().any(|t| t.covers(key) && t.timestamp > entry.timestamp) {
+      return Err(SetOutcome::Stale);
+    }
+    let index = self.get_index(key);
+    let timestamp = self.resolve_timestamp(index, entry).ok_or(SetOutcome::Stale)?;
+    let data_len = entry.data_len();
+    let fits = match index {
+      Ok(idx) => {
+        let curr_len = self.entries[idx].data_len();
+        data_len <= curr_len || self.size + (data_len - curr_len) <= self.capacity.bytes
+      },
+      Err(_) => {
+        let entry_size = record_size(key.len(), data_len);
+        self.size + entry_size <= self.capacity.bytes && self.entries.len() < self.capacity.entries
+      },
+    };
+    match fits {
+      true => Ok((index, timestamp)),
+      false => Err(SetOutcome::Full),
+    }
+  }
+
+  // Sets the value of a key in the MemTable only if its current value is
+  //  `expected`, where None expects the key to be missing or deleted.
+  //
+  // If the value differs the MemTable is left unchanged and None is
+  //  returned, otherwise the outcome of the write
+  pub fn compare_and_set(
+    &mut self,
+    key: &[u8],
+    expected: Option<&[u8]>,
+    value: &[u8],
+    timestamp: u128,
+  ) -> Option<SetOutcome> {
+    match self.value_matches(key, expected) {
+      true => Some(self.set(key, value, timestamp)),
+      false => None,
+    }
+  }
+
+  // Gets whether the current value of a key is `expected`, where None
+  //  matches a key which is missing or deleted
+  pub fn value_matches(&self, key: &[u8], expected: Option<&[u8]>) -> bool {
+    let current = self.get(key).filter(|e| !e.deleted).and_then(|e| e.value.as_deref());
+    current == expected
+  }
+
   // Gets a Key-Value entry from the MemTable.
   //
-  // If no record with the key exists in the MemTable, returns None
+  // If no record with the key exists in the MemTable, or its record is
+  //  older than a range tombstone covering it, returns None
   pub fn get(&self, key: &[u8]) -> Option<&MemTableEntry> {
     if let Ok(idx) = self.get_index(key) {
-      return Some(&self.entries[idx]);
+      let entry = &self.entries[idx];
+      if !is_masked(&self.range_tombstones, entry) {
+        return Some(entry);
+      }
     }
     None
   }
 
+  // Gets whether a key has a live record in the MemTable, has been deleted,
+  //  or has no record, without reading the record
+  pub fn contains_key(&self, key: &[u8]) -> KeyState {
+    if let Ok(idx) = self.get_index(key) {
+      let entry = &self.entries[idx];
+      return match entry.deleted || is_masked(&self.range_tombstones, entry) {
+        true => KeyState::Tombstoned,
+        false => KeyState::Present,
+      };
+    }
+    let in_range = self.range_tombstones.iter().any(|t| t.covers(key));
+    match in_range {
+      true => KeyState::Tombstoned,
+      false => KeyState::Absent,
+    }
+  }
+
   // Performs a scan over the MemTable to find a record by value.
   //
   // If the record with the specified value is found `[Result::Ok]` is 
@@ -92,10 +460,10 @@ impl MemTable {
   // If the record is not found then `[Result:Err]` is returned with 
   //  `usize::MAX`
   pub fn scan(&self, value: &[u8]) -> Option<&MemTableEntry> {
-    for (_index, entry) in self.entries.iter().enumerate() {
+    for entry in self.entries.iter() {
       match &entry.value {
-        Some(curr_val) => if value == curr_val.as_slice() {
-          return Some(&entry);
+        Some(curr_val) => if value == curr_val.as_slice() && !is_masked(&self.range_tombstones, entry) {
+          return Some(entry);
         },
         None => continue
       }
@@ -103,85 +471,697 @@ impl MemTable {
     None
   }
 
-  // Deletes an entry from the MemTable.
+  // Deletes an entry from the MemTable by recording a tombstone.
   //
-  pub fn delete(&mut self, key: &[u8], timestamp: u128) {
-    let entry = MemTableEntry {
-      key: key.to_owned(),
-      value: None,
-      timestamp: timestamp,
-      deleted: true,
+  // Returns `SetOutcome::Inserted` if the key had no record, and
+  //  `SetOutcome::Updated` if its record was replaced. Deletes are accepted
+  //  beyond the capacity of the MemTable
+  pub fn delete(&mut self, key: &[u8], timestamp: u128) -> SetOutcome {
+    let index = self.get_index(key);
+    let mut entry = MemTableEntry::tombstone(key, timestamp);
+    let timestamp = match self.resolve_timestamp(index, &entry) {
+      Some(timestamp) => timestamp,
+      None => return SetOutcome::Stale,
     };
+    entry.timestamp = timestamp;
 
-    match self.get_index(key) {
+    let outcome = match index {
       Ok(idx) => {
         // If the present entry at the given index contains a value, then 
         //  subtract the size of the value from the MemTable size
-        if let Some(curr_val) = self.entries[idx].value.as_ref() {
-          self.size -= curr_val.len();
-        }
+        self.size -= self.entries[idx].data_len();
+        self.reindex(self.entries[idx].timestamp, &entry);
         self.entries[idx] = entry;
+        SetOutcome::Updated
       },
       Err(idx) => {
-        // Increase the size of the MemTable by the size of the:
-        //  key, timestamp and tombstone
-        self.size += key.len() + 16 + 1;
+        // Increase the size of the MemTable by the size of the record,
+        //  which holds no value
+        self.size += record_size(key.len(), 0);
+        self.by_time.insert((timestamp, entry.key.clone()));
         self.entries.insert(idx, entry);
+        SetOutcome::Inserted
+      }
+    };
+    self.observe_timestamp(timestamp);
+    outcome
+  }
+
+  // Deletes every key in the range `[start, end)` from the MemTable.
+  //
+  // Records in the range which are no newer than the deletion are replaced
+  //  by tombstones, and the range itself is kept so that it can mask
+  //  records of the same keys held outside of the MemTable. Records with
+  //  the same timestamp as the deletion are kept if the ConflictPolicy
+  //  keeps them.
+  pub fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128) {
+    self.mask_range(start, end, timestamp, |_| true);
+    self.push_range_tombstone(start, end, timestamp);
+  }
+
+  // Deletes every key starting with a prefix from the MemTable, with a
+  //  single range tombstone over the keys.
+  //
+  // Unlike `delete_range` the records are left as they are and masked as
+  //  they are read, so the delete takes constant time however many keys
+  //  start with the prefix. Masked keys read as deleted, with `get`
+  //  returning None for them, and records with the same timestamp as the
+  //  delete are kept. Returns false, deleting nothing, if the prefix is
+  //  empty or only 0xff bytes, as no range tombstone can end after the keys
+  //  starting with it
+  pub fn delete_prefix(&mut self, prefix: &[u8], timestamp: u128) -> bool {
+    match prefix_end(prefix) {
+      Some(end) => {
+        self.push_range_tombstone(prefix, &end, timestamp);
+        true
+      },
+      None => false,
+    }
+  }
+
+  fn push_range_tombstone(&mut self, start: &[u8], end: &[u8], timestamp: u128) {
+    // Increase the size of the MemTable by the size of the:
+    //  start key, end key and timestamp
+    self.size += start.len() + end.len() + 16;
+    self.range_tombstones.push(RangeTombstone {
+      start: start.to_owned(),
+      end: end.to_owned(),
+      timestamp,
+    });
+    self.observe_timestamp(timestamp);
+  }
+
+  // Merges the records and range tombstones of another MemTable into this
+  //  one, in time linear in the number of records.
+  //
+  // Where both hold a record of a key the newer record is kept, and records
+  //  with the same timestamp are resolved by the ConflictPolicy, with the
+  //  record of the other MemTable as the incoming write. The range
+  //  tombstones of each MemTable mask the older records of the other. The
+  //  merged records are not limited by the capacity of the MemTable
+  pub fn merge_from(&mut self, other: MemTable) {
+    let ours = core::mem::take(&mut self.entries);
+    let mut merged = Vec::with_capacity(ours.len() + other.entries.len());
+    // Whether each merged record came from the other MemTable
+    let mut from_other = Vec::with_capacity(merged.capacity());
+
+    let mut ours = ours.into_iter().peekable();
+    let mut theirs = other.entries.into_iter().peekable();
+    loop {
+      let (entry, is_other) = match (ours.peek(), theirs.peek()) {
+        (None, None) => break,
+        (Some(_), None) => (ours.next().unwrap(), false),
+        (None, Some(_)) => (theirs.next().unwrap(), true),
+        (Some(a), Some(b)) if a.key < b.key => (ours.next().unwrap(), false),
+        (Some(a), Some(b)) if a.key > b.key => (theirs.next().unwrap(), true),
+        (Some(_), Some(_)) => {
+          let (a, b) = (ours.next().unwrap(), theirs.next().unwrap());
+          let keep_ours = a.timestamp > b.timestamp
+            || (a.timestamp == b.timestamp && self.conflicts.keeps_current(&a, &b));
+          if keep_ours { (a, false) } else { (b, true) }
+        },
+      };
+      merged.push(entry);
+      from_other.push(is_other);
+    }
+
+    self.entries = merged;
+    self.recount();
+    if let Some((oldest, newest)) = other.time_bounds {
+      self.observe_timestamp(oldest);
+      self.observe_timestamp(newest);
+    }
+
+    // Records are only masked by the range tombstones of the other MemTable,
+    //  as each MemTable has already applied its own
+    for tombstone in self.range_tombstones.clone() {
+      self.mask_range(&tombstone.start, &tombstone.end, tombstone.timestamp, |idx| from_other[idx]);
+    }
+    for tombstone in other.range_tombstones {
+      self.mask_range(&tombstone.start, &tombstone.end, tombstone.timestamp, |idx| !from_other[idx]);
+      self.size += tombstone.start.len() + tombstone.end.len() + 16;
+      self.range_tombstones.push(tombstone);
+    }
+  }
+
+  // Splits the MemTable at a pivot key, moving the records with keys from
+  //  the pivot onwards into a new MemTable, which is returned.
+  //
+  // Range tombstones are split at the pivot, so each MemTable masks only
+  //  its own keys. The new MemTable has the same capacity and policies, and
+  //  orders later writes after the same timestamp
+  pub fn split_off(&mut self, pivot: &[u8]) -> MemTable {
+    let split_idx = self.entries.partition_point(|e| e.key.as_slice() < pivot);
+    let entries = self.entries.split_off(split_idx);
+
+    let mut range_tombstones = Vec::new();
+    for tombstone in self.range_tombstones.iter_mut() {
+      if tombstone.end.as_slice() <= pivot {
+        continue;
       }
+      range_tombstones.push(RangeTombstone {
+        start: tombstone.start.as_slice().max(pivot).to_owned(),
+        end: tombstone.end.clone(),
+        timestamp: tombstone.timestamp,
+      });
+      tombstone.end = pivot.to_owned();
     }
+    self.range_tombstones.retain(|t| t.start < t.end);
+
+    let mut other = MemTable {
+      entries,
+      range_tombstones,
+      time_bounds: self.time_bounds,
+      ..MemTable::new()
+    };
+    other.capacity = self.capacity;
+    other.stale_writes = self.stale_writes;
+    other.conflicts = self.conflicts;
+    self.recount();
+    other.recount();
+    other
+  }
+
+  // Recomputes the size and time ordering of the records after they are
+  //  moved in bulk
+  fn recount(&mut self) {
+    self.by_time = self.entries.iter().map(|e| (e.timestamp, e.key.clone())).collect();
+    self.size = self.entries.iter().map(|e| record_size(e.key.len(), e.data_len())).sum::<usize>()
+      + self.range_tombstones.iter().map(|t| t.start.len() + t.end.len() + 16).sum::<usize>();
+  }
+
+  // Replaces the records in the range `[start, end)` which are no newer than
+  //  a deletion with tombstones, skipping the records at indexes for which
+  //  `applies` returns false
+  fn mask_range(&mut self, start: &[u8], end: &[u8], timestamp: u128, mut applies: impl FnMut(usize) -> bool) {
+    let start_idx = self.entries.partition_point(|e| e.key.as_slice() < start);
+    let end_idx = self.entries.partition_point(|e| e.key.as_slice() < end);
+
+    let conflicts = self.conflicts;
+    for (idx, entry) in self.entries.iter_mut().enumerate().take(end_idx).skip(start_idx) {
+      if entry.deleted || entry.timestamp > timestamp || !applies(idx) {
+        continue;
+      }
+      if entry.timestamp == timestamp {
+        let tombstone = MemTableEntry::tombstone(&entry.key, timestamp);
+        if conflicts.keeps_current(entry, &tombstone) {
+          continue;
+        }
+      }
+      self.size -= entry.data_len();
+      self.by_time.remove(&(entry.timestamp, entry.key.clone()));
+      self.by_time.insert((timestamp, entry.key.clone()));
+      entry.value = None;
+      entry.meta = None;
+      entry.timestamp = timestamp;
+      entry.deleted = true;
+    }
+  }
+
+  // Gets the key ranges deleted from the MemTable, in the order they were
+  //  deleted
+  pub fn range_tombstones(&self) -> &[RangeTombstone] {
+    &self.range_tombstones
+  }
+
+  // Gets the number of records in the MemTable
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  // Returns true if the MemTable holds no records
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  // Gets the total size of the records in the MemTable, including their
+  //  keys and timestamps held again to order them by time
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  // Gets the maximum size and number of records the MemTable accepts
+  pub fn capacity(&self) -> Capacity {
+    self.capacity
+  }
+
+  // Gets the space left in the MemTable before it reaches its capacity
+  pub fn remaining_capacity(&self) -> Capacity {
+    Capacity {
+      bytes: self.capacity.bytes.saturating_sub(self.size),
+      entries: self.capacity.entries.saturating_sub(self.entries.len()),
+    }
+  }
+
+  // Returns true if the MemTable has reached either its size or record
+  //  capacity and should be flushed
+  pub fn is_full(&self) -> bool {
+    self.size >= self.capacity.bytes || self.entries.len() >= self.capacity.entries
+  }
+
+  // Shrinks the allocations of the MemTable and its records to fit their
+  //  contents, returning the number of bytes released.
+  //
+  // After heavy churn the backing Vectors may hold much more capacity than
+  //  they use, so this can be called when memory is under pressure
+  pub fn compact_memory(&mut self) -> usize {
+    fn shrink<T>(vec: &mut Vec<T>) -> usize {
+      let before = vec.capacity();
+      vec.shrink_to_fit();
+      (before - vec.capacity()) * core::mem::size_of::<T>()
+    }
+
+    let mut released = shrink(&mut self.entries) + shrink(&mut self.range_tombstones);
+    for entry in self.entries.iter_mut() {
+      released += shrink(&mut entry.key);
+      released += entry.value.as_mut().map_or(0, shrink);
+      released += entry.meta.as_mut().map_or(0, shrink);
+    }
+    for tombstone in self.range_tombstones.iter_mut() {
+      released += shrink(&mut tombstone.start) + shrink(&mut tombstone.end);
+    }
+
+    released
+  }
+
+  // Estimates the number of records, including tombstones, with keys in the
+  //  range `[start, end)` and the bytes they hold.
+  //
+  // The number of records is exact, and the bytes are estimated from the
+  //  average size of a record, so the estimate takes logarithmic time
+  pub fn estimate_range(&self, start: &[u8], end: &[u8]) -> RangeEstimate {
+    let entries = self.key_range(start..end).len();
+    let average_size = self.size.checked_div(self.entries.len()).unwrap_or(0);
+    RangeEstimate {
+      entries,
+      bytes: entries * average_size,
+    }
+  }
+
+  // Counts the live records and tombstones in the MemTable and the bytes
+  //  held by their keys, values and metadata
+  pub fn stats(&self) -> MemTableStats {
+    let mut stats = MemTableStats::default();
+    for entry in self.entries.iter() {
+      if entry.deleted || is_masked(&self.range_tombstones, entry) {
+        stats.tombstones += 1;
+      } else {
+        stats.live_entries += 1;
+      }
+      stats.key_bytes += entry.key.len();
+      stats.value_bytes += entry.value.as_ref().map_or(0, |v| v.len());
+      stats.meta_bytes += entry.meta.as_ref().map_or(0, |m| m.len());
+    }
+    stats.average_entry_size = self.size.checked_div(self.entries.len()).unwrap_or(0);
+
+    stats
+  }
+
+  // Iterates over the live entries of the MemTable in key order, skipping
+  //  tombstones
+  pub fn iter(&self) -> MemTableIter<'_> {
+    self.iter_with_options(&IterOptions::new())
+  }
+
+  // Iterates over all the entries of the MemTable in key order, including
+  //  tombstones
+  pub fn iter_with_tombstones(&self) -> MemTableIter<'_> {
+    self.iter_with_options(&IterOptions::new().include_tombstones(true))
+  }
+
+  // Iterates over the entries of the MemTable selected by the options
+  pub fn iter_with_options(&self, options: &IterOptions) -> MemTableIter<'_> {
+    let start = match &options.lower {
+      Some(lower) => self.entries.partition_point(|e| e.key.as_slice() < lower.as_slice()),
+      None => 0,
+    };
+    let end = match &options.upper {
+      Some(upper) => self.entries.partition_point(|e| e.key.as_slice() < upper.as_slice()),
+      None => self.entries.len(),
+    };
+
+    MemTableIter {
+      entries: self.entries[start..end.max(start)].iter(),
+      range_tombstones: &self.range_tombstones,
+      include_tombstones: options.include_tombstones,
+      reverse: options.reverse,
+    }
+  }
+
+  // Folds the live entries whose keys are within a range into a value, in
+  //  key order, skipping tombstones.
+  //
+  // This allows aggregating records, such as counting them or summing
+  //  their values, without building an iterator over them
+  pub fn fold<'k, R, B, F>(&self, range: R, init: B, mut f: F) -> B
+  where
+    R: RangeBounds<&'k [u8]>,
+    F: FnMut(B, &MemTableEntry) -> B,
+  {
+    let mut acc = init;
+    for entry in self.entries[self.key_range(range)].iter() {
+      if !entry.deleted && !is_masked(&self.range_tombstones, entry) {
+        acc = f(acc, entry);
+      }
+    }
+    acc
+  }
+
+  // Calls a function with each live entry whose key is within a range, in
+  //  key order, skipping tombstones
+  pub fn for_each<'k, R, F>(&self, range: R, mut f: F)
+  where
+    R: RangeBounds<&'k [u8]>,
+    F: FnMut(&MemTableEntry),
+  {
+    self.fold(range, (), |_, entry| f(entry))
+  }
+
+  // Iterates over the entries of the MemTable whose timestamps are within a
+  //  range, in the order of their timestamps, including tombstones.
+  //
+  // This answers which keys changed since a time without scanning the
+  //  whole MemTable. Entries with the same timestamp are in key order
+  pub fn iter_by_time<R: RangeBounds<u128>>(&self, range: R) -> impl Iterator<Item = &MemTableEntry> + '_ {
+    let start = match range.start_bound() {
+      Bound::Included(&start) => Some(start),
+      Bound::Excluded(&start) => start.checked_add(1),
+      Bound::Unbounded => Some(0),
+    };
+    let end = range.end_bound().cloned();
+
+    start.into_iter()
+      .flat_map(move |start| self.by_time.range((start, Vec::new())..))
+      .take_while(move |(timestamp, _)| match end {
+        Bound::Included(end) => *timestamp <= end,
+        Bound::Excluded(end) => *timestamp < end,
+        Bound::Unbounded => true,
+      })
+      .map(|(_, key)| &self.entries[self.get_index(key).unwrap()])
+      .filter(|entry| !is_masked(&self.range_tombstones, entry))
+  }
+
+  // Gets the newest timestamp of any write applied to the MemTable, so the
+  //  next write can be ordered after it.
+  //
+  // Returns None if the MemTable is empty
+  pub fn max_timestamp(&self) -> Option<u128> {
+    self.time_bounds.map(|(_, newest)| newest)
+  }
+
+  // Gets the oldest and newest timestamps of any write applied to the
+  //  MemTable, including range deletes, so a flush of it can be skipped by
+  //  queries outside of them.
+  //
+  // The bounds are never narrowed, so they include the timestamps of writes
+  //  which have since been overwritten. Returns None if the MemTable is
+  //  empty
+  pub fn time_bounds(&self) -> Option<(u128, u128)> {
+    self.time_bounds
+  }
+
+  // Gets the timestamp a write to the record at an index is applied with,
+  //  according to the ConflictPolicy and StaleWritePolicy, or None if the
+  //  write is rejected
+  fn resolve_timestamp(&self, index: Result<usize, usize>, incoming: &MemTableEntry) -> Option<u128> {
+    let timestamp = incoming.timestamp;
+    let current = match index {
+      Ok(idx) => &self.entries[idx],
+      Err(_) => return Some(timestamp),
+    };
+    if timestamp == current.timestamp && self.conflicts.keeps_current(current, incoming) {
+      return None;
+    }
+    let current = current.timestamp;
+    match self.stale_writes {
+      StaleWritePolicy::Reject if timestamp <= current => None,
+      StaleWritePolicy::Bump if timestamp <= current => Some(current + 1),
+      _ => Some(timestamp),
+    }
+  }
+
+  // Moves a record replaced by an entry to the entry's timestamp in the
+  //  time ordering
+  fn reindex(&mut self, replaced: u128, entry: &MemTableEntry) {
+    self.by_time.remove(&(replaced, entry.key.clone()));
+    self.by_time.insert((entry.timestamp, entry.key.clone()));
+  }
+
+  // Records the timestamp of a write applied to the MemTable
+  fn observe_timestamp(&mut self, timestamp: u128) {
+    self.time_bounds = Some(match self.time_bounds {
+      Some((oldest, newest)) => (oldest.min(timestamp), newest.max(timestamp)),
+      None => (timestamp, timestamp),
+    });
+  }
+
+  // Creates a cursor over the entries of the MemTable, including 
+  //  tombstones. The cursor is not valid until it is positioned with one of
+  //  the seek methods
+  pub fn cursor(&self) -> MemTableCursor<'_> {
+    MemTableCursor {
+      entries: &self.entries,
+      range_tombstones: &self.range_tombstones,
+      position: None,
+    }
+  }
+
+  // Gets the indexes of the entries whose keys are within a range
+  fn key_range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> core::ops::Range<usize> {
+    let start = match range.start_bound() {
+      Bound::Included(start) => self.entries.partition_point(|e| e.key.as_slice() < *start),
+      Bound::Excluded(start) => self.entries.partition_point(|e| e.key.as_slice() <= *start),
+      Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+      Bound::Included(end) => self.entries.partition_point(|e| e.key.as_slice() <= *end),
+      Bound::Excluded(end) => self.entries.partition_point(|e| e.key.as_slice() < *end),
+      Bound::Unbounded => self.entries.len(),
+    };
+    start..end.max(start)
+  }
+
+  // Gets every record of the MemTable, including tombstones, in key order
+  #[cfg(feature = "std")]
+  pub(crate) fn entries(&self) -> &[MemTableEntry] {
+    &self.entries
+  }
+
+  // Performs binary search over the MemTable to find a record by key
+  //
+  // If the record with the specified key is found `[Result::Ok]` is returned,
+  //   with the index of the record
+  // If the record is not found then `[Result:Err]` is returned, with the index to
+  //  insert the record at.
+  fn get_index(&self, key: &[u8]) -> Result<usize, usize> {
+    self.entries.binary_search_by_key(&key, |entry| entry.key.as_slice())
+  }
+}
+
+impl Default for MemTable {
+  fn default() -> MemTable {
+    MemTable::new()
+  }
+}
+
+/// A MemTableCursor is positioned at an entry of a MemTable and can be
+///   moved in either direction, or repositioned by key.
+///
+/// Tombstones are visited like any other entry so that cursors over several
+///   tables can be merged, while records older than a range tombstone
+///   covering them are skipped. Once moved past either end the cursor is no
+///   longer valid until it is repositioned.
+pub struct MemTableCursor<'a> {
+  entries: &'a [MemTableEntry],
+  range_tombstones: &'a [RangeTombstone],
+  position: Option<usize>,
+}
+
+
+impl<'a> MemTableCursor<'a> {
+  // Returns true if the cursor is positioned at an entry
+  pub fn valid(&self) -> bool {
+    self.position.is_some()
+  }
+
+  // Gets the entry the cursor is positioned at, or None if it is not valid
+  pub fn entry(&self) -> Option<&'a MemTableEntry> {
+    self.position.map(|idx| &self.entries[idx])
+  }
+
+  // Gets the key of the entry the cursor is positioned at, or None if it is
+  //  not valid
+  pub fn key(&self) -> Option<&'a [u8]> {
+    self.entry().map(|e| e.key.as_slice())
+  }
+
+  // Positions the cursor at the first entry
+  pub fn seek_to_first(&mut self) {
+    self.position = self.at(0);
+  }
+
+  // Positions the cursor at the last entry
+  pub fn seek_to_last(&mut self) {
+    self.position = self.back_from(self.entries.len().checked_sub(1));
+  }
+
+  // Positions the cursor at the first entry with a key greater than or
+  //  equal to the key
+  pub fn seek(&mut self, key: &[u8]) {
+    self.position = self.at(self.entries.partition_point(|e| e.key.as_slice() < key));
+  }
+
+  // Positions the cursor at the last entry with a key less than or equal to
+  //  the key
+  pub fn seek_for_prev(&mut self, key: &[u8]) {
+    self.position = self.back_from(self.entries.partition_point(|e| e.key.as_slice() <= key).checked_sub(1));
+  }
+
+  // Moves the cursor to the next entry
+  pub fn next(&mut self) {
+    self.position = self.position.and_then(|idx| self.at(idx + 1));
+  }
+
+  // Moves the cursor to the previous entry
+  pub fn prev(&mut self) {
+    self.position = self.back_from(self.position.and_then(|idx| idx.checked_sub(1)));
+  }
+
+  // Gets the first index from an index onwards whose entry isn't masked
+  fn at(&self, idx: usize) -> Option<usize> {
+    (idx..self.entries.len()).find(|idx| !is_masked(self.range_tombstones, &self.entries[*idx]))
+  }
+
+  // Gets the last index up to an index whose entry isn't masked
+  fn back_from(&self, idx: Option<usize>) -> Option<usize> {
+    (0..=idx?).rev().find(|idx| !is_masked(self.range_tombstones, &self.entries[*idx]))
+  }
+}
+
+
+impl<'a> IntoIterator for &'a MemTable {
+  type IntoIter = MemTableIter<'a>;
+  type Item = &'a MemTableEntry;
+
+  fn into_iter(self) -> MemTableIter<'a> {
+    self.iter()
+  }
+}
+
+
+impl IterOptions {
+  // Creates options visiting every live entry in key order
+  pub fn new() -> IterOptions {
+    IterOptions::default()
   }
 
-  // Gets the number of records in the MemTable
-  pub fn len(&self) -> usize {
-    self.entries.len()
+  // Sets whether deleted entries are visited
+  pub fn include_tombstones(mut self, include_tombstones: bool) -> IterOptions {
+    self.include_tombstones = include_tombstones;
+    self
   }
 
-  // Gets the total size of the records in the MemTable
-  pub fn size(&self) -> usize {
-    self.size
+  // Sets the inclusive lower bound of the keys visited
+  pub fn lower(mut self, lower: &[u8]) -> IterOptions {
+    self.lower = Some(lower.to_owned());
+    self
   }
 
-  // Performs binary search over the MemTable to find a record by key
-  //
-  // If the record with the specified key is found `[Result::Ok]` is returned,
-  //   with the index of the record
-  // If the record is not found then `[Result:Err]` is returned, with the index to
-  //  insert the record at.
-  fn get_index(&self, key: &[u8]) -> Result<usize, usize> {
-    self.entries.binary_search_by_key(&key, |entry| entry.key.as_slice())
+  // Sets the exclusive upper bound of the keys visited
+  pub fn upper(mut self, upper: &[u8]) -> IterOptions {
+    self.upper = Some(upper.to_owned());
+    self
+  }
+
+  // Sets whether the entries are visited in descending key order
+  pub fn reverse(mut self, reverse: bool) -> IterOptions {
+    self.reverse = reverse;
+    self
   }
 }
 
+
+impl<'a> Iterator for MemTableIter<'a> {
+  type Item = &'a MemTableEntry;
+
+  fn next(&mut self) -> Option<&'a MemTableEntry> {
+    loop {
+      let entry = if self.reverse {
+        self.entries.next_back()?
+      } else {
+        self.entries.next()?
+      };
+      if is_masked(self.range_tombstones, entry) {
+        continue;
+      }
+      if self.include_tombstones || !entry.deleted {
+        return Some(entry);
+      }
+    }
+  }
+}
+
+
+// Gets the size of a record with a key and value and metadata of lengths:
+//  the key, value and metadata, timestamp and tombstone, and the key and
+//  timestamp again in the time ordering.
+// The extra size of vectors is not considered here
+fn record_size(key_len: usize, data_len: usize) -> usize {
+  (key_len + 16) * 2 + data_len + 1
+}
+
+
+// Checks whether a record is older than a range tombstone covering it, so
+//  it reads as deleted however the tombstone came to be newer.
+//
+// Records with the same timestamp as a range tombstone are resolved by the
+//  ConflictPolicy when the tombstone is applied, so only older records are
+//  masked here
+pub(crate) fn is_masked(range_tombstones: &[RangeTombstone], entry: &MemTableEntry) -> bool {
+  !entry.deleted && range_tombstones.iter().any(|t| t.timestamp > entry.timestamp && t.covers(&entry.key))
+}
+
+// Gets the smallest key greater than every key starting with a prefix, or
+//  None if there is none because the prefix is empty or all 0xff bytes
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+  let last = prefix.iter().rposition(|b| *b != 0xff)?;
+  let mut end = prefix[..=last].to_vec();
+  end[last] += 1;
+  Some(end)
+}
+
 #[cfg(test)]
 mod tests {
-  use crate::mem_table::MemTable;
+  use core::ops::Bound;
+
+  use crate::mem_table::{prefix_end, Capacity, ConflictPolicy, EntryKind, IterOptions, KeyState, RangeEstimate, MemTable, MemTableEntry, MemTableStats, SetOutcome, StaleWritePolicy};
 
   #[test]
   fn test_mem_table_put_start() {
     let mut table = MemTable::new();
-    table.set(b"Monday", b"Rejoice", 0);       // 13 + 16 + 1
-    table.set(b"Tuesday", b"Celebrate", 10);   // 16 + 16 + 1
+    table.set(b"Monday", b"Rejoice", 0);       // 13 + 16 + 1 + 6 + 16
+    table.set(b"Tuesday", b"Celebrate", 10);   // 16 + 16 + 1 + 7 + 16
     // This one should go at the beginning of the table
-    table.set(b"Friday",  b"Party", 21);       // 11 + 16 + 1
+    table.set(b"Friday",  b"Party", 21);       // 11 + 16 + 1 + 6 + 16
 
     assert_eq!(table.len(), 3);
-    assert_eq!(table.size(), 91);
+    assert_eq!(table.size(), 158);
 
     assert_eq!(table.entries[0].key, b"Friday");
     assert_eq!(table.entries[0].value.as_ref().unwrap(), b"Party");
     assert_eq!(table.entries[0].timestamp, 21);
-    assert_eq!(table.entries[0].deleted, false);
+    assert!(!table.entries[0].deleted);
 
 
     assert_eq!(table.entries[1].key, b"Monday");
     assert_eq!(table.entries[1].value.as_ref().unwrap(), b"Rejoice");
     assert_eq!(table.entries[1].timestamp, 0);
-    assert_eq!(table.entries[1].deleted, false);
+    assert!(!table.entries[1].deleted);
 
     assert_eq!(table.entries[2].key, b"Tuesday");
     assert_eq!(table.entries[2].value.as_ref().unwrap(), b"Celebrate");
     assert_eq!(table.entries[2].timestamp, 10);
-    assert_eq!(table.entries[2].deleted, false);
+    assert!(!table.entries[2].deleted);
   }
 
   #[test]
@@ -194,23 +1174,23 @@ mod tests {
     table.set(b"Monday", b"Rejoice", 0);
 
     assert_eq!(table.len(), 3);
-    assert_eq!(table.size(), 91);
+    assert_eq!(table.size(), 158);
 
     assert_eq!(table.entries[0].key, b"Friday");
     assert_eq!(table.entries[0].value.as_ref().unwrap(), b"Party");
     assert_eq!(table.entries[0].timestamp, 21);
-    assert_eq!(table.entries[0].deleted, false);
+    assert!(!table.entries[0].deleted);
 
 
     assert_eq!(table.entries[1].key, b"Monday");
     assert_eq!(table.entries[1].value.as_ref().unwrap(), b"Rejoice");
     assert_eq!(table.entries[1].timestamp, 0);
-    assert_eq!(table.entries[1].deleted, false);
+    assert!(!table.entries[1].deleted);
 
     assert_eq!(table.entries[2].key, b"Tuesday");
     assert_eq!(table.entries[2].value.as_ref().unwrap(), b"Celebrate");
     assert_eq!(table.entries[2].timestamp, 10);
-    assert_eq!(table.entries[2].deleted, false); 
+    assert!(!table.entries[2].deleted); 
   }
 
   #[test]
@@ -225,7 +1205,7 @@ mod tests {
     assert_eq!(entry.key, b"Monday");
     assert_eq!(entry.value.as_ref().unwrap(), b"Rejoice");
     assert_eq!(entry.timestamp, 0);
-    assert_eq!(entry.deleted, false);
+    assert!(!entry.deleted);
   }
 
   #[test]
@@ -237,7 +1217,7 @@ mod tests {
     table.set(b"Friday", b"Party", 21);
     
     let entry = table.get(b"Thursday");
-    assert_eq!(entry.is_some(), false);
+    assert!(entry.is_none());
   }
 
   #[test]
@@ -252,7 +1232,7 @@ mod tests {
     assert_eq!(entry.key, b"Friday");
     assert_eq!(entry.value.as_ref().unwrap(), b"Party");
     assert_eq!(entry.timestamp, 21);
-    assert_eq!(entry.deleted, false);
+    assert!(!entry.deleted);
   }
 
   #[test]
@@ -264,7 +1244,7 @@ mod tests {
     table.set(b"Friday", b"Party", 21);
 
     let entry = table.scan(b"Blues");
-    assert_eq!(entry.is_some(), false);  
+    assert!(entry.is_none());  
   }
 
   #[test]
@@ -276,22 +1256,22 @@ mod tests {
     table.set(b"Friday", b"Party", 21);
     
     assert_eq!(table.len(), 3);
-    assert_eq!(table.size(), 91);
+    assert_eq!(table.size(), 158);
 
     assert_eq!(table.entries[1].key, b"Monday");
     assert_eq!(table.entries[1].value.as_ref().unwrap(), b"Rejoice");
     assert_eq!(table.entries[1].timestamp, 0);
-    assert_eq!(table.entries[1].deleted, false);
+    assert!(!table.entries[1].deleted);
 
     table.set(b"Monday", b"Blues", 25);
 
     assert_eq!(table.len(), 3);
-    assert_eq!(table.size(), 89);
+    assert_eq!(table.size(), 156);
     
     assert_eq!(table.entries[1].key, b"Monday");
     assert_eq!(table.entries[1].value.as_ref().unwrap(), b"Blues");
     assert_eq!(table.entries[1].timestamp, 25);
-    assert_eq!(table.entries[1].deleted, false);
+    assert!(!table.entries[1].deleted);
   }
 
   #[test]
@@ -304,13 +1284,13 @@ mod tests {
 
     table.delete(b"Monday", 30);
     assert_eq!(table.len(), 3);
-    assert_eq!(table.size(), 84);
+    assert_eq!(table.size(), 151);
 
     let entry = table.get(b"Monday").unwrap();
     assert_eq!(entry.key, b"Monday");
     assert_eq!(entry.value, None);
     assert_eq!(entry.timestamp, 30);
-    assert_eq!(entry.deleted, true);
+    assert!(entry.deleted);
   }
 
   #[test]
@@ -322,16 +1302,627 @@ mod tests {
     table.set(b"Friday", b"Party", 21);
 
     let entry = table.get(b"Thursday");
-    assert_eq!(entry.is_some(), false);
+    assert!(entry.is_none());
 
     table.delete(b"Thursday", 30);
     assert_eq!(table.len(), 4);
-    assert_eq!(table.size(), 116);
+    assert_eq!(table.size(), 207);
 
     let entry = table.get(b"Thursday").unwrap();
     assert_eq!(entry.key, b"Thursday");
     assert_eq!(entry.value, None);
     assert_eq!(entry.timestamp, 30);
-    assert_eq!(entry.deleted, true);
+    assert!(entry.deleted);
+  }
+
+  #[test]
+  fn test_mem_table_iter_skips_tombstones() {
+    let mut table = MemTable::new();
+
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.set(b"Friday", b"Party", 21);
+    table.delete(b"Monday", 30);
+
+    let keys: Vec<&[u8]> = table.iter().map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Friday" as &[u8], b"Tuesday"]);
+
+    let keys: Vec<&[u8]> = table.iter_with_tombstones().map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Friday" as &[u8], b"Monday", b"Tuesday"]);
+  }
+
+  #[test]
+  fn test_mem_table_iter_with_options() {
+    let mut table = MemTable::new();
+
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.set(b"Friday", b"Party", 21);
+    table.set(b"Sunday", b"Rest", 22);
+    table.delete(b"Saturday", 30);
+
+    let options = IterOptions::new().lower(b"Monday").upper(b"Tuesday");
+    let keys: Vec<&[u8]> = table.iter_with_options(&options).map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Monday" as &[u8], b"Sunday"]);
+
+    let options = options.include_tombstones(true).reverse(true);
+    let keys: Vec<&[u8]> = table.iter_with_options(&options).map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Sunday" as &[u8], b"Saturday", b"Monday"]);
+
+    let options = IterOptions::new().lower(b"Zebra").upper(b"Apple");
+    assert_eq!(table.iter_with_options(&options).count(), 0);
+  }
+
+  #[test]
+  fn test_mem_table_set_outcome() {
+    let mut table = MemTable::new();
+
+    assert_eq!(table.set(b"Monday", b"Rejoice", 0), SetOutcome::Inserted);
+    assert_eq!(table.set(b"Monday", b"Blues", 10), SetOutcome::Updated);
+    table.delete(b"Monday", 20);
+    assert_eq!(table.set(b"Monday", b"Rejoice", 30), SetOutcome::Updated);
+
+    assert_eq!(table.len(), 1);
+    assert_eq!(table.size(), 52);
+  }
+
+  #[test]
+  fn test_mem_table_capacity_entries() {
+    let mut table = MemTable::with_capacity(usize::MAX, 2);
+
+    assert_eq!(table.set(b"Monday", b"Rejoice", 0), SetOutcome::Inserted);
+    assert!(!table.is_full());
+    assert_eq!(table.set(b"Tuesday", b"Celebrate", 10), SetOutcome::Inserted);
+    assert!(table.is_full());
+
+    assert_eq!(table.set(b"Friday", b"Party", 21), SetOutcome::Full);
+    assert_eq!(table.set(b"Monday", b"Blues", 25), SetOutcome::Updated);
+    assert_eq!(table.len(), 2);
+    assert!(table.get(b"Friday").is_none());
+  }
+
+  #[test]
+  fn test_mem_table_capacity_bytes() {
+    let mut table = MemTable::with_capacity(104, usize::MAX);
+
+    assert_eq!(table.set(b"Monday", b"Rejoice", 0), SetOutcome::Inserted);
+    assert_eq!(table.remaining_capacity(), Capacity { bytes: 52, entries: usize::MAX - 1 });
+
+    // 11 + 16 + 1 + 6 + 16 fits, 16 + 16 + 1 + 7 + 16 does not
+    assert_eq!(table.set(b"Tuesday", b"Celebrate", 10), SetOutcome::Full);
+    assert_eq!(table.set(b"Monday", b"Rejoice and be glad about it all day long, from dawn until dusk", 10), SetOutcome::Full);
+    assert_eq!(table.set(b"Friday", b"Party", 21), SetOutcome::Inserted);
+
+    assert_eq!(table.size(), 102);
+    assert!(!table.is_full());
+    assert_eq!(table.remaining_capacity().bytes, 2);
+  }
+
+  #[test]
+  fn test_mem_table_delete_range() {
+    let mut table = MemTable::new();
+
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.set(b"Friday", b"Party", 21);
+    table.set(b"Sunday", b"Rest", 40);
+
+    table.delete_range(b"G", b"T", 30);
+    assert_eq!(table.len(), 4);
+    // 207 - 7 + 1 + 1 + 16
+    assert_eq!(table.size(), 218);
+
+    let entry = table.get(b"Monday").unwrap();
+    assert_eq!(entry.value, None);
+    assert_eq!(entry.timestamp, 30);
+    assert!(entry.deleted);
+
+    // Sunday was written after the range was deleted
+    let entry = table.get(b"Sunday").unwrap();
+    assert_eq!(entry.value.as_ref().unwrap(), b"Rest");
+    assert!(!entry.deleted);
+
+    let keys: Vec<&[u8]> = table.iter().map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Friday" as &[u8], b"Sunday", b"Tuesday"]);
+
+    assert_eq!(table.range_tombstones().len(), 1);
+    assert_eq!(table.range_tombstones()[0].start, b"G");
+    assert_eq!(table.range_tombstones()[0].end, b"T");
+    assert_eq!(table.range_tombstones()[0].timestamp, 30);
+  }
+
+  #[test]
+  fn test_range_tombstone_masks_older_writes() {
+    let mut table = MemTable::new();
+    table.delete_range(b"A", b"Z", 30);
+
+    // A write arriving after the range delete but older than it stays deleted
+    assert_eq!(table.set(b"Monday", b"Blues", 20), SetOutcome::Stale);
+    assert!(table.get(b"Monday").is_none());
+    assert_eq!(table.contains_key(b"Monday"), KeyState::Tombstoned);
+    assert_eq!(table.iter().count(), 0);
+    assert_eq!(table.len(), 0);
+
+    // Newer writes, and writes outside the range, are applied
+    assert_eq!(table.set(b"Monday", b"Rejoice", 30), SetOutcome::Inserted);
+    assert_eq!(table.set(b"a", b"Outside", 0), SetOutcome::Inserted);
+    let keys: Vec<&[u8]> = table.iter().map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Monday" as &[u8], b"a"]);
+    assert_eq!(table.contains_key(b"Monday"), KeyState::Present);
+  }
+
+  #[test]
+  fn test_mem_table_max_timestamp() {
+    let mut table = MemTable::with_capacity(usize::MAX, 2);
+    assert_eq!(table.max_timestamp(), None);
+
+    table.set(b"Monday", b"Rejoice", 10);
+    table.delete(b"Tuesday", 30);
+    assert_eq!(table.max_timestamp(), Some(30));
+
+    // Older and rejected writes leave the maximum unchanged
+    table.set(b"Monday", b"Blues", 20);
+    table.set(b"Friday", b"Party", 40);
+    assert_eq!(table.max_timestamp(), Some(30));
+
+    table.delete_range(b"A", b"Z", 50);
+    assert_eq!(table.max_timestamp(), Some(50));
+  }
+
+  #[test]
+  fn test_delete_prefix() {
+    let mut table = MemTable::new();
+    table.set(b"users/Monday", b"Rejoice", 0);
+    table.set(b"users/Tuesday", b"Celebrate", 10);
+    table.set(b"users0", b"Outside", 10);
+    table.set(b"users/Sunday", b"Rest", 40);
+
+    assert!(table.delete_prefix(b"users/", 21));
+    assert_eq!(table.range_tombstones().len(), 1);
+    let keys: Vec<&[u8]> = table.iter().map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"users/Sunday" as &[u8], b"users0"]);
+    assert_eq!(table.contains_key(b"users/Friday"), KeyState::Tombstoned);
+
+    // The records are masked as they are read rather than rewritten
+    assert_eq!(table.len(), 4);
+    assert!(table.get(b"users/Monday").is_none());
+    assert_eq!(table.contains_key(b"users/Tuesday"), KeyState::Tombstoned);
+    assert_eq!(table.iter_with_tombstones().count(), 2);
+    assert_eq!(table.stats().tombstones, 2);
+    let mut cursor = table.cursor();
+    cursor.seek_to_first();
+    assert_eq!(cursor.key(), Some(&b"users/Sunday"[..]));
+    cursor.prev();
+    assert!(!cursor.valid());
+    cursor.seek_for_prev(b"users/Tuesday");
+    assert_eq!(cursor.key(), Some(&b"users/Sunday"[..]));
+    assert_eq!(table.set(b"users/Monday", b"Blues", 15), SetOutcome::Stale);
+
+    assert!(!table.delete_prefix(b"\xff", 30));
+    assert!(!table.delete_prefix(b"", 30));
+    assert_eq!(table.range_tombstones().len(), 1);
+  }
+
+  #[test]
+  fn test_prefix_end() {
+    assert_eq!(prefix_end(b"users/"), Some(b"users0".to_vec()));
+    assert_eq!(prefix_end(b"a\xff\xff"), Some(b"b".to_vec()));
+    assert_eq!(prefix_end(b"\xff"), None);
+    assert_eq!(prefix_end(b""), None);
+  }
+
+  #[test]
+  fn test_time_bounds() {
+    let mut table = MemTable::new();
+    assert_eq!(table.time_bounds(), None);
+
+    table.set(b"Monday", b"Rejoice", 10);
+    assert_eq!(table.time_bounds(), Some((10, 10)));
+    table.delete(b"Tuesday", 5);
+    table.delete_range(b"A", b"B", 30);
+    assert_eq!(table.time_bounds(), Some((5, 30)));
+
+    // Overwritten writes still bound the MemTable
+    table.set(b"Tuesday", b"Celebrate", 21);
+    assert_eq!(table.time_bounds(), Some((5, 30)));
+
+    let mut other = MemTable::new();
+    other.set(b"Friday", b"Party", 2);
+    table.merge_from(other);
+    assert_eq!(table.time_bounds(), Some((2, 30)));
+    assert_eq!(table.split_off(b"Sunday").time_bounds(), Some((2, 30)));
+  }
+
+  #[test]
+  fn test_mem_table_cursor_seek() {
+    let mut table = MemTable::new();
+
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.set(b"Friday", b"Party", 21);
+    table.delete(b"Sunday", 30);
+
+    let mut cursor = table.cursor();
+    assert!(!cursor.valid());
+
+    cursor.seek(b"Saturday");
+    assert_eq!(cursor.key().unwrap(), b"Sunday");
+    assert!(cursor.entry().unwrap().deleted);
+
+    cursor.seek(b"Monday");
+    assert_eq!(cursor.key().unwrap(), b"Monday");
+
+    cursor.seek_for_prev(b"Saturday");
+    assert_eq!(cursor.key().unwrap(), b"Monday");
+
+    cursor.seek_for_prev(b"Tuesday");
+    assert_eq!(cursor.key().unwrap(), b"Tuesday");
+
+    cursor.seek(b"Wednesday");
+    assert!(!cursor.valid());
+
+    cursor.seek_for_prev(b"Apple");
+    assert!(!cursor.valid());
+  }
+
+  #[test]
+  fn test_mem_table_cursor_next_prev() {
+    let mut table = MemTable::new();
+
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.set(b"Friday", b"Party", 21);
+
+    let mut cursor = table.cursor();
+    cursor.seek_to_first();
+    let mut keys: Vec<&[u8]> = Vec::new();
+    while cursor.valid() {
+      keys.push(cursor.key().unwrap());
+      cursor.next();
+    }
+    assert_eq!(keys, vec![b"Friday" as &[u8], b"Monday", b"Tuesday"]);
+
+    cursor.seek_to_last();
+    let mut keys: Vec<&[u8]> = Vec::new();
+    while cursor.valid() {
+      keys.push(cursor.key().unwrap());
+      cursor.prev();
+    }
+    assert_eq!(keys, vec![b"Tuesday" as &[u8], b"Monday", b"Friday"]);
+
+    // Moving an invalid cursor leaves it invalid
+    cursor.next();
+    assert!(!cursor.valid());
+  }
+
+  #[test]
+  fn test_mem_table_set_with_meta() {
+    let mut table = MemTable::new();
+
+    table.set_with_meta(b"Monday", b"Rejoice", Some(b"v1"), 0);   // 15 + 16 + 1 + 6 + 16
+    table.set(b"Tuesday", b"Celebrate", 10);                        // 16 + 16 + 1 + 7 + 16
+    assert_eq!(table.size(), 110);
+
+    let entry = table.get(b"Monday").unwrap();
+    assert_eq!(entry.meta.as_ref().unwrap(), b"v1");
+    assert_eq!(table.get(b"Tuesday").unwrap().meta, None);
+
+    table.set_with_meta(b"Monday", b"Rejoice", Some(b"v2.0"), 20);
+    assert_eq!(table.size(), 112);
+    assert_eq!(table.get(b"Monday").unwrap().meta.as_ref().unwrap(), b"v2.0");
+
+    table.delete(b"Monday", 30);
+    assert_eq!(table.size(), 101);
+    assert_eq!(table.get(b"Monday").unwrap().meta, None);
+  }
+
+  #[test]
+  fn test_stats() {
+    let mut table = MemTable::new();
+    assert_eq!(table.stats(), MemTableStats::default());
+
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set_with_meta(b"Tuesday", b"Celebrate", Some(b"v1"), 10);
+    table.delete(b"Friday", 21);
+    table.delete(b"Monday", 30);
+
+    assert_eq!(table.stats(), MemTableStats {
+      live_entries: 1,
+      tombstones: 2,
+      key_bytes: 19,
+      value_bytes: 9,
+      meta_bytes: 2,
+      average_entry_size: table.size() / 3,
+    });
+  }
+
+  #[test]
+  fn test_stale_write_policy() {
+    let mut table = MemTable::new();
+    table.set(b"Monday", b"Rejoice", 10);
+    assert_eq!(table.set(b"Monday", b"Blues", 5), SetOutcome::Updated);
+    assert_eq!(table.get(b"Monday").unwrap().timestamp, 5);
+
+    let mut table = MemTable::new().with_stale_write_policy(StaleWritePolicy::Reject);
+    table.set(b"Monday", b"Rejoice", 10);
+    assert_eq!(table.set(b"Monday", b"Blues", 10), SetOutcome::Stale);
+    assert_eq!(table.delete(b"Monday", 5), SetOutcome::Stale);
+    assert_eq!(table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Rejoice");
+    assert_eq!(table.set(b"Monday", b"Blues", 11), SetOutcome::Updated);
+    assert_eq!(table.set(b"Tuesday", b"Celebrate", 0), SetOutcome::Inserted);
+
+    let mut table = MemTable::new().with_stale_write_policy(StaleWritePolicy::Bump);
+    table.set(b"Monday", b"Rejoice", 10);
+    assert_eq!(table.set(b"Monday", b"Blues", 5), SetOutcome::Updated);
+    assert_eq!(table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Blues");
+    assert_eq!(table.get(b"Monday").unwrap().timestamp, 11);
+    assert_eq!(table.delete(b"Monday", 11), SetOutcome::Updated);
+    assert_eq!(table.get(b"Monday").unwrap().timestamp, 12);
+    assert_eq!(table.max_timestamp(), Some(12));
+  }
+
+  #[test]
+  fn test_conflict_policy() {
+    let mut table = MemTable::new();
+    table.set(b"Monday", b"Rejoice", 10);
+    assert_eq!(table.set(b"Monday", b"Blues", 10), SetOutcome::Updated);
+    assert_eq!(table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Blues");
+
+    let mut table = MemTable::new().with_conflict_policy(ConflictPolicy::FirstWriteWins);
+    table.set(b"Monday", b"Rejoice", 10);
+    assert_eq!(table.set(b"Monday", b"Blues", 10), SetOutcome::Stale);
+    assert_eq!(table.delete(b"Monday", 10), SetOutcome::Stale);
+    table.delete_range(b"A", b"Z", 10);
+    assert_eq!(table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Rejoice");
+    // Older writes are masked by the range tombstone, whatever the policy
+    assert_eq!(table.set(b"Monday", b"Blues", 5), SetOutcome::Stale);
+
+    // Keep the larger value
+    let larger = |current: &MemTableEntry, incoming: &MemTableEntry| incoming.value > current.value;
+    let mut table = MemTable::new().with_conflict_policy(ConflictPolicy::Custom(larger));
+    table.set(b"Monday", b"Blues", 10);
+    assert_eq!(table.set(b"Monday", b"Rejoice", 10), SetOutcome::Updated);
+    assert_eq!(table.set(b"Monday", b"Party", 10), SetOutcome::Stale);
+    assert_eq!(table.delete(b"Monday", 10), SetOutcome::Stale);
+    assert_eq!(table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Rejoice");
+  }
+
+  #[test]
+  fn test_empty_value() {
+    let mut table = MemTable::new();
+    table.set(b"Monday", b"", 0);
+    table.delete(b"Tuesday", 10);
+
+    let entry = table.get(b"Monday").unwrap();
+    assert_eq!(entry.value, Some(Vec::new()));
+    assert!(!entry.deleted);
+    assert_eq!(table.contains_key(b"Monday"), KeyState::Present);
+    assert_eq!(table.contains_key(b"Tuesday"), KeyState::Tombstoned);
+    assert_eq!(table.iter().count(), 1);
+    assert_eq!(table.size(), 6 + 17 + 6 + 16 + 7 + 17 + 7 + 16);
+  }
+
+  #[test]
+  fn test_compare_and_set() {
+    let mut table = MemTable::new();
+    assert_eq!(table.compare_and_set(b"Monday", None, b"Rejoice", 0), Some(SetOutcome::Inserted));
+    assert_eq!(table.compare_and_set(b"Monday", None, b"Blues", 5), None);
+    assert_eq!(table.compare_and_set(b"Monday", Some(b"Blues"), b"Blues", 5), None);
+    assert_eq!(table.get(b"Monday").unwrap().value.as_deref(), Some(&b"Rejoice"[..]));
+
+    assert_eq!(table.compare_and_set(b"Monday", Some(b"Rejoice"), b"Blues", 5), Some(SetOutcome::Updated));
+    assert_eq!(table.get(b"Monday").unwrap().value.as_deref(), Some(&b"Blues"[..]));
+
+    // Deleted keys, and keys masked by a range delete, match None
+    table.delete(b"Monday", 10);
+    assert!(table.value_matches(b"Monday", None));
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.delete_range(b"T", b"U", 15);
+    assert!(!table.value_matches(b"Tuesday", Some(b"Celebrate")));
+    assert_eq!(table.compare_and_set(b"Tuesday", None, b"Work", 20), Some(SetOutcome::Updated));
+  }
+
+  #[test]
+  fn test_would_accept() {
+    let mut table = MemTable::with_capacity(usize::MAX, 1)
+      .with_stale_write_policy(StaleWritePolicy::Reject);
+    assert_eq!(table.would_accept(b"Monday", b"Rejoice", None, 5), SetOutcome::Inserted);
+    assert!(table.is_empty());
+
+    table.set(b"Monday", b"Rejoice", 5);
+    assert_eq!(table.would_accept(b"Monday", b"Blues", None, 10), SetOutcome::Updated);
+    assert_eq!(table.would_accept(b"Monday", b"Blues", None, 5), SetOutcome::Stale);
+    assert_eq!(table.would_accept(b"Tuesday", b"Blues", None, 10), SetOutcome::Full);
+    table.delete_range(b"M", b"N", 15);
+    assert_eq!(table.would_accept(b"Monday", b"Blues", None, 10), SetOutcome::Stale);
+  }
+
+  #[test]
+  fn test_entry_constructors() {
+    let put = MemTableEntry::put(b"Monday", b"Rejoice", 0);
+    assert_eq!(put.kind(), EntryKind::Put);
+    assert_eq!((put.value.as_deref(), put.deleted, put.meta), (Some(&b"Rejoice"[..]), false, None));
+    let put = MemTableEntry::put_with_meta(b"Monday", b"", Some(b"v2"), 0);
+    assert_eq!(put.kind(), EntryKind::Put);
+    assert_eq!(put.meta.as_deref(), Some(&b"v2"[..]));
+
+    let tombstone = MemTableEntry::tombstone(b"Tuesday", 10);
+    assert_eq!(tombstone.kind(), EntryKind::Tombstone);
+    assert_eq!((tombstone.value, tombstone.meta), (None, None));
+
+    // Entries built by hand are classed by whether they are deleted
+    let entry = MemTableEntry { key: b"Monday".to_vec(), value: None, timestamp: 0, deleted: false, meta: None };
+    assert_eq!(entry.kind(), EntryKind::Put);
+    let entry = MemTableEntry { value: Some(b"Rejoice".to_vec()), deleted: true, ..entry };
+    assert_eq!(entry.kind(), EntryKind::Tombstone);
+
+    let mut table = MemTable::new();
+    table.set(b"Monday", b"Rejoice", 0);
+    table.delete(b"Tuesday", 10);
+    assert_eq!(table.get(b"Monday"), Some(&MemTableEntry::put(b"Monday", b"Rejoice", 0)));
+    assert_eq!(table.get(b"Tuesday"), Some(&MemTableEntry::tombstone(b"Tuesday", 10)));
+  }
+
+  #[test]
+  fn test_contains_key() {
+    let mut table = MemTable::new();
+    table.set(b"Monday", b"Rejoice", 0);
+    table.delete(b"Tuesday", 10);
+    table.delete_range(b"F", b"G", 20);
+    table.set(b"Friday", b"Party", 25);
+
+    assert_eq!(table.contains_key(b"Monday"), KeyState::Present);
+    assert_eq!(table.contains_key(b"Friday"), KeyState::Present);
+    assert_eq!(table.contains_key(b"Tuesday"), KeyState::Tombstoned);
+    assert_eq!(table.contains_key(b"Fun"), KeyState::Tombstoned);
+    assert_eq!(table.contains_key(b"G"), KeyState::Absent);
+    assert_eq!(table.contains_key(b"Sunday"), KeyState::Absent);
+  }
+
+  #[test]
+  fn test_estimate_range() {
+    let mut table = MemTable::new();
+    for i in 0..100u32 {
+      table.set(&i.to_be_bytes(), &[b'x'; 16], i as u128);
+    }
+    table.delete(&50u32.to_be_bytes(), 100);
+
+    let estimate = table.estimate_range(&10u32.to_be_bytes(), &60u32.to_be_bytes());
+    assert_eq!(estimate.entries, 50);
+    assert_eq!(estimate.bytes, 50 * (table.size() / 100));
+    assert_eq!(table.estimate_range(&60u32.to_be_bytes(), &10u32.to_be_bytes()), RangeEstimate::default());
+    assert_eq!(MemTable::new().estimate_range(b"A", b"Z"), RangeEstimate::default());
+  }
+
+  #[test]
+  fn test_merge_from() {
+    let mut table = MemTable::new();
+    table.set(b"Monday", b"Rejoice", 10);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.delete_range(b"S", b"T", 15);
+    table.set(b"Sunday", b"Rest", 15);
+
+    let mut other = MemTable::new();
+    other.set(b"Monday", b"Blues", 20);
+    other.set(b"Tuesday", b"Bored", 5);
+    other.set(b"Saturday", b"Party", 12);
+    other.set(b"Friday", b"Party", 21);
+    other.delete_range(b"T", b"U", 8);
+
+    let mut expected = MemTable::new();
+    expected.delete_range(b"S", b"T", 15);
+    expected.delete_range(b"T", b"U", 8);
+    expected.set(b"Friday", b"Party", 21);
+    expected.set(b"Monday", b"Blues", 20);
+    expected.set(b"Sunday", b"Rest", 15);
+    expected.set(b"Tuesday", b"Celebrate", 10);
+    expected.delete(b"Saturday", 15);
+
+    table.merge_from(other);
+    let entries: Vec<_> = table.iter_with_tombstones().collect();
+    let expected_entries: Vec<_> = expected.iter_with_tombstones().collect();
+    assert_eq!(entries, expected_entries);
+    // A record set after the range deletion of the same MemTable is kept
+    assert_eq!(table.get(b"Sunday").unwrap().value.as_ref().unwrap(), b"Rest");
+    assert!(table.get(b"Saturday").unwrap().deleted);
+    assert_eq!(table.get(b"Tuesday").unwrap().value.as_ref().unwrap(), b"Celebrate");
+    assert_eq!(table.size(), expected.size());
+    assert_eq!(table.max_timestamp(), Some(21));
+    assert_eq!(table.iter_by_time(21..).count(), 1);
+  }
+
+  #[test]
+  fn test_split_off() {
+    let mut table = MemTable::with_capacity(1024, 16);
+    table.set(b"Friday", b"Party", 21);
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set(b"Sunday", b"Rest", 30);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.delete_range(b"A", b"B", 35);
+    table.delete_range(b"N", b"U", 40);
+    let size = table.size();
+
+    let other = table.split_off(b"Saturday");
+    let keys: Vec<&[u8]> = table.iter_with_tombstones().map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Friday" as &[u8], b"Monday"]);
+    let keys: Vec<&[u8]> = other.iter_with_tombstones().map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Sunday" as &[u8], b"Tuesday"]);
+
+    // The range tombstone spanning the pivot is split between the tables
+    let ranges: Vec<(&[u8], &[u8])> = table.range_tombstones().iter()
+      .map(|t| (t.start.as_slice(), t.end.as_slice()))
+      .collect();
+    assert_eq!(ranges, vec![(b"A" as &[u8], b"B" as &[u8]), (b"N", b"Saturday")]);
+    assert_eq!(other.range_tombstones()[0].start, b"Saturday");
+    assert_eq!(other.range_tombstones()[0].end, b"U");
+    // N..U becomes N..Saturday and Saturday..U
+    assert_eq!(table.size() + other.size(), size - 18 + 25 + 25);
+    assert_eq!(other.capacity(), table.capacity());
+    assert_eq!(other.iter_by_time(..).count(), 2);
+  }
+
+  #[test]
+  fn test_compact_memory() {
+    let mut table = MemTable::new();
+    for i in 0..100u32 {
+      table.set(&i.to_be_bytes(), &[b'x'; 64], i as u128);
+    }
+    for i in 0..90u32 {
+      table.delete(&i.to_be_bytes(), 100);
+    }
+
+    assert!(table.compact_memory() > 0);
+    assert_eq!(table.compact_memory(), 0);
+    assert_eq!(table.len(), 100);
+    assert_eq!(table.iter().count(), 10);
+    assert_eq!(table.get(&95u32.to_be_bytes()).unwrap().value.as_ref().unwrap().len(), 64);
+  }
+
+  #[test]
+  fn test_iter_by_time() {
+    let mut table = MemTable::new();
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.set(b"Friday", b"Party", 21);
+    table.set(b"Monday", b"Blues", 25);
+    table.delete(b"Tuesday", 30);
+    table.set(b"Sunday", b"Rest", 30);
+    table.delete_range(b"F", b"G", 40);
+
+    let changes: Vec<(&[u8], u128)> = table.iter_by_time(..)
+      .map(|e| (e.key.as_slice(), e.timestamp))
+      .collect();
+    assert_eq!(changes, vec![
+      (b"Monday" as &[u8], 25),
+      (b"Sunday", 30),
+      (b"Tuesday", 30),
+      (b"Friday", 40),
+    ]);
+
+    let keys: Vec<&[u8]> = table.iter_by_time(26..40).map(|e| e.key.as_slice()).collect();
+    assert_eq!(keys, vec![b"Sunday" as &[u8], b"Tuesday"]);
+    assert_eq!(table.iter_by_time(41..).count(), 0);
+    assert_eq!(table.iter_by_time((Bound::Excluded(u128::MAX), Bound::Unbounded)).count(), 0);
+  }
+
+  #[test]
+  fn test_fold() {
+    let mut table = MemTable::new();
+    table.set(b"Friday", b"Party", 21);
+    table.set(b"Monday", b"Rejoice", 0);
+    table.set(b"Sunday", b"Rest", 30);
+    table.set(b"Tuesday", b"Celebrate", 10);
+    table.delete(b"Saturday", 40);
+
+    assert_eq!(table.fold(.., 0, |count, _| count + 1), 4);
+    let value_bytes = table.fold(b"Monday".as_slice()..b"Tuesday".as_slice(), 0, |sum, e| {
+      sum + e.value.as_ref().unwrap().len()
+    });
+    assert_eq!(value_bytes, 11);
+    assert_eq!(table.fold((Bound::Excluded(b"Sunday".as_slice()), Bound::Unbounded), 0, |n, _| n + 1), 1);
+
+    let mut keys = Vec::new();
+    table.for_each(b"Monday".as_slice()..=b"Sunday".as_slice(), |e| keys.push(e.key.clone()));
+    assert_eq!(keys, vec![b"Monday".to_vec(), b"Sunday".to_vec()]);
   }
 }
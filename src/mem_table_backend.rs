@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::ops::Deref;
+
+use crate::mem_table::{MemTable, MemTableEntry, SetOutcome};
+
+
+/// A MemTableBackend is the sorted in-memory structure holding the records
+///   of a MemTable.
+///
+/// Backends differ in their cost of inserting and looking up records, so
+///   the one best suited to a workload can be chosen when a table is
+///   created with `new_backend`. Any backend can be recovered from a WAL
+///   directory with `WAL::from_dir_into`.
+pub trait MemTableBackend {
+	// Sets the value of a key along with metadata describing the write
+	fn set_with_meta(&mut self, key: &[u8], value: &[u8], meta: Option<&[u8]>, timestamp: u128) -> SetOutcome;
+
+	// Gets the record of a key, which may be a tombstone
+	fn get(&self, key: &[u8]) -> Option<&MemTableEntry>;
+
+	// Deletes a key by recording a tombstone
+	fn delete(&mut self, key: &[u8], timestamp: u128);
+
+	// Deletes every key in the range `[start, end)` whose record is no newer
+	//	than the deletion
+	fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128);
+
+	// Gets the number of records, including tombstones
+	fn len(&self) -> usize;
+
+	// Gets the total size of the records in bytes
+	fn size(&self) -> usize;
+
+	// Iterates over the live records in key order
+	fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a MemTableEntry> + 'a>;
+
+	// Sets the value of a key
+	fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> SetOutcome {
+		self.set_with_meta(key, value, None, timestamp)
+	}
+
+	// Returns true if the backend holds no records
+	fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	// Makes the backend read-only, such as once it is full and waiting to be
+	//	flushed
+	fn freeze(self) -> Frozen<Self> where Self: Sized {
+		Frozen(self)
+	}
+}
+
+
+/// The kinds of MemTableBackend which can be created by `new_backend`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+	// A sorted Vector, with fast lookups and scans but insertions which
+	//	shift the records after them
+	Vec,
+	// A BTreeMap, with logarithmic insertions
+	BTree,
+}
+
+
+/// A Frozen backend can no longer be written to, but can still be read
+///   through its shared methods.
+pub struct Frozen<T: MemTableBackend>(T);
+
+
+/// A MemTable backed by a BTreeMap instead of a sorted Vector.
+///
+/// Records are sized the same way as in a MemTable, less the key and
+///   timestamp a MemTable holds again to order its records by time. Deleted
+///   ranges replace the records within them with tombstones, but aren't
+///   kept themselves.
+pub struct BTreeMemTable {
+	entries: BTreeMap<Vec<u8>, MemTableEntry>,
+	size: usize,
+}
+
+
+// Creates an empty MemTable using the kind of backend
+pub fn new_backend(kind: BackendKind) -> Box<dyn MemTableBackend> {
+	match kind {
+		BackendKind::Vec => Box::new(MemTable::new()),
+		BackendKind::BTree => Box::new(BTreeMemTable::new()),
+	}
+}
+
+
+impl<T: MemTableBackend> Frozen<T> {
+	// Unwraps the Frozen backend, allowing it to be written to again
+	pub fn into_inner(self) -> T {
+		self.0
+	}
+}
+
+impl<T: MemTableBackend> Deref for Frozen<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.0
+	}
+}
+
+
+impl MemTableBackend for MemTable {
+	fn set_with_meta(&mut self, key: &[u8], value: &[u8], meta: Option<&[u8]>, timestamp: u128) -> SetOutcome {
+		MemTable::set_with_meta(self, key, value, meta, timestamp)
+	}
+
+	fn get(&self, key: &[u8]) -> Option<&MemTableEntry> {
+		MemTable::get(self, key)
+	}
+
+	fn delete(&mut self, key: &[u8], timestamp: u128) {
+		MemTable::delete(self, key, timestamp);
+	}
+
+	fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128) {
+		MemTable::delete_range(self, start, end, timestamp)
+	}
+
+	fn len(&self) -> usize {
+		MemTable::len(self)
+	}
+
+	fn size(&self) -> usize {
+		MemTable::size(self)
+	}
+
+	fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a MemTableEntry> + 'a> {
+		Box::new(MemTable::iter(self))
+	}
+}
+
+
+impl BTreeMemTable {
+	// Creates a new BTreeMemTable containing no records
+	pub fn new() -> BTreeMemTable {
+		BTreeMemTable {
+			entries: BTreeMap::new(),
+			size: 0,
+		}
+	}
+}
+
+impl Default for BTreeMemTable {
+	fn default() -> BTreeMemTable {
+		BTreeMemTable::new()
+	}
+}
+
+impl MemTableBackend for BTreeMemTable {
+	fn set_with_meta(&mut self, key: &[u8], value: &[u8], meta: Option<&[u8]>, timestamp: u128) -> SetOutcome {
+		let entry = MemTableEntry::put_with_meta(key, value, meta, timestamp);
+		let data_len = value.len() + meta.map_or(0, |m| m.len());
+
+		match self.entries.insert(key.to_owned(), entry) {
+			Some(old) => {
+				// Replace the size of the old value and metadata with the size of
+				//	the new ones
+				self.size -= data_len_of(&old);
+				self.size += data_len;
+				SetOutcome::Updated
+			},
+			None => {
+				// Increase the size by the size of the key, the value and
+				//	metadata, timestamp and tombstone
+				self.size += key.len() + data_len + 16 + 1;
+				SetOutcome::Inserted
+			}
+		}
+	}
+
+	fn get(&self, key: &[u8]) -> Option<&MemTableEntry> {
+		self.entries.get(key)
+	}
+
+	fn delete(&mut self, key: &[u8], timestamp: u128) {
+		let entry = MemTableEntry::tombstone(key, timestamp);
+
+		match self.entries.insert(key.to_owned(), entry) {
+			Some(old) => self.size -= data_len_of(&old),
+			None => self.size += key.len() + 16 + 1,
+		}
+	}
+
+	fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128) {
+		if start >= end {
+			return;
+		}
+		let range = (Bound::Included(start), Bound::Excluded(end));
+		for entry in self.entries.range_mut::<[u8], _>(range).map(|(_, e)| e) {
+			if entry.deleted || entry.timestamp > timestamp {
+				continue;
+			}
+			self.size -= data_len_of(entry);
+			entry.value = None;
+			entry.meta = None;
+			entry.timestamp = timestamp;
+			entry.deleted = true;
+		}
+	}
+
+	fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	fn size(&self) -> usize {
+		self.size
+	}
+
+	fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a MemTableEntry> + 'a> {
+		Box::new(self.entries.values().filter(|e| !e.deleted))
+	}
+}
+
+
+// Gets the size of the value and metadata held by an entry
+fn data_len_of(entry: &MemTableEntry) -> usize {
+	entry.value.as_ref().map_or(0, |v| v.len()) + entry.meta.as_ref().map_or(0, |m| m.len())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::mem_table::SetOutcome;
+	use crate::mem_table_backend::{new_backend, BackendKind};
+
+	#[test]
+	fn test_backends_agree() {
+		for kind in [BackendKind::Vec, BackendKind::BTree] {
+			let mut table = new_backend(kind);
+			// The size of the keys and timestamps a MemTable orders by time
+			let by_time = |keys: &[&[u8]]| match kind {
+				BackendKind::Vec => keys.iter().map(|key| key.len() + 16).sum::<usize>(),
+				BackendKind::BTree => 0,
+			};
+			let days: [&[u8]; 3] = [b"Monday", b"Tuesday", b"Friday"];
+
+			assert_eq!(table.set(b"Monday", b"Rejoice", 0), SetOutcome::Inserted);
+			assert_eq!(table.set(b"Tuesday", b"Celebrate", 10), SetOutcome::Inserted);
+			assert_eq!(table.set(b"Friday", b"Party", 21), SetOutcome::Inserted);
+			assert_eq!(table.size(), 91 + by_time(&days));
+
+			assert_eq!(table.set(b"Monday", b"Blues", 25), SetOutcome::Updated);
+			assert_eq!(table.size(), 89 + by_time(&days));
+
+			table.delete(b"Tuesday", 30);
+			table.delete(b"Thursday", 30);
+			assert_eq!(table.len(), 4);
+			assert_eq!(table.size(), 105 + by_time(&days) + by_time(&[b"Thursday"]));
+
+			assert_eq!(table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Blues");
+			assert!(table.get(b"Tuesday").unwrap().deleted);
+			assert!(table.get(b"Sunday").is_none());
+
+			let keys: Vec<&[u8]> = table.iter().map(|e| e.key.as_slice()).collect();
+			assert_eq!(keys, vec![b"Friday" as &[u8], b"Monday"]);
+
+			table.set_with_meta(b"Sunday", b"Rest", Some(b"v1"), 40);
+			assert_eq!(table.size(), 134 + by_time(&days) + by_time(&[b"Thursday", b"Sunday"]));
+			table.delete_range(b"M", b"T", 35);
+			assert!(table.get(b"Monday").unwrap().deleted);
+			assert_eq!(table.get(b"Sunday").unwrap().meta.as_ref().unwrap(), b"v1");
+		}
+	}
+}
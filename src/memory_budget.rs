@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+
+// The fraction of the limit which, once used, is reported as pressure
+const DEFAULT_PRESSURE_RATIO: f64 = 0.9;
+
+
+/// A MemoryBudget tracks the memory used by the MemTables and caches of a
+///   process against a limit, calling the registered callbacks when usage
+///   nears it so they can flush MemTables early or shrink caches.
+///
+/// Users charge the budget as they grow and release it as they shrink, such
+///   as by the change in `MemTable::size`. The callbacks are called with the
+///   bytes in use once usage reaches the pressure threshold, 90% of the
+///   limit by default, and again only after it has dropped back below it.
+///   Clones share the same budget.
+#[derive(Clone)]
+pub struct MemoryBudget {
+	inner: Arc<BudgetState>,
+}
+
+
+/// A PressureCallback is called with the bytes in use when a MemoryBudget
+///   comes under pressure. It is called on the thread which charged the
+///   budget, so must not charge the same budget itself.
+pub type PressureCallback = Box<dyn Fn(usize) + Send + Sync>;
+
+
+struct BudgetState {
+	limit: usize,
+	threshold: usize,
+	used: AtomicUsize,
+	// Whether the callbacks have been called since usage last dropped below
+	//	the threshold
+	under_pressure: AtomicBool,
+	callbacks: Mutex<Vec<PressureCallback>>,
+}
+
+
+impl MemoryBudget {
+	pub fn new(limit: usize) -> MemoryBudget {
+		MemoryBudget::with_pressure_ratio(limit, DEFAULT_PRESSURE_RATIO)
+	}
+
+	// Creates a MemoryBudget which reports pressure once the given fraction
+	//	of the limit is used
+	pub fn with_pressure_ratio(limit: usize, ratio: f64) -> MemoryBudget {
+		let threshold = (limit as f64 * ratio.clamp(0.0, 1.0)) as usize;
+		MemoryBudget {
+			inner: Arc::new(BudgetState {
+				limit,
+				threshold,
+				used: AtomicUsize::new(0),
+				under_pressure: AtomicBool::new(false),
+				callbacks: Mutex::new(Vec::new()),
+			}),
+		}
+	}
+
+	// Registers a callback to be called when the budget comes under pressure
+	pub fn on_pressure(&self, callback: PressureCallback) {
+		self.inner.callbacks.lock().unwrap().push(callback);
+	}
+
+	// Records memory being used, returning whether usage is within the
+	//	limit. The memory is charged even if the limit is exceeded
+	pub fn charge(&self, bytes: usize) -> bool {
+		let used = self.inner.used.fetch_add(bytes, Ordering::AcqRel).saturating_add(bytes);
+		if used >= self.inner.threshold && !self.inner.under_pressure.swap(true, Ordering::AcqRel) {
+			for callback in self.inner.callbacks.lock().unwrap().iter() {
+				callback(used);
+			}
+		}
+		used <= self.inner.limit
+	}
+
+	// Records memory being freed
+	pub fn release(&self, bytes: usize) {
+		let used = self.inner.used
+			.fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| Some(used.saturating_sub(bytes)))
+			.unwrap()
+			.saturating_sub(bytes);
+		if used < self.inner.threshold {
+			self.inner.under_pressure.store(false, Ordering::Release);
+		}
+	}
+
+	// Gets the number of bytes in use
+	pub fn used(&self) -> usize {
+		self.inner.used.load(Ordering::Acquire)
+	}
+
+	pub fn limit(&self) -> usize {
+		self.inner.limit
+	}
+
+	// Gets the number of bytes which can be charged before the limit is
+	//	reached
+	pub fn remaining(&self) -> usize {
+		self.inner.limit.saturating_sub(self.used())
+	}
+
+	pub fn is_under_pressure(&self) -> bool {
+		self.used() >= self.inner.threshold
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+
+	use crate::mem_table::MemTable;
+	use crate::memory_budget::MemoryBudget;
+
+	#[test]
+	fn test_memory_budget() {
+		let budget = MemoryBudget::new(1000);
+		let calls = Arc::new(Mutex::new(Vec::new()));
+		let recorded = Arc::clone(&calls);
+		budget.on_pressure(Box::new(move |used| recorded.lock().unwrap().push(used)));
+
+		let mut table = MemTable::new();
+		table.set(b"Monday", b"Rejoice", 0);
+		assert!(budget.charge(table.size()));
+		assert_eq!(budget.used(), 52);
+		assert!(!budget.is_under_pressure());
+
+		// Pressure is only reported once until usage drops below it
+		let shared = budget.clone();
+		assert!(shared.charge(848));
+		assert!(!shared.charge(200));
+		assert_eq!(*calls.lock().unwrap(), vec![900]);
+		assert_eq!(budget.remaining(), 0);
+
+		budget.release(200);
+		budget.release(table.size());
+		assert!(!budget.is_under_pressure());
+		budget.charge(52);
+		assert_eq!(*calls.lock().unwrap(), vec![900, 900]);
+
+		budget.release(usize::MAX);
+		assert_eq!(budget.used(), 0);
+	}
+}
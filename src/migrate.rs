@@ -0,0 +1,190 @@
+// Migrates the WAL files of a directory to a new key encoding, rewriting
+//   each file in the current format so older files are upgraded too.
+//
+// Files are migrated one at a time, streaming their entries into
+//   `<timestamp>.migrating` before it is renamed over the original, so
+//   memory use doesn't grow with the size of the directory. The files
+//   migrated so far are recorded in the `MIGRATION` file, so a migration
+//   interrupted by a crash resumes where it stopped when run again, and no
+//   file has its keys migrated twice. The file is removed once every WAL
+//   file is migrated.
+
+use std::fs::{read_to_string, remove_file, rename};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::utils::{atomic_write, lock_dir, sync_dir};
+use crate::wal::{segment_catalog, WalOptions, WAL};
+use crate::wal_iterator::WALIterator;
+
+
+/// MigrateProgress reports how far a migration has got, after each WAL
+///   file is migrated.
+///
+/// `segments_done` includes the files migrated by an earlier, interrupted
+///   run, while `entries` only counts the entries rewritten by this run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MigrateProgress {
+	pub segments_done: usize,
+	pub segments_total: usize,
+	pub entries: u64,
+}
+
+
+// Rewrites the keys of every entry in the WAL files of a directory with
+//	`migrate_key`, calling `progress` after each file.
+//
+// Range tombstones have their start and end migrated, so the new encoding
+//	must order keys as the old one did for them to cover the same keys. The
+//	directory is locked while it is migrated. A file holding corrupt records
+//	is left as it was and an error of kind `InvalidData` is returned, so it
+//	can be repaired before the migration is resumed
+pub fn migrate_dir<F, P>(dir: &Path, migrate_key: F, mut progress: P) -> io::Result<MigrateProgress>
+where
+	F: Fn(&[u8]) -> Vec<u8>,
+	P: FnMut(&MigrateProgress),
+{
+	let _lock = lock_dir(dir)?;
+	let journal = dir.join("MIGRATION");
+	let mut done = read_journal(&journal)?;
+
+	let segments = segment_catalog(dir)?;
+	let mut report = MigrateProgress { segments_done: 0, segments_total: segments.len(), entries: 0 };
+	for segment in segments.iter() {
+		let migrating = migrating_path(&segment.path);
+		if done.contains(&segment.timestamp) {
+			// The file may have been migrated but not yet renamed
+			if migrating.exists() {
+				rename(&migrating, &segment.path)?;
+				sync_dir(dir)?;
+			}
+			report.segments_done += 1;
+			continue;
+		}
+
+		if migrating.exists() {
+			remove_file(&migrating)?;
+		}
+		report.entries += migrate_segment(&segment.path, &migrating, &migrate_key)?;
+
+		done.push(segment.timestamp);
+		write_journal(&journal, &done)?;
+		rename(&migrating, &segment.path)?;
+		sync_dir(dir)?;
+		report.segments_done += 1;
+		progress(&report);
+	}
+
+	if journal.exists() {
+		remove_file(&journal)?;
+		sync_dir(dir)?;
+	}
+	Ok(report)
+}
+
+
+// Writes the entries of a WAL file to a new file with their keys migrated,
+//	returning the number of entries
+fn migrate_segment<F>(path: &Path, dest: &Path, migrate_key: &F) -> io::Result<u64>
+where
+	F: Fn(&[u8]) -> Vec<u8>,
+{
+	let mut iter = WALIterator::new(path.to_owned())?;
+	let mut wal = WAL::from_path_with_options(dest, WalOptions::default())?;
+	let mut entries = 0;
+	for mut entry in iter.by_ref() {
+		entry.key = migrate_key(&entry.key);
+		entry.range_end = entry.range_end.map(|end| migrate_key(&end));
+		wal.append_entry(&entry)?;
+		entries += 1;
+	}
+	wal.close()?;
+
+	if let Some(e) = iter.take_read_error() {
+		remove_file(dest)?;
+		return Err(e);
+	}
+	if let Some(corruption) = iter.corruptions().first() {
+		remove_file(dest)?;
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("{} is corrupt at offset {}, repair it before migrating", path.display(), corruption.offset),
+		));
+	}
+	Ok(entries)
+}
+
+fn migrating_path(path: &Path) -> PathBuf {
+	path.with_extension("migrating")
+}
+
+// Reads the timestamps of the WAL files already migrated, one per line
+fn read_journal(path: &Path) -> io::Result<Vec<u128>> {
+	let contents = match read_to_string(path) {
+		Ok(contents) => contents,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(e),
+	};
+	contents.lines()
+		.map(|line| line.parse::<u128>().map_err(|_| io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("{} holds an invalid timestamp: {}", path.display(), line),
+		)))
+		.collect()
+}
+
+fn write_journal(path: &Path, done: &[u128]) -> io::Result<()> {
+	let contents: String = done.iter().map(|timestamp| format!("{}\n", timestamp)).collect();
+	atomic_write(path, contents.as_bytes())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{create_dir, read, remove_dir_all, write};
+	use std::path::PathBuf;
+	use rand::Rng;
+
+	use crate::keys::encode_composite;
+	use crate::migrate::{migrate_dir, migrating_path, MigrateProgress};
+	use crate::wal::{segment_catalog, WAL};
+
+	#[test]
+	fn test_migrate_dir() {
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.delete_range(b"A", b"B", 5).unwrap();
+		wal.close().unwrap();
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		wal.close().unwrap();
+		let segments = segment_catalog(&dir).unwrap();
+		assert_eq!(segments.len(), 2);
+
+		// Interrupt the migration after the first file, with a partly
+		//	written file left behind for the second
+		let migrate_key = |key: &[u8]| encode_composite(&[b"v2", key]);
+		let original = read(&segments[1].path).unwrap();
+		migrate_dir(&dir, migrate_key, |_| {}).unwrap();
+		write(&segments[1].path, &original).unwrap();
+		write(dir.join("MIGRATION"), format!("{}\n", segments[0].timestamp)).unwrap();
+		write(migrating_path(&segments[1].path), b"partial").unwrap();
+
+		let mut reports = Vec::new();
+		let report = migrate_dir(&dir, migrate_key, |p| reports.push(*p)).unwrap();
+		assert_eq!(report, MigrateProgress { segments_done: 2, segments_total: 2, entries: 1 });
+		assert_eq!(reports, vec![report]);
+		assert!(!dir.join("MIGRATION").exists());
+		assert!(!migrating_path(&segments[1].path).exists());
+
+		let table = WAL::from_dir_read_only(&dir).unwrap();
+		let keys: Vec<Vec<u8>> = table.iter().map(|e| e.key.clone()).collect();
+		assert_eq!(keys, vec![encode_composite(&[b"v2", b"Monday"]), encode_composite(&[b"v2", b"Tuesday"])]);
+		assert_eq!(table.range_tombstones()[0].start, encode_composite(&[b"v2", b"A"]));
+
+		remove_dir_all(&dir).unwrap();
+	}
+}
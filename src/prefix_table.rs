@@ -0,0 +1,274 @@
+use crate::mem_table::SetOutcome;
+
+
+// The most records held in a block before it is split in two
+const MAX_BLOCK_ENTRIES: usize = 32;
+
+
+/// A PrefixMemTable holds a sorted list of records like a MemTable, but
+///   stores each key as the length of the prefix it shares with the key
+///   before it plus the remaining suffix.
+///
+/// Records are grouped into blocks of at most 32 records. The first key of
+///   each block is stored in full, so a lookup binary searches the blocks
+///   and only decodes the keys of one block.
+///
+/// This cuts the memory used by workloads whose keys share long prefixes,
+///   such as tenant or user ids.
+pub struct PrefixMemTable {
+	blocks: Vec<Block>,
+	len: usize,
+	// The size of the MemTable in units of bytes, counting only the stored
+	//	suffix of each key
+	size: usize,
+}
+
+
+/// A reference to the value of a record in a PrefixMemTable
+#[derive(Debug, PartialEq, Eq)]
+pub struct PrefixEntry<'a> {
+	pub value: Option<&'a [u8]>,
+	pub timestamp: u128,
+	pub deleted: bool,
+}
+
+
+// A run of records whose keys are compressed against the key before them
+struct Block {
+	entries: Vec<CompressedEntry>,
+}
+
+
+struct CompressedEntry {
+	// The number of bytes shared with the key of the previous entry
+	shared: usize,
+	suffix: Vec<u8>,
+	value: Option<Vec<u8>>,
+	timestamp: u128,
+	deleted: bool,
+}
+
+
+impl PrefixMemTable {
+	// Creates a new PrefixMemTable containing no records
+	pub fn new() -> PrefixMemTable {
+		PrefixMemTable {
+			blocks: Vec::new(),
+			len: 0,
+			size: 0,
+		}
+	}
+
+	// Sets the value of a key in the PrefixMemTable
+	pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> SetOutcome {
+		self.write(key, Some(value), timestamp)
+	}
+
+	// Deletes an entry from the PrefixMemTable by recording a tombstone
+	pub fn delete(&mut self, key: &[u8], timestamp: u128) {
+		self.write(key, None, timestamp);
+	}
+
+	// Gets the record of a key from the PrefixMemTable.
+	//
+	// If no record with the key exists in the PrefixMemTable, returns None
+	pub fn get(&self, key: &[u8]) -> Option<PrefixEntry<'_>> {
+		let block = &self.blocks[self.get_block(key)?];
+		let keys = block.keys();
+		let idx = keys.binary_search_by(|k| k.as_slice().cmp(key)).ok()?;
+		Some(block.entries[idx].as_entry())
+	}
+
+	// Iterates over the records of the PrefixMemTable in key order,
+	//	including tombstones, yielding each decoded key with its record
+	pub fn iter(&self) -> impl Iterator<Item = (Vec<u8>, PrefixEntry<'_>)> + '_ {
+		self.blocks.iter().flat_map(|block| {
+			block.keys().into_iter().zip(block.entries.iter().map(|e| e.as_entry()))
+		})
+	}
+
+	// Gets the number of records in the PrefixMemTable
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	// Returns true if the PrefixMemTable holds no records
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	// Gets the total size of the records in the PrefixMemTable
+	pub fn size(&self) -> usize {
+		self.size
+	}
+
+	// Sets or deletes a key, updating the size of the PrefixMemTable
+	fn write(&mut self, key: &[u8], value: Option<&[u8]>, timestamp: u128) -> SetOutcome {
+		let block_idx = match self.get_block(key) {
+			Some(idx) => idx,
+			None if self.blocks.is_empty() => {
+				self.blocks.push(Block { entries: Vec::new() });
+				0
+			},
+			// The key comes before the first key of the table
+			None => 0,
+		};
+
+		let block = &mut self.blocks[block_idx];
+		let mut keys = block.keys();
+		match keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+			Ok(idx) => {
+				// Replace the size of the old value with the size of the new one
+				let entry = &mut block.entries[idx];
+				self.size -= entry.value.as_ref().map_or(0, |v| v.len());
+				self.size += value.map_or(0, |v| v.len());
+				entry.value = value.map(|v| v.to_owned());
+				entry.timestamp = timestamp;
+				entry.deleted = value.is_none();
+				SetOutcome::Updated
+			},
+			Err(idx) => {
+				let size_before = block.size();
+				keys.insert(idx, key.to_owned());
+				block.entries.insert(idx, CompressedEntry {
+					shared: 0,
+					suffix: Vec::new(),
+					value: value.map(|v| v.to_owned()),
+					timestamp,
+					deleted: value.is_none(),
+				});
+				// Only the new entry and the one following it are compressed
+				//	against a different key
+				block.encode_key(idx, &keys);
+				if idx + 1 < keys.len() {
+					block.encode_key(idx + 1, &keys);
+				}
+				self.size = self.size - size_before + block.size();
+				self.len += 1;
+
+				if block.entries.len() > MAX_BLOCK_ENTRIES {
+					let mut tail = Block { entries: block.entries.split_off(MAX_BLOCK_ENTRIES / 2) };
+					// The first key of a block is stored in full
+					let size_before = tail.size();
+					tail.encode_key(0, &keys[MAX_BLOCK_ENTRIES / 2..]);
+					self.size = self.size - size_before + tail.size();
+					self.blocks.insert(block_idx + 1, tail);
+				}
+				SetOutcome::Inserted
+			}
+		}
+	}
+
+	// Finds the block which would hold a key: the last block whose first key
+	//	is less than or equal to it.
+	//
+	// Returns None if the key is before the first key of the table
+	fn get_block(&self, key: &[u8]) -> Option<usize> {
+		let idx = self.blocks.partition_point(|b| b.entries[0].suffix.as_slice() <= key);
+		idx.checked_sub(1)
+	}
+}
+
+impl Default for PrefixMemTable {
+	fn default() -> PrefixMemTable {
+		PrefixMemTable::new()
+	}
+}
+
+
+impl Block {
+	// Decodes the full keys of the entries in the block
+	fn keys(&self) -> Vec<Vec<u8>> {
+		let mut keys: Vec<Vec<u8>> = Vec::with_capacity(self.entries.len());
+		for entry in self.entries.iter() {
+			let mut key = match keys.last() {
+				Some(prev) => prev[..entry.shared].to_vec(),
+				None => Vec::new(),
+			};
+			key.extend_from_slice(&entry.suffix);
+			keys.push(key);
+		}
+		keys
+	}
+
+	// Compresses the key of the entry at an index against the key before it,
+	//	given the full keys of the block
+	fn encode_key(&mut self, idx: usize, keys: &[Vec<u8>]) {
+		let key = &keys[idx];
+		let shared = match idx {
+			0 => 0,
+			_ => key.iter().zip(keys[idx - 1].iter()).take_while(|(a, b)| a == b).count(),
+		};
+		self.entries[idx].shared = shared;
+		self.entries[idx].suffix = key[shared..].to_vec();
+	}
+
+	// Gets the size of the entries in the block, in the same units as a
+	//	MemTable: the key suffix, the value, timestamp and tombstone
+	fn size(&self) -> usize {
+		self.entries.iter()
+			.map(|e| e.suffix.len() + e.value.as_ref().map_or(0, |v| v.len()) + 16 + 1)
+			.sum()
+	}
+}
+
+
+impl CompressedEntry {
+	fn as_entry(&self) -> PrefixEntry<'_> {
+		PrefixEntry {
+			value: self.value.as_deref(),
+			timestamp: self.timestamp,
+			deleted: self.deleted,
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::mem_table::{MemTable, SetOutcome};
+	use crate::prefix_table::PrefixMemTable;
+
+	#[test]
+	fn test_prefix_table_set_get() {
+		let mut table = PrefixMemTable::new();
+
+		assert_eq!(table.set(b"Monday", b"Rejoice", 0), SetOutcome::Inserted);
+		assert_eq!(table.set(b"Tuesday", b"Celebrate", 10), SetOutcome::Inserted);
+		assert_eq!(table.set(b"Friday", b"Party", 21), SetOutcome::Inserted);
+		assert_eq!(table.set(b"Monday", b"Blues", 25), SetOutcome::Updated);
+		table.delete(b"Tuesday", 30);
+
+		assert_eq!(table.len(), 3);
+		assert_eq!(table.get(b"Monday").unwrap().value.unwrap(), b"Blues");
+		assert_eq!(table.get(b"Monday").unwrap().timestamp, 25);
+		assert!(table.get(b"Tuesday").unwrap().deleted);
+		assert_eq!(table.get(b"Tuesday").unwrap().value, None);
+		assert_eq!(table.get(b"Thursday"), None);
+		assert_eq!(table.get(b"Apple"), None);
+	}
+
+	#[test]
+	fn test_prefix_table_order_and_size() {
+		let mut table = PrefixMemTable::new();
+		let mut plain = MemTable::new();
+
+		// Insert keys out of order so blocks are split and re-encoded
+		for i in (0..500).rev().step_by(2).chain((0..500).step_by(2)) {
+			let key = format!("tenant/0001/user/{:05}", i).into_bytes();
+			table.set(&key, b"value", i as u128);
+			plain.set(&key, b"value", i as u128);
+		}
+
+		assert_eq!(table.len(), plain.len());
+		let keys: Vec<Vec<u8>> = table.iter().map(|(k, _)| k).collect();
+		let plain_keys: Vec<Vec<u8>> = plain.iter().map(|e| e.key.clone()).collect();
+		assert_eq!(keys, plain_keys);
+
+		for key in plain_keys.iter() {
+			assert_eq!(table.get(key).unwrap().value.unwrap(), b"value");
+		}
+		// Most keys only store the one or two digits differing from the last key
+		assert!(table.size() * 10 < plain.size() * 6);
+	}
+}
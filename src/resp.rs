@@ -0,0 +1,387 @@
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use crate::clock::{Clock, MonotonicClock};
+use crate::latency::{LatencyHistogram, LatencySummary};
+use crate::mem_table::{IterOptions, MemTable};
+use crate::wal::{WalLatencyReport, WAL};
+
+
+// The largest bulk string and number of arguments accepted in a command,
+//	matching the limits of Redis
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+const MAX_ARGS: usize = 1024 * 1024;
+
+
+/// A RespHandler runs commands sent with the Redis protocol (RESP) against
+///   a MemTable and the WAL its writes are recorded to, so Redis clients
+///   and tools can be pointed at a WAL directory.
+///
+/// GET, SET, DEL, EXISTS, SCAN, INFO, PING and QUIT are supported. SCAN
+///   returns every matching key in one reply, and its MATCH pattern
+///   supports `*` and `?`. INFO replies with the latency report of the
+///   handler, whatever section is asked for. Other commands are answered
+///   with an error.
+pub struct RespHandler {
+	wal: WAL,
+	table: MemTable,
+	clock: MonotonicClock,
+	set_latency: LatencyHistogram,
+	get_latency: LatencyHistogram,
+}
+
+
+/// A RespLatencyReport summarises the time taken by a RespHandler to write
+///   to and read from its MemTable, and to append to and flush its WAL.
+///
+/// `set` covers the writes of SET and DEL to the MemTable, and `get` the
+///   reads of GET.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RespLatencyReport {
+	pub set: LatencySummary,
+	pub get: LatencySummary,
+	pub wal: WalLatencyReport,
+}
+
+
+impl RespHandler {
+	pub fn new(wal: WAL, table: MemTable) -> RespHandler {
+		RespHandler {
+			wal,
+			table,
+			clock: MonotonicClock::new(),
+			set_latency: LatencyHistogram::new(),
+			get_latency: LatencyHistogram::new(),
+		}
+	}
+
+	// Gets the percentiles of the time taken by the MemTable sets and gets,
+	//	and the WAL appends and flushes, since the handler was created
+	pub fn latency_report(&self) -> RespLatencyReport {
+		RespLatencyReport {
+			set: self.set_latency.summary(),
+			get: self.get_latency.summary(),
+			wal: self.wal.latency_report(),
+		}
+	}
+
+	// Unwraps the RespHandler, returning the WAL and MemTable
+	pub fn into_parts(self) -> (WAL, MemTable) {
+		(self.wal, self.table)
+	}
+
+	// Runs a command, writing its reply. Returns false if the connection
+	//	should be closed
+	pub fn execute(&mut self, command: &[Vec<u8>], out: &mut dyn Write) -> io::Result<bool> {
+		let name = command.first().map(|n| n.to_ascii_uppercase()).unwrap_or_default();
+		let args = command.get(1..).unwrap_or_default();
+		match (name.as_slice(), args) {
+			(b"PING", []) => out.write_all(b"+PONG\r\n")?,
+			(b"PING", [message]) => write_bulk(out, Some(message))?,
+			(b"QUIT", []) => {
+				out.write_all(b"+OK\r\n")?;
+				return Ok(false);
+			},
+			(b"GET", [key]) => {
+				let started = Instant::now();
+				let value = self.table.get(key).and_then(|e| e.value.as_deref());
+				self.get_latency.record(started.elapsed());
+				write_bulk(out, value)?;
+			},
+			(b"SET", [key, value]) => {
+				let now = self.clock.now();
+				self.wal.set(key, value, now)?;
+				let started = Instant::now();
+				self.table.set(key, value, now);
+				self.set_latency.record(started.elapsed());
+				out.write_all(b"+OK\r\n")?;
+			},
+			(b"DEL", keys) if !keys.is_empty() => {
+				let now = self.clock.now();
+				let mut deleted = 0;
+				for key in keys {
+					if self.table.get(key).is_some_and(|e| !e.deleted) {
+						self.wal.delete(key, now)?;
+						let started = Instant::now();
+						self.table.delete(key, now);
+						self.set_latency.record(started.elapsed());
+						deleted += 1;
+					}
+				}
+				write!(out, ":{}\r\n", deleted)?;
+			},
+			(b"EXISTS", keys) if !keys.is_empty() => {
+				let found = keys.iter().filter(|k| self.table.get(k).is_some_and(|e| !e.deleted)).count();
+				write!(out, ":{}\r\n", found)?;
+			},
+			(b"INFO", [] | [_]) => {
+				let info = latency_info(&self.latency_report());
+				write_bulk(out, Some(info.as_bytes()))?;
+			},
+			(b"SCAN", [_cursor, options @ ..]) => {
+				let mut pattern: &[u8] = b"*";
+				for option in options.chunks(2) {
+					match (option[0].to_ascii_uppercase().as_slice(), option.get(1)) {
+						(b"MATCH", Some(p)) => pattern = p,
+						(b"COUNT", Some(_)) => {},
+						_ => {
+							out.write_all(b"-ERR syntax error\r\n")?;
+							return Ok(true);
+						},
+					}
+				}
+				let keys: Vec<&[u8]> = self.table.iter_with_options(&IterOptions::new())
+					.map(|e| e.key.as_slice())
+					.filter(|k| glob_match(pattern, k))
+					.collect();
+				write!(out, "*2\r\n$1\r\n0\r\n*{}\r\n", keys.len())?;
+				for key in keys {
+					write_bulk(out, Some(key))?;
+				}
+			},
+			_ => {
+				let name = String::from_utf8_lossy(command.first().map_or(&[][..], |n| n.as_slice()));
+				write!(out, "-ERR unknown command or wrong number of arguments for '{}'\r\n", name)?;
+			},
+		}
+
+		Ok(true)
+	}
+
+	// Runs the commands read from a connection until it is closed.
+	//
+	// The WAL is flushed once every pipelined command has been run, before
+	//	waiting for more
+	fn serve_connection(handler: &Mutex<RespHandler>, stream: TcpStream) -> io::Result<()> {
+		let mut reader = BufReader::new(stream.try_clone()?);
+		let mut writer = io::BufWriter::new(stream);
+		while let Some(command) = read_command(&mut reader)? {
+			let mut handler = handler.lock().unwrap();
+			if !handler.execute(&command, &mut writer)? {
+				break;
+			}
+			if reader.buffer().is_empty() {
+				handler.wal.flush()?;
+				drop(handler);
+				writer.flush()?;
+			}
+		}
+
+		writer.flush()
+	}
+}
+
+
+// Accepts connections from Redis clients, running their commands with the
+//	handler on a thread per connection.
+//
+// This only returns if accepting a connection fails
+pub fn serve(listener: TcpListener, handler: RespHandler) -> io::Result<()> {
+	let handler = Arc::new(Mutex::new(handler));
+	for stream in listener.incoming() {
+		let stream = stream?;
+		let handler = Arc::clone(&handler);
+		thread::spawn(move || RespHandler::serve_connection(&handler, stream));
+	}
+
+	Ok(())
+}
+
+
+// Reads a command sent by a client, as an array of bulk strings or as an
+//	inline command of words separated by spaces.
+//
+// Returns None once the connection is closed between commands
+pub fn read_command<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+	let line = match read_line(reader)? {
+		Some(line) => line,
+		None => return Ok(None),
+	};
+
+	if line.first() != Some(&b'*') {
+		let words = line.split(|b| b.is_ascii_whitespace()).filter(|w| !w.is_empty());
+		return Ok(Some(words.map(|w| w.to_vec()).collect()));
+	}
+
+	let count = parse_len(&line[1..], MAX_ARGS)?;
+	let mut command = Vec::with_capacity(count);
+	for _ in 0..count {
+		let header = read_line(reader)?.ok_or_else(|| protocol_error("connection closed mid command"))?;
+		if header.first() != Some(&b'$') {
+			return Err(protocol_error("expected a bulk string"));
+		}
+		let len = parse_len(&header[1..], MAX_BULK_LEN)?;
+		let mut arg = vec![0; len + 2];
+		reader.read_exact(&mut arg)?;
+		if !arg.ends_with(b"\r\n") {
+			return Err(protocol_error("bulk string is not terminated"));
+		}
+		arg.truncate(len);
+		command.push(arg);
+	}
+
+	Ok(Some(command))
+}
+
+
+// Reads a line terminated by CRLF, without the terminator
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+	let mut line = Vec::new();
+	if reader.read_until(b'\n', &mut line)? == 0 {
+		return Ok(None);
+	}
+	if line.ends_with(b"\r\n") {
+		line.truncate(line.len() - 2);
+	} else if line.ends_with(b"\n") {
+		line.truncate(line.len() - 1);
+	}
+	Ok(Some(line))
+}
+
+fn parse_len(digits: &[u8], max: usize) -> io::Result<usize> {
+	std::str::from_utf8(digits).ok()
+		.and_then(|d| d.parse::<usize>().ok())
+		.filter(|len| *len <= max)
+		.ok_or_else(|| protocol_error("invalid length"))
+}
+
+fn write_bulk(out: &mut dyn Write, value: Option<&[u8]>) -> io::Result<()> {
+	match value {
+		Some(value) => {
+			write!(out, "${}\r\n", value.len())?;
+			out.write_all(value)?;
+			out.write_all(b"\r\n")
+		},
+		None => out.write_all(b"$-1\r\n"),
+	}
+}
+
+// Formats a latency report as the latencystats section of a Redis INFO
+//	reply, with the percentiles in microseconds
+fn latency_info(report: &RespLatencyReport) -> String {
+	let mut info = String::from("# Latencystats\r\n");
+	for (name, summary) in [
+		("set", report.set),
+		("get", report.get),
+		("wal_append", report.wal.append),
+		("wal_flush", report.wal.flush),
+	] {
+		info.push_str(&format!(
+			"latency_percentiles_usec_{}:p50={:.3},p95={:.3},p99={:.3},max={:.3}\r\n",
+			name,
+			summary.p50.as_secs_f64() * 1e6,
+			summary.p95.as_secs_f64() * 1e6,
+			summary.p99.as_secs_f64() * 1e6,
+			summary.max.as_secs_f64() * 1e6,
+		));
+	}
+	info
+}
+
+fn protocol_error(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("RESP protocol error: {}", message))
+}
+
+
+// Matches a key against a pattern where `*` matches any bytes and `?` any
+//	one byte
+fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+	let (mut p, mut k) = (0, 0);
+	// The position after the last `*` and the key position it was tried at
+	let mut backtrack = None;
+	while k < key.len() {
+		match pattern.get(p) {
+			Some(b'*') => {
+				p += 1;
+				backtrack = Some((p, k));
+			},
+			Some(&b) if b == b'?' || b == key[k] => {
+				p += 1;
+				k += 1;
+			},
+			_ => match backtrack {
+				Some((star_p, star_k)) => {
+					p = star_p;
+					k = star_k + 1;
+					backtrack = Some((star_p, star_k + 1));
+				},
+				None => return false,
+			},
+		}
+	}
+	pattern[p.min(pattern.len())..].iter().all(|b| *b == b'*')
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::mem_table::MemTable;
+	use crate::resp::{glob_match, read_command, RespHandler};
+	use crate::wal::{MemWAL, WalOptions};
+
+	// Runs the commands sent by a client, returning the replies
+	fn run(handler: &mut RespHandler, mut input: &[u8]) -> String {
+		let mut out = Vec::new();
+		while let Some(command) = read_command(&mut input).unwrap() {
+			handler.execute(&command, &mut out).unwrap();
+		}
+		String::from_utf8(out).unwrap()
+	}
+
+	#[test]
+	fn test_resp_commands() {
+		let mem_wal = MemWAL::new();
+		let (wal, table) = mem_wal.open(WalOptions::default()).unwrap();
+		let mut handler = RespHandler::new(wal, table);
+
+		let replies = run(&mut handler, b"*3\r\n$3\r\nSET\r\n$6\r\nMonday\r\n$7\r\nRejoice\r\n\
+			*3\r\n$3\r\nset\r\n$7\r\nTuesday\r\n$9\r\nCelebrate\r\n\
+			*2\r\n$3\r\nGET\r\n$6\r\nMonday\r\n\
+			*2\r\n$3\r\nGET\r\n$6\r\nFriday\r\n\
+			DEL Tuesday Friday\r\n\
+			SCAN 0 MATCH *day COUNT 10\r\n\
+			PING\r\n\
+			FLUSHALL\r\n");
+		assert_eq!(replies, "+OK\r\n+OK\r\n\
+			$7\r\nRejoice\r\n\
+			$-1\r\n\
+			:1\r\n\
+			*2\r\n$1\r\n0\r\n*1\r\n$6\r\nMonday\r\n\
+			+PONG\r\n\
+			-ERR unknown command or wrong number of arguments for 'FLUSHALL'\r\n");
+
+		let report = handler.latency_report();
+		assert_eq!((report.set.count, report.get.count, report.wal.append.count), (3, 2, 3));
+		let info = run(&mut handler, b"INFO latencystats\r\n");
+		assert!(info.starts_with("$") && info.contains("\r\n# Latencystats\r\nlatency_percentiles_usec_set:p50="));
+		assert_eq!(info.matches("latency_percentiles_usec_").count(), 4);
+
+		// Writes are recorded to the WAL
+		let (wal, _) = handler.into_parts();
+		wal.close().unwrap();
+		let (_, table): (_, MemTable) = mem_wal.open(WalOptions::default()).unwrap();
+		assert_eq!(table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Rejoice");
+		assert!(table.get(b"Tuesday").unwrap().deleted);
+
+		assert!(read_command(&mut &b"*1\r\n$99\r\nPING\r\n"[..]).is_err());
+	}
+
+	#[test]
+	fn test_glob_match() {
+		assert!(glob_match(b"*", b"Monday"));
+		assert!(glob_match(b"*day", b"Monday"));
+		assert!(glob_match(b"M?nd*y", b"Monday"));
+		assert!(glob_match(b"*o*d*", b"Monday"));
+		assert!(!glob_match(b"*day", b"Mondays"));
+		assert!(!glob_match(b"T*", b"Monday"));
+		assert!(glob_match(b"", b""));
+	}
+}
@@ -0,0 +1,370 @@
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+
+use crate::utils::crc32c;
+use crate::wal::WAL;
+use crate::wal_iterator::WALEntry;
+
+
+// Size of the blocks making up a RocksDB log file
+const BLOCK_SIZE: usize = 32 * 1024;
+// Size of the header of a fragment in a RocksDB log file
+const HEADER_SIZE: usize = 7;
+// Size of the header of a fragment in a recyclable RocksDB log file, which
+//	also holds the log number
+const RECYCLABLE_HEADER_SIZE: usize = 11;
+// Size of the sequence number and count which start a WriteBatch
+const WRITE_BATCH_HEADER_SIZE: usize = 12;
+
+// Types of the records within a WriteBatch
+const TYPE_DELETION: u8 = 0x0;
+const TYPE_VALUE: u8 = 0x1;
+const TYPE_LOG_DATA: u8 = 0x3;
+const TYPE_COLUMN_FAMILY_DELETION: u8 = 0x4;
+const TYPE_COLUMN_FAMILY_VALUE: u8 = 0x5;
+const TYPE_SINGLE_DELETION: u8 = 0x7;
+const TYPE_COLUMN_FAMILY_SINGLE_DELETION: u8 = 0x8;
+const TYPE_NOOP: u8 = 0xD;
+const TYPE_COLUMN_FAMILY_RANGE_DELETION: u8 = 0xE;
+const TYPE_RANGE_DELETION: u8 = 0xF;
+
+
+// Parses the payload of a RocksDB WriteBatch into WAL entries.
+//
+// +--------------------+-----------+-------...-------+
+// | Sequence (8B)      | Count(4B) | Records         |
+// +--------------------+-----------+-------...-------+
+//
+// Each record starts with a type byte followed by varint32 length-prefixed
+//	keys and values. Every put or delete consumes one sequence number, which
+//	is used as the timestamp of its entry.
+//
+// Only records of the default column family are supported, merges and
+//	transaction markers return an `InvalidData` error.
+pub fn parse_write_batch(data: &[u8]) -> io::Result<Vec<WALEntry>> {
+	if data.len() < WRITE_BATCH_HEADER_SIZE {
+		return Err(invalid_data("write batch is shorter than its header"));
+	}
+	let sequence = u64::from_le_bytes(data[0..8].try_into().unwrap());
+	let count = u32::from_le_bytes(data[8..12].try_into().unwrap());
+
+	let mut entries = Vec::new();
+	let mut input = &data[WRITE_BATCH_HEADER_SIZE..];
+	let mut timestamp = sequence as u128;
+	while !input.is_empty() {
+		let tag = input[0];
+		input = &input[1..];
+
+		match tag {
+			TYPE_VALUE | TYPE_COLUMN_FAMILY_VALUE => {
+				if tag == TYPE_COLUMN_FAMILY_VALUE {
+					read_default_column_family(&mut input)?;
+				}
+				let key = read_slice(&mut input)?;
+				let value = read_slice(&mut input)?;
+				entries.push(WALEntry {
+					key: key.to_vec(),
+					value: Some(value.to_vec()),
+					timestamp,
+					deleted: false,
+					range_end: None,
+					meta: None,
+				});
+			},
+			TYPE_DELETION | TYPE_SINGLE_DELETION
+					| TYPE_COLUMN_FAMILY_DELETION | TYPE_COLUMN_FAMILY_SINGLE_DELETION => {
+				if tag == TYPE_COLUMN_FAMILY_DELETION || tag == TYPE_COLUMN_FAMILY_SINGLE_DELETION {
+					read_default_column_family(&mut input)?;
+				}
+				let key = read_slice(&mut input)?;
+				entries.push(WALEntry {
+					key: key.to_vec(),
+					value: None,
+					timestamp,
+					deleted: true,
+					range_end: None,
+					meta: None,
+				});
+			},
+			TYPE_RANGE_DELETION | TYPE_COLUMN_FAMILY_RANGE_DELETION => {
+				if tag == TYPE_COLUMN_FAMILY_RANGE_DELETION {
+					read_default_column_family(&mut input)?;
+				}
+				let start = read_slice(&mut input)?;
+				let end = read_slice(&mut input)?;
+				entries.push(WALEntry {
+					key: start.to_vec(),
+					value: None,
+					timestamp,
+					deleted: true,
+					range_end: Some(end.to_vec()),
+					meta: None,
+				});
+			},
+			TYPE_LOG_DATA => {
+				// Log data is not applied to the database and has no sequence
+				read_slice(&mut input)?;
+				continue;
+			},
+			TYPE_NOOP => continue,
+			_ => return Err(invalid_data(&format!("unsupported write batch record type {}", tag))),
+		}
+		timestamp += 1;
+	}
+
+	if entries.len() != count as usize {
+		return Err(invalid_data("write batch count does not match its records"));
+	}
+	Ok(entries)
+}
+
+// Reads the WriteBatches held in a RocksDB log file and parses them into
+//	WAL entries.
+//
+// The log file is a sequence of 32KB blocks holding checksummed fragments
+//	of records, as in LevelDB. A record which is corrupt or truncated
+//	returns an `InvalidData` error.
+pub fn read_log(path: &Path) -> io::Result<Vec<WALEntry>> {
+	let mut reader = BufReader::new(File::open(path)?);
+	let mut entries = Vec::new();
+
+	let mut block = vec![0; BLOCK_SIZE];
+	let mut record: Vec<u8> = Vec::new();
+	let mut in_record = false;
+	loop {
+		let block_len = read_block(&mut reader, &mut block)?;
+		if block_len == 0 {
+			break;
+		}
+
+		let mut offset = 0;
+		while offset + HEADER_SIZE <= block_len {
+			let header = &block[offset..];
+			let crc = u32::from_le_bytes(header[0..4].try_into().unwrap());
+			let length = u16::from_le_bytes(header[4..6].try_into().unwrap()) as usize;
+			let fragment_type = header[6];
+
+			// A zero type marks the padding at the end of a block
+			if fragment_type == 0 && length == 0 {
+				break;
+			}
+
+			let header_size = if fragment_type >= 5 { RECYCLABLE_HEADER_SIZE } else { HEADER_SIZE };
+			if offset + header_size + length > block_len {
+				return Err(invalid_data("log fragment extends beyond its block"));
+			}
+			let checked = &block[offset + 6..offset + header_size + length];
+			if unmask_crc(crc) != crc32c(checked) {
+				return Err(invalid_data("log fragment checksum mismatch"));
+			}
+			let fragment = &block[offset + header_size..offset + header_size + length];
+			offset += header_size + length;
+
+			// Recyclable fragment types mirror the plain types offset by 4
+			let kind = if fragment_type >= 5 { fragment_type - 4 } else { fragment_type };
+			match kind {
+				1 => {
+					entries.extend(parse_write_batch(fragment)?);
+					in_record = false;
+				},
+				2 => {
+					record.clear();
+					record.extend_from_slice(fragment);
+					in_record = true;
+				},
+				3 | 4 if in_record => {
+					record.extend_from_slice(fragment);
+					if kind == 4 {
+						entries.extend(parse_write_batch(&record)?);
+						in_record = false;
+					}
+				},
+				_ => return Err(invalid_data("unexpected log fragment type")),
+			}
+		}
+	}
+
+	if in_record {
+		return Err(invalid_data("log ends within a record"));
+	}
+	Ok(entries)
+}
+
+// Writes a stream of entries, such as those read from a RocksDB log, to a
+//	WAL at the given path
+pub fn export_to_wal<I: IntoIterator<Item = WALEntry>>(entries: I, path: &Path) -> io::Result<WAL> {
+	let mut wal = WAL::from_path(path)?;
+	for entry in entries {
+		wal.append_entry(&entry)?;
+	}
+	wal.flush()?;
+
+	Ok(wal)
+}
+
+// Reads up to a block of data, returning fewer bytes only at the end of the
+//	file
+fn read_block(reader: &mut BufReader<File>, block: &mut [u8]) -> io::Result<usize> {
+	let mut len = 0;
+	while len < block.len() {
+		match reader.read(&mut block[len..])? {
+			0 => break,
+			n => len += n,
+		}
+	}
+	Ok(len)
+}
+
+// Reads a varint32 encoded integer from the front of the input
+fn read_varint32(input: &mut &[u8]) -> io::Result<u32> {
+	let mut result = 0u32;
+	for shift in (0..35).step_by(7) {
+		let (byte, rest) = input.split_first()
+			.ok_or_else(|| invalid_data("truncated varint"))?;
+		*input = rest;
+		result |= ((byte & 0x7F) as u32) << shift;
+		if byte & 0x80 == 0 {
+			return Ok(result);
+		}
+	}
+	Err(invalid_data("varint is longer than 5 bytes"))
+}
+
+// Reads a varint32 length-prefixed slice from the front of the input
+fn read_slice<'a>(input: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+	let len = read_varint32(input)? as usize;
+	if input.len() < len {
+		return Err(invalid_data("truncated write batch record"));
+	}
+	let (slice, rest) = input.split_at(len);
+	*input = rest;
+	Ok(slice)
+}
+
+// Reads a column family id, returning an error if it is not the default
+//	column family
+fn read_default_column_family(input: &mut &[u8]) -> io::Result<()> {
+	match read_varint32(input)? {
+		0 => Ok(()),
+		id => Err(invalid_data(&format!("unsupported column family {}", id))),
+	}
+}
+
+// Recovers a checksum stored masked in a RocksDB log
+fn unmask_crc(masked: u32) -> u32 {
+	let rot = masked.wrapping_sub(0xa282_ead8);
+	rot.rotate_left(15)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{create_dir, remove_dir_all, File};
+	use std::io::Write;
+	use std::path::PathBuf;
+	use rand::Rng;
+
+	use crate::rocksdb::{export_to_wal, parse_write_batch, read_log, BLOCK_SIZE};
+	use crate::utils::crc32c;
+
+	// Builds a WriteBatch payload of puts and deletes starting at a sequence
+	fn write_batch(sequence: u64, records: &[(&[u8], Option<&[u8]>)]) -> Vec<u8> {
+		let mut batch = Vec::new();
+		batch.extend_from_slice(&sequence.to_le_bytes());
+		batch.extend_from_slice(&(records.len() as u32).to_le_bytes());
+		for (key, value) in records {
+			batch.push(if value.is_some() { 0x1 } else { 0x0 });
+			batch.push(key.len() as u8);
+			batch.extend_from_slice(key);
+			if let Some(value) = value {
+				batch.push(value.len() as u8);
+				batch.extend_from_slice(value);
+			}
+		}
+		batch
+	}
+
+	// Frames a record as a fragment of a RocksDB log
+	fn fragment(fragment_type: u8, data: &[u8]) -> Vec<u8> {
+		let mut checked = vec![fragment_type];
+		checked.extend_from_slice(data);
+		let crc = crc32c(&checked).rotate_right(15).wrapping_add(0xa282_ead8);
+
+		let mut out = Vec::new();
+		out.extend_from_slice(&crc.to_le_bytes());
+		out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+		out.extend_from_slice(&checked);
+		out
+	}
+
+	#[test]
+	fn test_parse_write_batch() {
+		let batch = write_batch(100, &[
+			(b"Monday", Some(b"Rejoice")),
+			(b"Tuesday", None),
+		]);
+
+		let entries = parse_write_batch(&batch).unwrap();
+		assert_eq!(entries.len(), 2);
+		assert_eq!(entries[0].key, b"Monday");
+		assert_eq!(entries[0].value.as_ref().unwrap(), b"Rejoice");
+		assert_eq!(entries[0].timestamp, 100);
+		assert!(!entries[0].deleted);
+		assert_eq!(entries[1].key, b"Tuesday");
+		assert_eq!(entries[1].value, None);
+		assert_eq!(entries[1].timestamp, 101);
+		assert!(entries[1].deleted);
+	}
+
+	#[test]
+	fn test_parse_write_batch_corrupt() {
+		let mut batch = write_batch(100, &[(b"Monday", Some(b"Rejoice"))]);
+		batch.truncate(batch.len() - 1);
+		assert!(parse_write_batch(&batch).is_err());
+
+		let mut batch = write_batch(100, &[(b"Monday", Some(b"Rejoice"))]);
+		batch[12] = 0x2;
+		assert!(parse_write_batch(&batch).is_err());
+	}
+
+	#[test]
+	fn test_read_log_and_export() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		// One small record, then a record split across two blocks
+		let small = write_batch(1, &[(b"Monday", Some(b"Rejoice"))]);
+		let large_value = vec![b'x'; 120];
+		let large_records: Vec<(&[u8], Option<&[u8]>)> = (0..300)
+			.map(|_| (b"Tuesday" as &[u8], Some(large_value.as_slice())))
+			.collect();
+		let large = write_batch(2, &large_records);
+
+		let mut log = fragment(1, &small);
+		let first_len = BLOCK_SIZE - log.len() - 7;
+		log.extend(fragment(2, &large[..first_len]));
+		log.extend(fragment(4, &large[first_len..]));
+		File::create(dir.join("000001.log")).unwrap().write_all(&log).unwrap();
+
+		let entries = read_log(&dir.join("000001.log")).unwrap();
+		assert_eq!(entries.len(), 301);
+		assert_eq!(entries[0].key, b"Monday");
+		assert_eq!(entries[300].timestamp, 301);
+
+		let wal = export_to_wal(entries, &dir.join("1.wal")).unwrap();
+		let exported: Vec<_> = wal.into_iter().collect();
+		assert_eq!(exported.len(), 301);
+		assert_eq!(exported[0].value.as_ref().unwrap(), b"Rejoice");
+		assert_eq!(exported[300].value.as_ref().unwrap(), &large_value);
+
+		remove_dir_all(&dir).unwrap();
+	}
+}
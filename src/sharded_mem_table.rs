@@ -0,0 +1,165 @@
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use crate::mem_table::{is_masked, MemTable, MemTableEntry, SetOutcome};
+use crate::utils::crc32c;
+
+
+/// A ShardedMemTable splits its records across several MemTables, each
+///   behind its own lock, so writers on different threads rarely contend.
+///
+/// Each key is held by the shard chosen by the hash of the key. Range
+///   deletions are applied to every shard, as the keys of a range are
+///   spread across them.
+pub struct ShardedMemTable {
+	shards: Vec<Mutex<MemTable>>,
+}
+
+
+/// An iterator over the live records of a ShardedMemTable in key order,
+///   created with `ShardedMemTable::iter`.
+///
+/// Every shard is locked until the iterator is dropped, so writes wait for
+///   the scan to finish. Records are cloned as they are yielded.
+pub struct ShardedIter<'a> {
+	shards: Vec<MutexGuard<'a, MemTable>>,
+	// The index of the next record of each shard
+	positions: Vec<usize>,
+}
+
+
+impl ShardedMemTable {
+	// Creates a new ShardedMemTable with a number of shards, which is at
+	//	least one
+	pub fn new(shards: usize) -> ShardedMemTable {
+		ShardedMemTable {
+			shards: (0..shards.max(1)).map(|_| Mutex::new(MemTable::new())).collect(),
+		}
+	}
+
+	// Sets the value of a key in its shard
+	pub fn set(&self, key: &[u8], value: &[u8], timestamp: u128) -> SetOutcome {
+		self.shard(key).set(key, value, timestamp)
+	}
+
+	// Sets the value of a key in its shard along with metadata describing
+	//	the write
+	pub fn set_with_meta(&self, key: &[u8], value: &[u8], meta: Option<&[u8]>, timestamp: u128) -> SetOutcome {
+		self.shard(key).set_with_meta(key, value, meta, timestamp)
+	}
+
+	// Gets a copy of the record of a key, which may be a tombstone
+	pub fn get(&self, key: &[u8]) -> Option<MemTableEntry> {
+		self.shard(key).get(key).cloned()
+	}
+
+	// Deletes a key from its shard by recording a tombstone
+	pub fn delete(&self, key: &[u8], timestamp: u128) -> SetOutcome {
+		self.shard(key).delete(key, timestamp)
+	}
+
+	// Deletes every key in the range `[start, end)` from every shard
+	pub fn delete_range(&self, start: &[u8], end: &[u8], timestamp: u128) {
+		for shard in self.shards.iter() {
+			shard.lock().unwrap().delete_range(start, end, timestamp);
+		}
+	}
+
+	// Gets the number of records in every shard, including tombstones
+	pub fn len(&self) -> usize {
+		self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	// Gets the size of every shard in bytes
+	pub fn size(&self) -> usize {
+		self.shards.iter().map(|s| s.lock().unwrap().size()).sum()
+	}
+
+	// Iterates over the live records of every shard in key order, skipping
+	//	tombstones
+	pub fn iter(&self) -> ShardedIter<'_> {
+		// Shards are always locked in order, so concurrent scans can't
+		//	deadlock
+		let shards: Vec<_> = self.shards.iter().map(|s| s.lock().unwrap()).collect();
+		ShardedIter {
+			positions: vec![0; shards.len()],
+			shards,
+		}
+	}
+
+	// Locks the shard holding a key
+	fn shard(&self, key: &[u8]) -> MutexGuard<'_, MemTable> {
+		let idx = crc32c(key) as usize % self.shards.len();
+		self.shards[idx].lock().unwrap()
+	}
+}
+
+
+impl Iterator for ShardedIter<'_> {
+	type Item = MemTableEntry;
+
+	fn next(&mut self) -> Option<MemTableEntry> {
+		// Skip the tombstones and masked records at the head of each shard
+		for (shard, position) in self.shards.iter().zip(self.positions.iter_mut()) {
+			let entries = shard.entries();
+			while entries.get(*position).is_some_and(|e| e.deleted || is_masked(shard.range_tombstones(), e)) {
+				*position += 1;
+			}
+		}
+
+		// A key is only held by one shard, so the smallest head is next
+		let (idx, entry) = self.shards.iter()
+			.zip(self.positions.iter())
+			.enumerate()
+			.filter_map(|(idx, (shard, position))| shard.entries().get(*position).map(|e| (idx, e)))
+			.min_by(|(_, a), (_, b)| a.key.cmp(&b.key))?;
+		let entry = entry.clone();
+		self.positions[idx] += 1;
+		Some(entry)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use std::thread;
+
+	use crate::sharded_mem_table::ShardedMemTable;
+
+	#[test]
+	fn test_sharded_mem_table() {
+		let table = Arc::new(ShardedMemTable::new(4));
+		let writers: Vec<_> = (0..4u32)
+			.map(|writer| {
+				let table = Arc::clone(&table);
+				thread::spawn(move || {
+					for i in 0..100u32 {
+						let key = (writer * 100 + i).to_be_bytes();
+						table.set(&key, b"Rejoice", i as u128);
+					}
+				})
+			})
+			.collect();
+		for writer in writers {
+			writer.join().unwrap();
+		}
+		assert_eq!(table.len(), 400);
+
+		table.delete(&7u32.to_be_bytes(), 200);
+		table.delete_range(&100u32.to_be_bytes(), &300u32.to_be_bytes(), 200);
+		assert!(table.get(&7u32.to_be_bytes()).unwrap().deleted);
+		assert_eq!(table.get(&8u32.to_be_bytes()).unwrap().value.unwrap(), b"Rejoice");
+
+		// Scans are in key order across the shards
+		let keys: Vec<u32> = table.iter()
+			.map(|e| u32::from_be_bytes(e.key.as_slice().try_into().unwrap()))
+			.collect();
+		let expected: Vec<u32> = (0..100).filter(|k| *k != 7).chain(300..400).collect();
+		assert_eq!(keys, expected);
+	}
+}
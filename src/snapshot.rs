@@ -0,0 +1,233 @@
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use crate::format;
+use crate::mem_table::{EntryKind, MemTable};
+use crate::utils::{atomic_write, crc32c};
+
+
+// A snapshot holds the records and range tombstones of a MemTable in a
+//   single file, independent of the WAL format.
+//
+// +-------------+---------------+-------------+-----------------+-----------------------+------------+
+// | Magic (8B)  | Version (4B)  | Count (8B)  | Entry | ...     | Range Count (8B) | ... | CRC (4B)   |
+// +-------------+---------------+-------------+-----------------+-----------------------+------------+
+//
+// Magic = The bytes `MEMTABLE`
+// Version = The version of the format, currently 1
+// Count = The number of entries which follow, including tombstones
+// Range Count = The number of range tombstones which follow the entries
+// CRC = CRC-32C of every byte before it
+//
+// Each entry is stored with flags recording whether it is a tombstone and
+//   which of the value and metadata it holds. Value Size and Meta Size are
+//   only present if the entry holds them.
+//
+// +---------------+------------+-----------------+----------------+-...-+--...--+--...--+-----------------+
+// | Key Size (8B) | Flags (1B) | Value Size (8B) | Meta Size (8B) | Key | Value | Meta  | Timestamp (16B) |
+// +---------------+------------+-----------------+----------------+-...-+--...--+--...--+-----------------+
+//
+// Each range tombstone is stored as its start and end keys
+//
+// +-----------------+---------------+--...--+-...-+-----------------+
+// | Start Size (8B) | End Size (8B) | Start | End | Timestamp (16B) |
+// +-----------------+---------------+--...--+-...-+-----------------+
+const MAGIC: &[u8; 8] = format::SNAPSHOT_MAGIC;
+const VERSION: u32 = format::SNAPSHOT_VERSION;
+
+const DELETED_FLAG: u8 = format::SNAPSHOT_DELETED_FLAG;
+const VALUE_FLAG: u8 = format::SNAPSHOT_VALUE_FLAG;
+const META_FLAG: u8 = format::SNAPSHOT_META_FLAG;
+
+
+impl MemTable {
+	// Writes the records and range tombstones of the MemTable to a snapshot
+	//	file, replacing any file at the path.
+	//
+	// The file is replaced atomically, so a crash leaves either the old or
+	//	the new snapshot
+	pub fn export(&self, path: &Path) -> io::Result<()> {
+		atomic_write(path, &encode_snapshot(self))
+	}
+
+	// Reads a MemTable from a snapshot file written by `export`.
+	//
+	// An error of kind `InvalidData` is returned if the file is not a
+	//	snapshot or fails its checksum
+	pub fn import(path: &Path) -> io::Result<MemTable> {
+		decode_snapshot(&fs::read(path)?)
+	}
+}
+
+
+fn encode_snapshot(mem_table: &MemTable) -> Vec<u8> {
+	let mut buf = Vec::with_capacity(mem_table.size() + 32);
+	buf.extend_from_slice(MAGIC);
+	buf.extend_from_slice(&VERSION.to_le_bytes());
+
+	buf.extend_from_slice(&(mem_table.len() as u64).to_le_bytes());
+	for entry in mem_table.iter_with_tombstones() {
+		let mut flags = 0;
+		if entry.kind() == EntryKind::Tombstone {
+			flags |= DELETED_FLAG;
+		}
+		if entry.value.is_some() {
+			flags |= VALUE_FLAG;
+		}
+		if entry.meta.is_some() {
+			flags |= META_FLAG;
+		}
+
+		buf.extend_from_slice(&(entry.key.len() as u64).to_le_bytes());
+		buf.push(flags);
+		if let Some(value) = entry.value.as_ref() {
+			buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+		}
+		if let Some(meta) = entry.meta.as_ref() {
+			buf.extend_from_slice(&(meta.len() as u64).to_le_bytes());
+		}
+		buf.extend_from_slice(&entry.key);
+		buf.extend_from_slice(entry.value.as_deref().unwrap_or_default());
+		buf.extend_from_slice(entry.meta.as_deref().unwrap_or_default());
+		buf.extend_from_slice(&entry.timestamp.to_le_bytes());
+	}
+
+	let range_tombstones = mem_table.range_tombstones();
+	buf.extend_from_slice(&(range_tombstones.len() as u64).to_le_bytes());
+	for tombstone in range_tombstones {
+		buf.extend_from_slice(&(tombstone.start.len() as u64).to_le_bytes());
+		buf.extend_from_slice(&(tombstone.end.len() as u64).to_le_bytes());
+		buf.extend_from_slice(&tombstone.start);
+		buf.extend_from_slice(&tombstone.end);
+		buf.extend_from_slice(&tombstone.timestamp.to_le_bytes());
+	}
+
+	let crc = crc32c(&buf);
+	buf.extend_from_slice(&crc.to_le_bytes());
+	buf
+}
+
+
+fn decode_snapshot(data: &[u8]) -> io::Result<MemTable> {
+	if data.len() < MAGIC.len() + 4 + 4 || &data[..MAGIC.len()] != MAGIC {
+		return Err(invalid_data("not a MemTable snapshot"));
+	}
+	let (body, crc) = data.split_at(data.len() - 4);
+	if crc32c(body) != u32::from_le_bytes(crc.try_into().unwrap()) {
+		return Err(invalid_data("snapshot checksum mismatch"));
+	}
+
+	let mut reader = &body[MAGIC.len()..];
+	let version = u32::from_le_bytes(read_array(&mut reader)?);
+	if version != VERSION {
+		return Err(invalid_data(&format!("unsupported snapshot version {}", version)));
+	}
+
+	let mut entries = Vec::new();
+	for _ in 0..read_len(&mut reader)? {
+		let key_len = read_len(&mut reader)?;
+		let flags = read_array::<1>(&mut reader)?[0];
+		let value_len = match flags & VALUE_FLAG {
+			0 => None,
+			_ => Some(read_len(&mut reader)?),
+		};
+		let meta_len = match flags & META_FLAG {
+			0 => None,
+			_ => Some(read_len(&mut reader)?),
+		};
+		let key = read_bytes(&mut reader, key_len)?;
+		let value = value_len.map(|len| read_bytes(&mut reader, len)).transpose()?;
+		let meta = meta_len.map(|len| read_bytes(&mut reader, len)).transpose()?;
+		let timestamp = u128::from_le_bytes(read_array(&mut reader)?);
+		entries.push((key, value, meta, timestamp, flags & DELETED_FLAG != 0));
+	}
+
+	// Range tombstones are applied first, as applying them after the entries
+	//	would delete entries which were set after them with older timestamps
+	let mut mem_table = MemTable::new();
+	for _ in 0..read_len(&mut reader)? {
+		let start_len = read_len(&mut reader)?;
+		let end_len = read_len(&mut reader)?;
+		let start = read_bytes(&mut reader, start_len)?;
+		let end = read_bytes(&mut reader, end_len)?;
+		let timestamp = u128::from_le_bytes(read_array(&mut reader)?);
+		mem_table.delete_range(&start, &end, timestamp);
+	}
+	for (key, value, meta, timestamp, deleted) in entries {
+		match value {
+			Some(value) if !deleted => {
+				mem_table.set_with_meta(&key, &value, meta.as_deref(), timestamp);
+			},
+			_ => {
+				mem_table.delete(&key, timestamp);
+			},
+		}
+	}
+
+	Ok(mem_table)
+}
+
+
+fn read_array<const N: usize>(reader: &mut &[u8]) -> io::Result<[u8; N]> {
+	let mut buf = [0; N];
+	reader.read_exact(&mut buf).map_err(|_| invalid_data("snapshot is truncated"))?;
+	Ok(buf)
+}
+
+fn read_len(reader: &mut &[u8]) -> io::Result<usize> {
+	Ok(u64::from_le_bytes(read_array(reader)?) as usize)
+}
+
+fn read_bytes(reader: &mut &[u8], len: usize) -> io::Result<Vec<u8>> {
+	if reader.len() < len {
+		return Err(invalid_data("snapshot is truncated"));
+	}
+	let (bytes, rest) = reader.split_at(len);
+	*reader = rest;
+	Ok(bytes.to_vec())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{read, remove_file, write};
+	use std::io::ErrorKind;
+	use std::path::PathBuf;
+	use rand::Rng;
+
+	use crate::mem_table::MemTable;
+
+	#[test]
+	fn test_snapshot_round_trip() {
+		let mut rng = rand::thread_rng();
+		let path = PathBuf::from(format!("./{}.snapshot", rng.gen::<u32>()));
+
+		let mut table = MemTable::new();
+		table.set(b"Monday", b"Rejoice", 0);
+		table.set_with_meta(b"Tuesday", b"Celebrate", Some(b"v1"), 10);
+		table.delete(b"Friday", 21);
+		table.delete_range(b"A", b"B", 25);
+		table.export(&path).unwrap();
+
+		let imported = MemTable::import(&path).unwrap();
+		let entries: Vec<_> = table.iter_with_tombstones().collect();
+		let imported_entries: Vec<_> = imported.iter_with_tombstones().collect();
+		assert_eq!(entries, imported_entries);
+		assert_eq!(imported.range_tombstones(), table.range_tombstones());
+		assert_eq!(imported.max_timestamp(), Some(25));
+
+		// Any change to the file is caught by the checksum
+		let mut data = read(&path).unwrap();
+		data[20] ^= 0xFF;
+		write(&path, &data).unwrap();
+		assert_eq!(MemTable::import(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+
+		remove_file(&path).unwrap();
+	}
+}
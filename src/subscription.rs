@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::mem_table::prefix_end;
+use crate::wal_iterator::WALEntry;
+
+
+/// A ChangeEvent describes a write recorded to a WAL, delivered to the
+///   subscribers to each prefix of its key once the write is durable.
+///
+/// `sequence` is the sequence number of the write in the WAL. A range
+///   delete is delivered to the subscribers to every prefix whose keys it
+///   may delete.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeEvent {
+	pub sequence: u64,
+	pub entry: WALEntry,
+}
+
+
+// The subscribers to the writes to key prefixes, and the writes waiting to
+//	be durable before they're delivered
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+	subscribers: Vec<(Vec<u8>, Sender<ChangeEvent>)>,
+	pending: VecDeque<ChangeEvent>,
+}
+
+
+impl Subscriptions {
+	// Subscribes to the writes to keys starting with a prefix, which is
+	//	empty to subscribe to every write
+	pub(crate) fn subscribe(&mut self, prefix: &[u8]) -> Receiver<ChangeEvent> {
+		let (sender, receiver) = channel();
+		self.subscribers.push((prefix.to_owned(), sender));
+		receiver
+	}
+
+	pub(crate) fn has_subscribers(&self) -> bool {
+		!self.subscribers.is_empty()
+	}
+
+	// Holds a write until it is durable
+	pub(crate) fn push(&mut self, event: ChangeEvent) {
+		self.pending.push_back(event);
+	}
+
+	// Delivers the writes up to a sequence number, which have become
+	//	durable. Subscribers which have dropped their receiver are removed
+	pub(crate) fn publish(&mut self, durable: u64) {
+		while self.pending.front().is_some_and(|e| e.sequence <= durable) {
+			let event = self.pending.pop_front().unwrap();
+			self.subscribers.retain(|(prefix, sender)| {
+				!matches(prefix, &event.entry) || sender.send(event.clone()).is_ok()
+			});
+		}
+		if self.subscribers.is_empty() {
+			self.pending.clear();
+		}
+	}
+}
+
+
+// Gets whether an entry writes to a key starting with a prefix. A range
+//	delete matches if its range overlaps the keys starting with the prefix
+fn matches(prefix: &[u8], entry: &WALEntry) -> bool {
+	match entry.range_end.as_ref() {
+		Some(end) => {
+			let below_end = match prefix_end(prefix) {
+				Some(prefix_end) => entry.key < prefix_end,
+				None => true,
+			};
+			below_end && end.as_slice() > prefix
+		},
+		None => entry.key.starts_with(prefix),
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::subscription::{ChangeEvent, Subscriptions};
+	use crate::wal_iterator::WALEntry;
+
+	fn entry(key: &[u8], range_end: Option<&[u8]>) -> WALEntry {
+		WALEntry {
+			key: key.to_vec(),
+			value: None,
+			timestamp: 0,
+			deleted: true,
+			range_end: range_end.map(|end| end.to_vec()),
+			meta: None,
+		}
+	}
+
+	#[test]
+	fn test_subscriptions() {
+		let mut subscriptions = Subscriptions::default();
+		let users = subscriptions.subscribe(b"users/");
+		let all = subscriptions.subscribe(b"");
+
+		let events: Vec<ChangeEvent> = [
+			entry(b"users/Monday", None),
+			entry(b"orders/Monday", None),
+			entry(b"users", Some(b"users/A")),
+			entry(b"orders", Some(b"users")),
+		].into_iter().enumerate().map(|(i, entry)| ChangeEvent { sequence: i as u64 + 1, entry }).collect();
+		for event in events.iter() {
+			subscriptions.push(event.clone());
+		}
+
+		// Writes are only delivered once durable
+		subscriptions.publish(2);
+		assert_eq!(users.try_iter().collect::<Vec<_>>(), vec![events[0].clone()]);
+		subscriptions.publish(4);
+		assert_eq!(users.try_iter().collect::<Vec<_>>(), vec![events[2].clone()]);
+		assert_eq!(all.try_iter().count(), 4);
+
+		// Dropped receivers are unsubscribed
+		drop(users);
+		drop(all);
+		subscriptions.push(ChangeEvent { sequence: 5, entry: entry(b"users/Tuesday", None) });
+		subscriptions.publish(5);
+		assert!(!subscriptions.has_subscribers());
+	}
+}
@@ -0,0 +1,249 @@
+use std::marker::PhantomData;
+
+use crate::keys;
+use crate::mem_table::{MemTable, SetOutcome};
+
+
+/// Encode converts a value into the byte representation stored in a
+///   MemTable.
+///
+/// Encodings of keys should preserve the ordering of the values so that
+///   the byte-wise ordering of the MemTable matches the ordering of the type.
+pub trait Encode {
+	fn encode(&self) -> Vec<u8>;
+}
+
+
+/// Decode recovers a value from the bytes produced by its `Encode`
+///   implementation.
+///
+/// Returns None if the bytes are not a valid encoding of the type.
+pub trait Decode: Sized {
+	fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+
+/// A TypedTable wraps a MemTable and stores keys and values of application
+///   types, encoding them to bytes on the way in and decoding them on the
+///   way out.
+///
+/// The underlying MemTable remains byte-oriented and can be recovered with
+///   `into_inner` to be flushed or written to a WAL.
+pub struct TypedTable<K, V> {
+	table: MemTable,
+	_types: PhantomData<(K, V)>,
+}
+
+
+impl<K: Encode, V: Encode + Decode> TypedTable<K, V> {
+	// Creates a new TypedTable over an empty MemTable
+	pub fn new() -> TypedTable<K, V> {
+		TypedTable::from_table(MemTable::new())
+	}
+
+	// Creates a TypedTable over an existing MemTable, e.g. one recovered from
+	//	a WAL
+	pub fn from_table(table: MemTable) -> TypedTable<K, V> {
+		TypedTable {
+			table,
+			_types: PhantomData,
+		}
+	}
+
+	// Encodes the key and value and sets them in the MemTable
+	pub fn set(&mut self, key: &K, value: &V, timestamp: u128) -> SetOutcome {
+		self.table.set(&key.encode(), &value.encode(), timestamp)
+	}
+
+	// Gets the decoded value of a key.
+	//
+	// Returns None if the key does not exist, has been deleted or if the
+	//	stored value cannot be decoded as `V`
+	pub fn get(&self, key: &K) -> Option<V> {
+		let entry = self.table.get(&key.encode())?;
+		V::decode(entry.value.as_ref()?)
+	}
+
+	// Deletes a key from the MemTable
+	pub fn delete(&mut self, key: &K, timestamp: u128) {
+		self.table.delete(&key.encode(), timestamp);
+	}
+
+	// Gets the number of records in the underlying MemTable
+	pub fn len(&self) -> usize {
+		self.table.len()
+	}
+
+	// Returns true if the underlying MemTable holds no records
+	pub fn is_empty(&self) -> bool {
+		self.table.is_empty()
+	}
+
+	// Gets a reference to the underlying MemTable
+	pub fn table(&self) -> &MemTable {
+		&self.table
+	}
+
+	// Consumes the TypedTable returning the underlying MemTable
+	pub fn into_inner(self) -> MemTable {
+		self.table
+	}
+}
+
+impl<K: Encode, V: Encode + Decode> Default for TypedTable<K, V> {
+	fn default() -> TypedTable<K, V> {
+		TypedTable::new()
+	}
+}
+
+
+impl Encode for Vec<u8> {
+	fn encode(&self) -> Vec<u8> {
+		self.clone()
+	}
+}
+
+impl Decode for Vec<u8> {
+	fn decode(bytes: &[u8]) -> Option<Vec<u8>> {
+		Some(bytes.to_vec())
+	}
+}
+
+impl Encode for String {
+	fn encode(&self) -> Vec<u8> {
+		self.as_bytes().to_vec()
+	}
+}
+
+impl Decode for String {
+	fn decode(bytes: &[u8]) -> Option<String> {
+		String::from_utf8(bytes.to_vec()).ok()
+	}
+}
+
+// Unsigned integers are encoded big-endian so the byte ordering matches the
+//	numeric ordering
+macro_rules! impl_codec_for_uint {
+	($($t:ty),*) => {
+		$(
+			impl Encode for $t {
+				fn encode(&self) -> Vec<u8> {
+					self.to_be_bytes().to_vec()
+				}
+			}
+
+			impl Decode for $t {
+				fn decode(bytes: &[u8]) -> Option<$t> {
+					Some(<$t>::from_be_bytes(bytes.try_into().ok()?))
+				}
+			}
+		)*
+	};
+}
+
+impl_codec_for_uint!(u8, u16, u32, u64, u128);
+
+// Signed integers and floats use the order-preserving encodings of `keys`
+impl Encode for i64 {
+	fn encode(&self) -> Vec<u8> {
+		keys::encode_i64(*self).to_vec()
+	}
+}
+
+impl Decode for i64 {
+	fn decode(bytes: &[u8]) -> Option<i64> {
+		keys::decode_i64(bytes)
+	}
+}
+
+impl Encode for f64 {
+	fn encode(&self) -> Vec<u8> {
+		keys::encode_f64(*self).to_vec()
+	}
+}
+
+impl Decode for f64 {
+	fn decode(bytes: &[u8]) -> Option<f64> {
+		keys::decode_f64(bytes)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::typed_table::{Decode, Encode, TypedTable};
+
+	#[derive(Debug, PartialEq)]
+	struct Point {
+		x: u32,
+		y: u32,
+	}
+
+	impl Encode for Point {
+		fn encode(&self) -> Vec<u8> {
+			[self.x.encode(), self.y.encode()].concat()
+		}
+	}
+
+	impl Decode for Point {
+		fn decode(bytes: &[u8]) -> Option<Point> {
+			if bytes.len() != 8 {
+				return None;
+			}
+			Some(Point {
+				x: u32::decode(&bytes[..4])?,
+				y: u32::decode(&bytes[4..])?,
+			})
+		}
+	}
+
+	#[test]
+	fn test_typed_table_set_get() {
+		let mut table: TypedTable<String, Point> = TypedTable::new();
+
+		table.set(&"origin".to_string(), &Point { x: 0, y: 0 }, 0);
+		table.set(&"corner".to_string(), &Point { x: 10, y: 20 }, 10);
+
+		assert_eq!(table.len(), 2);
+		assert_eq!(table.get(&"corner".to_string()), Some(Point { x: 10, y: 20 }));
+		assert_eq!(table.get(&"origin".to_string()), Some(Point { x: 0, y: 0 }));
+		assert_eq!(table.get(&"middle".to_string()), None);
+	}
+
+	#[test]
+	fn test_typed_table_delete() {
+		let mut table: TypedTable<u64, String> = TypedTable::new();
+
+		table.set(&1, &"Rejoice".to_string(), 0);
+		table.delete(&1, 10);
+
+		assert_eq!(table.len(), 1);
+		assert_eq!(table.get(&1), None);
+	}
+
+	#[test]
+	fn test_typed_table_into_inner() {
+		let mut table: TypedTable<u64, String> = TypedTable::new();
+
+		table.set(&256, &"Celebrate".to_string(), 0);
+		table.set(&1, &"Rejoice".to_string(), 0);
+
+		let table = table.into_inner();
+		assert_eq!(table.get(&1u64.encode()).unwrap().value.as_ref().unwrap(), b"Rejoice");
+		assert_eq!(u64::decode(&256u64.encode()), Some(256));
+		assert_eq!(u64::decode(b"short"), None);
+	}
+
+	#[test]
+	fn test_typed_table_signed_keys() {
+		let mut table: TypedTable<i64, f64> = TypedTable::new();
+
+		table.set(&5, &1.5, 0);
+		table.set(&-5, &-1.5, 0);
+		table.set(&0, &0.0, 0);
+
+		assert_eq!(table.get(&-5), Some(-1.5));
+		let keys: Vec<i64> = table.table().iter().map(|e| i64::decode(&e.key).unwrap()).collect();
+		assert_eq!(keys, vec![-5, 0, 5]);
+	}
+}
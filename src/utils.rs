@@ -1,15 +1,201 @@
-use std::fs::read_dir;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{read_dir, rename, File, OpenOptions, TryLockError};
+use std::io;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 
-pub fn files_with_ext(dir: &Path, ext: &str) -> Vec<PathBuf> {
+/// AlreadyLocked is the error returned when a directory is locked by
+///   another process, or another open of it in this process.
+///
+/// It is held by an io::Error of kind `WouldBlock`, so it can be recovered
+///   with `downcast_ref` or checked for with `is_already_locked`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AlreadyLocked {
+	pub dir: PathBuf,
+}
+
+
+// Lists the files within a directory with an extension.
+//
+// Errors reading the directory are returned rather than panicking
+pub fn files_with_ext(dir: &Path, ext: &str) -> io::Result<Vec<PathBuf>> {
 	let mut files = Vec::new();
-	for file in read_dir(dir).unwrap() {
-		let path = file.unwrap().path();
-		if path.extension().unwrap() == ext {
+	for file in read_dir(dir)? {
+		let path = file?.path();
+		if path.extension().is_some_and(|e| e == ext) {
 			files.push(path)
 		}
 	}
 
-	files
-}
\ No newline at end of file
+	Ok(files)
+}
+
+
+// Acquires an exclusive advisory lock on the `LOCK` file within a directory,
+//	creating the file if needed.
+//
+// The lock is held until the returned file is dropped. If another process
+//	(or another open of the directory) holds the lock an error of kind 
+//	`WouldBlock` holding an AlreadyLocked is returned.
+pub fn lock_dir(dir: &Path) -> io::Result<File> {
+	let file = OpenOptions::new()
+		.read(true)
+		.write(true)
+		.create(true)
+		.truncate(false)
+		.open(dir.join("LOCK"))?;
+
+	match file.try_lock() {
+		Ok(()) => Ok(file),
+		Err(TryLockError::WouldBlock) => Err(io::Error::new(
+			io::ErrorKind::WouldBlock,
+			AlreadyLocked { dir: dir.to_owned() },
+		)),
+		Err(TryLockError::Error(e)) => Err(e),
+	}
+}
+
+// Checks whether an error is an AlreadyLocked returned by `lock_dir`
+pub fn is_already_locked(error: &io::Error) -> bool {
+	error.get_ref().is_some_and(|e| e.is::<AlreadyLocked>())
+}
+
+
+// Writes a file so it either holds all of the data or is left as it was,
+//	even if the system crashes mid write.
+//
+// The data is written and synced to `<path>.tmp`, which is then renamed
+//	over the path and the directory synced so the rename is durable
+pub fn atomic_write(path: &Path, data: &[u8]) -> io::Result<()> {
+	let mut tmp_path = OsString::from(path);
+	tmp_path.push(".tmp");
+	let tmp_path = PathBuf::from(tmp_path);
+
+	let mut file = File::create(&tmp_path)?;
+	file.write_all(data)?;
+	file.sync_all()?;
+	drop(file);
+
+	rename(&tmp_path, path)?;
+	sync_dir(parent_dir(path))
+}
+
+// Syncs a directory, so the files created, renamed or removed within it
+//	survive a crash.
+//
+// Directories can't be synced on Windows, where this does nothing
+#[cfg(unix)]
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+	File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+pub fn sync_dir(_dir: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+// Gets the directory holding a file, which is the current directory for a
+//	bare file name
+pub fn parent_dir(path: &Path) -> &Path {
+	match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	}
+}
+
+
+// Lookup table for computing CRC-32C checksums a byte at a time
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+	let mut table = [0; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut crc = i as u32;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = (crc >> 1) ^ (0x82F6_3B78 & (crc & 1).wrapping_neg());
+			bit += 1;
+		}
+		table[i] = crc;
+		i += 1;
+	}
+	table
+}
+
+// Computes the CRC-32C (Castagnoli) checksum of the data
+pub fn crc32c(data: &[u8]) -> u32 {
+	crc32c_extend(0, data)
+}
+
+// Extends a CRC-32C checksum with more data, so a checksum can be computed
+//	over data held in several buffers
+pub fn crc32c_extend(crc: u32, data: &[u8]) -> u32 {
+	let mut crc = !crc;
+	for byte in data {
+		crc = CRC32C_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+	}
+	!crc
+}
+
+
+impl fmt::Display for AlreadyLocked {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} is locked by another process", self.dir.display())
+	}
+}
+
+impl Error for AlreadyLocked {}
+
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{create_dir, read, read_dir, remove_dir_all};
+	use std::path::PathBuf;
+	use rand::Rng;
+
+	use crate::utils::{atomic_write, crc32c, crc32c_extend, is_already_locked, lock_dir, AlreadyLocked};
+
+	#[test]
+	fn test_lock_dir() {
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let lock = lock_dir(&dir).unwrap();
+		let error = lock_dir(&dir).unwrap_err();
+		assert!(is_already_locked(&error));
+		let locked = error.get_ref().unwrap().downcast_ref::<AlreadyLocked>().unwrap();
+		assert_eq!(locked.dir, dir);
+		assert!(!is_already_locked(&std::io::Error::other("other")));
+
+		drop(lock);
+		lock_dir(&dir).unwrap();
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_atomic_write() {
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+		let path = dir.join("Monday");
+
+		atomic_write(&path, b"Rejoice").unwrap();
+		atomic_write(&path, b"Celebrate").unwrap();
+		assert_eq!(read(&path).unwrap(), b"Celebrate");
+		// The temporary file is renamed over the path
+		assert_eq!(read_dir(&dir).unwrap().count(), 1);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_crc32c() {
+		assert_eq!(crc32c(b""), 0);
+		assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+		assert_eq!(crc32c(&[0; 32]), 0x8A91_36AA);
+		assert_eq!(crc32c_extend(crc32c(b"1234"), b"56789"), crc32c(b"123456789"));
+	}
+}
@@ -1,4 +1,10 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::create_dir_all;
+use std::fs::read_dir;
+use std::fs::remove_dir;
 use std::fs::remove_file;
+use std::fs::rename;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
@@ -6,13 +12,30 @@ use std::io::BufWriter;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
 
-use crate::mem_table::MemTable;
-use crate::utils::files_with_ext;
+use crate::clock::{Clock, SystemClock};
+use crate::event_listener::{EventListener, RecoveryInfo};
+use crate::latency::{LatencyHistogram, LatencySummary};
+use crate::log_format::LogWriter;
+use crate::log_storage::{FileStorage, LogStorage, MemoryStorage, StorageReader, StorageWriter, SyncHandle};
+use crate::mem_table::{prefix_end, ConflictPolicy, MemTable, SetOutcome};
+use crate::mem_table_backend::MemTableBackend;
+use crate::subscription::{ChangeEvent, Subscriptions};
+use crate::utils::{files_with_ext, lock_dir, parent_dir, sync_dir};
+use crate::wal_iterator::Corruption;
 use crate::wal_iterator::WALEntry;
 use crate::wal_iterator::WALIterator;
+use crate::wal_iterator::WalMergeIterator;
 
 
 /// Write Ahead Log (WAL)
@@ -22,9 +45,341 @@ use crate::wal_iterator::WALIterator;
 ///
 /// The WAL is used to recover the contents of the MemTable when the server
 /// is shutdown uncleanly.
+///
+/// A WAL is stored in a file, or in any LogStorage given to `from_storage`.
+#[allow(clippy::tabs_in_doc_comments)]
 pub struct WAL {
 	path: PathBuf,
-	file: BufWriter<File>,
+	file: LogWriter<BufWriter<StorageWriter>>,
+	options: WalOptions,
+	last_flush: Instant,
+	// The lock on the WAL directory, held while the WAL is open
+	lock: Option<File>,
+	// The number of records held in the WAL
+	last_sequence: u64,
+	// Consulted before each record is appended, if set
+	write_policy: Option<Box<dyn WritePolicy>>,
+	// The bytes written to the WAL since it was opened
+	io_stats: WalIoStats,
+	// The sequence numbers written to the storage and made durable
+	sync_state: Arc<SyncState>,
+	// Syncs the storage periodically, if `fsync_interval` is set
+	syncer: Option<BackgroundSync>,
+	// The time taken by each append and flush
+	append_latency: LatencyHistogram,
+	flush_latency: LatencyHistogram,
+}
+
+
+// The progress of writes to the storage of a WAL, shared with the thread
+//	syncing it in the background
+#[derive(Default)]
+struct SyncState {
+	// The sequence of the last record written in full to the storage
+	written: AtomicU64,
+	// The sequence of the last record known to be durable
+	durable: AtomicU64,
+	// The first error from syncing in the background, if not yet returned
+	error: Mutex<Option<io::Error>>,
+	// The subscribers to the writes, which are delivered once durable
+	subscriptions: Mutex<Subscriptions>,
+}
+
+
+// A thread syncing the storage of a WAL every interval, which stops once
+//	dropped
+struct BackgroundSync {
+	stop: Option<mpsc::Sender<()>>,
+	thread: Option<JoinHandle<()>>,
+}
+
+
+/// A WalRecord is an operation to be recorded to the WAL
+pub enum WalRecord<'a> {
+	Set { key: &'a [u8], value: &'a [u8], meta: Option<&'a [u8]>, timestamp: u128 },
+	Delete { key: &'a [u8], timestamp: u128 },
+	DeleteRange { start: &'a [u8], end: &'a [u8], timestamp: u128 },
+}
+
+
+// The kinds of record held in the WAL, which are described with the record
+//	layout in the wal_iterator and format modules
+pub(crate) use crate::format::{BATCH_KIND, DELETE_KIND, DELETE_RANGE_KIND, SET_KIND, SET_WITH_META_KIND};
+
+
+/// A WalDirIterator iterates over the entries of the WAL files within a
+///   directory in timestamp order, created with `open_dir_readonly`.
+///
+/// An error reading one of the files is returned in place of the next
+///   entry, after which the iterator ends.
+pub struct WalDirIterator {
+	merge: WalMergeIterator<WALIterator>,
+	failed: bool,
+}
+
+
+/// A WalSegment is a WAL file within a directory, named after the timestamp
+///   it was created at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalSegment {
+	pub timestamp: u128,
+	pub path: PathBuf,
+}
+
+
+/// A RetentionPolicy decides how long WAL files are kept once they have
+///   been merged during recovery, such as for point-in-time recovery.
+///
+/// Files beyond the newest `keep_segments`, or older than `keep_for`, are
+///   deleted. A limit which is None doesn't apply. By default no files are
+///   kept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+	pub keep_segments: Option<usize>,
+	pub keep_for: Option<Duration>,
+}
+
+
+/// An ArchiveSink receives each WAL file before it is deleted, so it can be
+///   copied elsewhere, such as to an object store.
+pub trait ArchiveSink {
+	fn archive(&mut self, segment: &WalSegment) -> io::Result<()>;
+}
+
+
+/// WalIoStats count the bytes written to a WAL since it was opened,
+///   including the records merged into it during recovery.
+///
+/// `logical_bytes` are the keys, values and metadata of the operations
+///   recorded. `record_bytes` are the encoded records holding them, and
+///   `log_bytes` are the bytes written to the file, including the headers
+///   of fragments and the padding at the end of blocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WalIoStats {
+	pub logical_bytes: u64,
+	pub record_bytes: u64,
+	pub log_bytes: u64,
+}
+
+
+/// A WalLatencyReport summarises the time taken to append records to a WAL
+///   and to flush it since it was opened.
+///
+/// Appends include the time to flush the buffer when it fills, so stalls
+///   writing to the file show in their tail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WalLatencyReport {
+	pub append: LatencySummary,
+	pub flush: LatencySummary,
+}
+
+
+/// A WritePolicy is consulted before each record is appended to a WAL, and
+///   can reject it, such as to enforce quotas on keys, sizes or rates.
+///
+/// Records merged during recovery have already been admitted, so are not
+///   checked.
+pub trait WritePolicy: Send {
+	fn check(&mut self, record: &WalRecord) -> Result<(), WriteRejection>;
+}
+
+
+/// A WriteRejection is the reason a WritePolicy rejected a record.
+///
+/// Rejected writes return an error of kind `PermissionDenied` which holds
+///   the WriteRejection, so it can be recovered with `downcast_ref`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteRejection {
+	pub reason: String,
+}
+
+
+/// A RateLimit is a WritePolicy limiting the rate records are appended to
+///   a WAL, so bulk imports can't overwhelm latency sensitive writers.
+///
+/// Records draw from buckets of `max_writes_per_sec` records and
+///   `max_bytes_per_sec` bytes of keys, values and metadata, which refill
+///   continuously and hold up to a second of writes. A record exceeding a
+///   limit is rejected as throttled, or waits for the buckets to refill if
+///   the RateLimit is `blocking`. A limit of zero is always exceeded, and
+///   rejects every record even when blocking, as its bucket never refills.
+pub struct RateLimit {
+	writes: Option<TokenBucket>,
+	bytes: Option<TokenBucket>,
+	block: bool,
+}
+
+
+// A bucket of tokens refilled at a rate per second, holding up to a second
+//	of tokens
+struct TokenBucket {
+	rate: f64,
+	tokens: f64,
+	last_refill: Instant,
+}
+
+
+/// Options controlling how the WAL files of a directory are recovered.
+///
+/// If `threads` is greater than one, the files are decoded in parallel by
+///   up to that many threads before their entries are merged. Otherwise
+///   they are decoded as they are merged, which holds less in memory.
+///
+/// `conflicts` is the ConflictPolicy of the recovered MemTable, which
+///   decides between entries of a key with the same timestamp as they are
+///   replayed.
+///
+/// `listeners` are told as the WAL files are merged and retired, and once
+///   recovery is complete.
+#[derive(Clone)]
+pub struct RecoveryOptions {
+	pub threads: usize,
+	pub conflicts: ConflictPolicy,
+	pub listeners: Vec<Arc<dyn EventListener>>,
+}
+
+
+/// Options controlling how writes to the WAL are batched in memory.
+///
+/// Writes are held in a buffer of at most `buffer_bytes` which is written
+///   to the file when full. If `flush_interval` is set the buffer is also
+///   flushed by the first write made after the interval has elapsed since
+///   the last flush.
+///
+/// If `use_odsync` is set the file is opened with O_DSYNC, so each write of
+///   the buffer to the file returns once the data is on disk. This is only
+///   supported on Unix platforms and is ignored elsewhere.
+///
+/// If `fsync_interval` is set a background thread syncs the file every
+///   interval instead, so at most an interval of writes is lost on a crash
+///   without syncing on each write. Only writes which have left the buffer
+///   are synced, so it is best combined with `flush_interval`.
+///
+/// If `preallocate_bytes` is set the file is allocated that many bytes
+///   ahead of the records written, and again whenever they reach the end of
+///   the allocation, so appends don't change the file's size and syncing
+///   them needn't update its metadata. The log ends at the zeros after the
+///   last record, and the space left unused is released when the WAL is
+///   closed. A record torn by a crash in preallocated space is read as
+///   corrupt rather than cut short, as zeros follow it.
+#[derive(Clone, Debug)]
+pub struct WalOptions {
+	pub buffer_bytes: usize,
+	pub flush_interval: Option<Duration>,
+	pub use_odsync: bool,
+	pub fsync_interval: Option<Duration>,
+	pub preallocate_bytes: Option<u64>,
+}
+
+
+/// A CommitToken identifies a write recorded to a WAL by its sequence
+///   number, as returned by each write.
+///
+/// Tokens are ordered as their writes were recorded, so a reader given a
+///   token can check whether a WAL has made the write durable with
+///   `WAL::is_durable`. A batch returns the token of its last operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CommitToken {
+	pub sequence: u64,
+}
+
+
+/// A VerifyReport compares a MemTable with the MemTable replayed from a
+///   WAL, as returned by `WAL::verify`.
+///
+/// `mismatched_keys` holds, in key order, the keys whose records are 
+///   missing from either MemTable or differ between them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyReport {
+	pub wal_entries: usize,
+	pub mem_table_entries: usize,
+	pub mismatched_keys: Vec<Vec<u8>>,
+	pub range_tombstones_match: bool,
+}
+
+
+/// A SegmentReport describes the records of a WAL file, as returned by
+///   `WAL::verify_dir`.
+///
+/// `first_timestamp` and `last_timestamp` are the timestamps of the first
+///   and last entries in the file, or None if it holds none.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentReport {
+	pub segment: WalSegment,
+	pub entries: usize,
+	pub first_timestamp: Option<u128>,
+	pub last_timestamp: Option<u128>,
+	pub corruptions: Vec<Corruption>,
+}
+
+
+/// A RepairReport describes the records kept and the bytes dropped when a
+///   WAL file is truncated to its last valid record, as returned by
+///   `WAL::repair`.
+///
+/// `corruptions` are those found in the dropped bytes, which may hide
+///   valid records written after them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepairReport {
+	pub entries_kept: usize,
+	pub bytes_kept: u64,
+	pub bytes_dropped: u64,
+	pub corruptions: Vec<Corruption>,
+}
+
+
+/// A MemWAL holds a WAL in memory instead of a directory.
+///
+/// WALs opened from a MemWAL append to the same log, so reopening it
+///   recovers the writes made before, as `from_dir` would. This allows fast
+///   deterministic tests and tables which don't need to be durable.
+#[derive(Clone, Default)]
+pub struct MemWAL {
+	storage: MemoryStorage,
+}
+
+
+impl Default for RetentionPolicy {
+	fn default() -> RetentionPolicy {
+		RetentionPolicy {
+			keep_segments: Some(0),
+			keep_for: None,
+		}
+	}
+}
+
+
+impl Default for RecoveryOptions {
+	fn default() -> RecoveryOptions {
+		RecoveryOptions {
+			threads: 1,
+			conflicts: ConflictPolicy::default(),
+			listeners: Vec::new(),
+		}
+	}
+}
+
+impl fmt::Debug for RecoveryOptions {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("RecoveryOptions")
+			.field("threads", &self.threads)
+			.field("conflicts", &self.conflicts)
+			.field("listeners", &self.listeners.len())
+			.finish()
+	}
+}
+
+
+impl Default for WalOptions {
+	fn default() -> WalOptions {
+		WalOptions {
+			buffer_bytes: 8 * 1024,
+			flush_interval: None,
+			use_odsync: false,
+			fsync_interval: None,
+			preallocate_bytes: None,
+		}
+	}
 }
 
 
@@ -33,278 +388,2736 @@ impl WAL {
 	//	recovered MemTable.
 	//
 	// If multiple WAL files exist in the directory they're merged into one
-	//	WAL
+	//	WAL, and the merged files are deleted
+	//
+	// The directory is locked for as long as the returned WAL is open, and 
+	//	an error of kind `WouldBlock` is returned if it is already locked
 	pub fn from_dir(dir: &Path) -> io::Result<(WAL, MemTable)> {
-		let mut wal_files = files_with_ext(dir, "wal");
-		wal_files.sort();
-
-		let mut new_mem_table = MemTable::new();
-		let mut new_wal = WAL::new(dir)?;
-
-		for wal_file in wal_files.iter() {
-			if let Ok(wal) = WAL::from_path(wal_file) {
-				for entry in wal.into_iter() {
-					if entry.deleted {
-						new_mem_table.delete(entry.key.as_slice(), entry.timestamp);
-						new_wal.delete(entry.key.as_slice(), entry.timestamp)?;
-					} else {
-						new_mem_table.set(entry.key.as_slice(), 
-															entry.value.as_ref().unwrap().as_slice(), 
-															entry.timestamp);
-						new_wal.set(entry.key.as_slice(), 
-												entry.value.as_ref().unwrap().as_slice(),
-												entry.timestamp)?;
-					}
-				}
+		WAL::from_dir_with_clock(dir, &SystemClock)
+	}
+
+	// Loads the WAL files within a directory like `from_dir`, timestamping
+	//	the merged WAL with the physical time of the clock.
+	//
+	// The merged WAL is always timestamped after the files it replaces, so
+	//	a clock which is behind them can't name it after one of them
+	pub fn from_dir_with_clock(dir: &Path, clock: &dyn Clock) -> io::Result<(WAL, MemTable)> {
+		WAL::from_dir_with_retention(dir, &RetentionPolicy::default(), None, clock)
+	}
+
+	// Loads the WAL files within a directory like `from_dir_with_clock`, but
+	//	keeps the merged files according to the retention policy.
+	//
+	// Merged files are moved into the `archive` subdirectory so they aren't
+	//	replayed again. Their age is measured against the physical time of
+	//	the clock. Before any file is deleted it is passed to the archive
+	//	sink, and if that fails the file is kept and the error returned
+	pub fn from_dir_with_retention(
+		dir: &Path,
+		retention: &RetentionPolicy,
+		sink: Option<&mut dyn ArchiveSink>,
+		clock: &dyn Clock,
+	) -> io::Result<(WAL, MemTable)> {
+		WAL::from_dir_with_recovery(dir, &RecoveryOptions::default(), retention, sink, clock)
+	}
+
+	// Loads the WAL files within a directory like `from_dir_with_retention`,
+	//	decoding the files as set by the recovery options.
+	//
+	// The recovered MemTable and merged WAL are the same however many 
+	//	threads the files are decoded by
+	pub fn from_dir_with_recovery(
+		dir: &Path,
+		recovery: &RecoveryOptions,
+		retention: &RetentionPolicy,
+		sink: Option<&mut dyn ArchiveSink>,
+		clock: &dyn Clock,
+	) -> io::Result<(WAL, MemTable)> {
+		let mem_table = MemTable::new().with_conflict_policy(recovery.conflicts);
+		WAL::from_dir_into(dir, mem_table, recovery, retention, sink, clock)
+	}
+
+	// Loads the WAL files within a directory like `from_dir_with_recovery`,
+	//	replaying them into an empty MemTableBackend instead of a MemTable.
+	//
+	// The ConflictPolicy of the recovery options is not applied, as writes
+	//	are resolved by the backend
+	pub fn from_dir_into<T: MemTableBackend>(
+		dir: &Path,
+		mut new_mem_table: T,
+		recovery: &RecoveryOptions,
+		retention: &RetentionPolicy,
+		sink: Option<&mut dyn ArchiveSink>,
+		clock: &dyn Clock,
+	) -> io::Result<(WAL, T)> {
+		let lock = lock_dir(dir)?;
+
+		let segments = segment_catalog(dir)?;
+
+		// The merged WAL can't be named after a file it replaces, or one it
+		//	would replace in the archive
+		let archive_dir = dir.join("archive");
+		let archived = match archive_dir.is_dir() {
+			true => segment_catalog(&archive_dir)?,
+			false => Vec::new(),
+		};
+		let newest = segments.iter().chain(archived.iter()).map(|s| s.timestamp + 1).max().unwrap_or(0);
+		let mut new_wal = WAL::from_path(&segment_path(dir, clock.physical_micros().max(newest)))?;
+		new_wal.lock = Some(lock);
+		let merged = merge_segments(&segments, recovery, &mut new_wal, &mut new_mem_table)
+			.and_then(|()| new_wal.file.get_mut().get_mut().0.sync());
+		if let Err(e) = merged {
+			// The partly merged WAL is removed, so the merge starts afresh when
+			//	recovery is retried
+			let path = new_wal.path.clone();
+			drop(new_wal);
+			let _ = remove_file(path);
+			return Err(e);
+		}
+		for listener in recovery.listeners.iter() {
+			listener.on_wal_rotate(&segments, &new_wal.path);
+		}
+		// The merged WAL is synced before the files it replaces are removed
+		let merged_segments = segments.len();
+		retire_segments(dir, segments, retention, sink, clock, &recovery.listeners)?;
+
+		let info = RecoveryInfo {
+			wal: new_wal.path.clone(),
+			segments: merged_segments,
+			entries: new_mem_table.len(),
+			last_sequence: new_wal.last_sequence,
+		};
+		for listener in recovery.listeners.iter() {
+			listener.on_recovery_complete(&info);
+		}
+		Ok((new_wal, new_mem_table))
+	}
+
+	// Loads the WAL files within a directory into a recovered MemTable
+	//	without creating, rewriting or deleting any files.
+	//
+	// This allows inspecting a directory which is owned by another process
+	pub fn from_dir_read_only(dir: &Path) -> io::Result<MemTable> {
+		let mut mem_table = MemTable::new();
+		for entry in open_dir_readonly(dir)? {
+			replay_entry(&mut mem_table, &entry?);
+		}
+
+		Ok(mem_table)
+	}
+
+	// Reads every WAL file within a directory, checking the checksums and
+	//	framing of its records, and reports the entries and corruptions 
+	//	found in each.
+	//
+	// Like `from_dir_read_only` no files are created, rewritten or deleted,
+	//	so this can be used to check a directory before recovering it
+	pub fn verify_dir(dir: &Path) -> io::Result<Vec<SegmentReport>> {
+		let mut reports = Vec::new();
+		for segment in segment_catalog(dir)? {
+			let mut iter = WALIterator::new(segment.path.clone())?;
+			let mut report = SegmentReport {
+				segment,
+				entries: 0,
+				first_timestamp: None,
+				last_timestamp: None,
+				corruptions: Vec::new(),
+			};
+			for entry in iter.by_ref() {
+				report.entries += 1;
+				report.first_timestamp.get_or_insert(entry.timestamp);
+				report.last_timestamp = Some(entry.timestamp);
+			}
+			if let Some(e) = iter.take_read_error() {
+				return Err(e);
+			}
+			report.corruptions = iter.corruptions().to_vec();
+			reports.push(report);
+		}
+
+		Ok(reports)
+	}
+
+	// Deletes a WAL directory, including its archive and lock, and the
+	//	directory itself.
+	//
+	// Nothing is deleted unless every file in the directory belongs to the
+	//	WAL, so a mistaken path can't destroy other data. An error of kind
+	//	`InvalidInput` names the first file which doesn't, and an error of
+	//	kind `WouldBlock` is returned if the directory is open
+	pub fn destroy_dir(dir: &Path) -> io::Result<()> {
+		let lock = lock_dir(dir)?;
+
+		let archive_dir = dir.join("archive");
+		let has_archive = archive_dir.is_dir();
+		let segments = segment_catalog(dir)?;
+		let archived = match has_archive {
+			true => segment_catalog(&archive_dir)?,
+			false => Vec::new(),
+		};
+		let owned = |path: &Path| {
+			path == dir.join("LOCK")
+				|| (has_archive && path == archive_dir)
+				|| segments.iter().chain(archived.iter()).any(|s| s.path == path)
+		};
+		for file in read_dir(dir)?.chain(read_dir(&archive_dir).into_iter().flatten()) {
+			let path = file?.path();
+			if !owned(&path) {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					format!("{} doesn't belong to the WAL, refusing to destroy {}", path.display(), dir.display()),
+				));
+			}
+		}
+
+		for segment in segments.iter().chain(archived.iter()) {
+			remove_file(&segment.path)?;
+		}
+		if has_archive {
+			remove_dir(&archive_dir)?;
+		}
+		remove_file(dir.join("LOCK"))?;
+		drop(lock);
+		// Only removes the directory if it is still empty
+		remove_dir(dir)
+	}
+
+	// Truncates a WAL file after its last record which is valid and not
+	//	preceded by a corrupt one, so it can be recovered without skipping
+	//	records in the middle of it.
+	//
+	// The file must not be open as a WAL, such as by taking the directory
+	//	lock first. A file with no corruption or partly written record is
+	//	left untouched
+	pub fn repair(path: &Path) -> io::Result<RepairReport> {
+		let mut iter = WALIterator::new(path.to_owned())?;
+		let mut entries_kept = 0;
+		while iter.next().is_some() {
+			if iter.corruptions().is_empty() {
+				entries_kept += 1;
+			}
+		}
+		if let Some(e) = iter.take_read_error() {
+			return Err(e);
+		}
+
+		let file = OpenOptions::new().write(true).open(path)?;
+		let len = file.metadata()?.len();
+		let bytes_kept = iter.valid_len();
+		if bytes_kept < len {
+			file.set_len(bytes_kept)?;
+			file.sync_all()?;
+		}
+
+		Ok(RepairReport {
+			entries_kept,
+			bytes_kept,
+			bytes_dropped: len - bytes_kept,
+			corruptions: iter.corruptions().to_vec(),
+		})
+	}
+
+	// Recovers the MemTable of a directory as it was at a timestamp, by
+	//	replaying the WAL files kept in its archive and the live WAL files,
+	//	skipping entries written after the timestamp.
+	//
+	// Like `from_dir_read_only` no files are created, rewritten or deleted.
+	//	Writes are only recovered back to the oldest file kept by the
+	//	RetentionPolicy
+	pub fn restore_to(dir: &Path, timestamp: u128) -> io::Result<MemTable> {
+		let archive_dir = dir.join("archive");
+		let mut segments = if archive_dir.is_dir() {
+			segment_catalog(&archive_dir)?
+		} else {
+			Vec::new()
+		};
+		segments.extend(segment_catalog(dir)?);
+
+		// Merged files repeat the entries of the files before them, which 
+		//	replay to the same records
+		let mut mem_table = MemTable::new();
+		let mut iters = open_segments(&segments)?;
+		let merge = WalMergeIterator::new(iters.iter_mut().collect()).skip_duplicates();
+		for entry in merge.filter(|e| e.timestamp <= timestamp) {
+			replay_entry(&mut mem_table, &entry);
+		}
+		check_read_errors(&mut iters)?;
+
+		Ok(mem_table)
+	}
+
+	// Creates a new WAL timestamped with the current time in the directory
+	pub fn new(dir: &Path) -> io::Result<WAL> {
+		WAL::new_with_options(dir, WalOptions::default())
+	}
+
+	// Creates a new WAL timestamped with the current time in the directory
+	//	which buffers writes according to the options
+	pub fn new_with_options(dir: &Path, options: WalOptions) -> io::Result<WAL> {
+		WAL::new_with_clock(dir, options, &SystemClock)
+	}
+
+	// Creates a new WAL in the directory timestamped with the physical time
+	//	of the clock
+	pub fn new_with_clock(dir: &Path, options: WalOptions, clock: &dyn Clock) -> io::Result<WAL> {
+		WAL::from_path_with_options(&segment_path(dir, clock.physical_micros()), options)
+	}
+
+	// Creates a WAL using the provided file path
+	pub fn from_path(path: &Path) -> io::Result<WAL> {
+		WAL::from_path_with_options(path, WalOptions::default())
+	}
+
+	// Creates a WAL using the provided file path which buffers writes 
+	//	according to the options.
+	//
+	// An error of kind `InvalidData` is returned if the file was written
+	//	before the block format, as it can't be appended to. Recovering its
+	//	directory with `from_dir` rewrites it in the block format
+	pub fn from_path_with_options(path: &Path, options: WalOptions) -> io::Result<WAL> {
+		let created = !path.exists();
+		let file = open_options(&options).open(path)?;
+		// A new file is only durable once its directory is synced
+		if created {
+			sync_dir(parent_dir(path))?;
+		}
+
+		// Continue the sequence of the records already in the file, after the
+		//	end of the last one
+		let mut last_sequence = 0;
+		let mut records_end = 0;
+		if file.metadata()?.len() > 0 {
+			let mut iter = WALIterator::new(path.to_owned())?.with_offsets();
+			for (offset, len, _) in iter.by_ref() {
+				last_sequence += 1;
+				records_end = offset + len;
+			}
+			// Framed records appended to an unframed file couldn't be read back
+			if iter.into_inner().is_unframed() {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!("{} predates the block format, recover its directory to rewrite it", path.display()),
+				));
+			}
+		}
+
+		let storage = match options.preallocate_bytes {
+			Some(ahead) => FileStorage::preallocated(file, records_end, ahead)?,
+			None => FileStorage::new(file),
+		};
+		let mut wal = WAL::with_storage(Box::new(storage), options, last_sequence)?;
+		wal.path = path.to_owned();
+		Ok(wal)
+	}
+
+	// Creates a WAL which appends to a storage, continuing the records it
+	//	already holds.
+	//
+	// The WAL has no path, so it can't be merged by `from_dir`, but can be
+	//	iterated over like any other
+	pub fn from_storage(storage: Box<dyn LogStorage>, options: WalOptions) -> io::Result<WAL> {
+		let last_sequence = match storage.len()? {
+			0 => 0,
+			_ => WALIterator::from_reader(StorageReader::new(&*storage)).count() as u64,
+		};
+
+		WAL::with_storage(storage, options, last_sequence)
+	}
+
+	// The records already in the storage are taken to be durable
+	fn with_storage(storage: Box<dyn LogStorage>, options: WalOptions, last_sequence: u64) -> io::Result<WAL> {
+		let storage_len = storage.len()?;
+		let sync_state = Arc::new(SyncState {
+			written: AtomicU64::new(last_sequence),
+			durable: AtomicU64::new(last_sequence),
+			error: Mutex::new(None),
+			subscriptions: Mutex::new(Subscriptions::default()),
+		});
+		let syncer = match options.fsync_interval {
+			Some(interval) => {
+				let handle = storage.sync_handle()?.ok_or_else(|| io::Error::new(
+					io::ErrorKind::Unsupported,
+					"the WAL storage can't be synced in the background",
+				))?;
+				Some(BackgroundSync::start(handle, interval, Arc::clone(&sync_state)))
+			},
+			None => None,
+		};
+		let writer = BufWriter::with_capacity(options.buffer_bytes, StorageWriter(storage));
+
+		Ok(WAL {
+			path: PathBuf::new(),
+			file: LogWriter::new(writer, storage_len),
+			options,
+			last_flush: Instant::now(),
+			lock: None,
+			last_sequence,
+			write_policy: None,
+			io_stats: WalIoStats::default(),
+			sync_state,
+			syncer,
+			append_latency: LatencyHistogram::new(),
+			flush_latency: LatencyHistogram::new(),
+		})
+	}
+
+	// Sets the policy consulted before each record is appended to the WAL
+	pub fn with_write_policy(mut self, policy: Box<dyn WritePolicy>) -> WAL {
+		self.write_policy = Some(policy);
+		self
+	}
+
+	// Records the set operation on a key-value pair to the WAL
+	pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> io::Result<CommitToken> {
+		self.append(&WalRecord::Set { key, value, meta: None, timestamp })
+	}
+
+	// Records the set operation on a key-value pair, with metadata describing
+	//	the write, to the WAL
+	pub fn set_with_meta(
+		&mut self,
+		key: &[u8],
+		value: &[u8],
+		meta: &[u8],
+		timestamp: u128,
+	) -> io::Result<CommitToken> {
+		self.append(&WalRecord::Set { key, value, meta: Some(meta), timestamp })
+	}
+
+	// Record a delete operation on a key to the WAL
+	pub fn delete(&mut self, key: &[u8], timestamp: u128) -> io::Result<CommitToken> {
+		self.append(&WalRecord::Delete { key, timestamp })
+	}
+
+	// Record a delete operation on every key in the range `[start, end)` to
+	//	the WAL
+	pub fn delete_range(&mut self, start: &[u8], end: &[u8], timestamp: u128) -> io::Result<CommitToken> {
+		self.append(&WalRecord::DeleteRange { start, end, timestamp })
+	}
+
+	// Records the deletion of every key starting with a prefix to the WAL, as
+	//	a single range delete.
+	//
+	// An error of kind `InvalidInput` is returned if the prefix is empty or
+	//	only 0xff bytes, as no range can end after the keys starting with it
+	pub fn delete_prefix(&mut self, prefix: &[u8], timestamp: u128) -> io::Result<CommitToken> {
+		let end = prefix_end(prefix).ok_or_else(|| io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"the keys starting with the prefix can't be bounded by a range",
+		))?;
+		self.delete_range(prefix, &end, timestamp)
+	}
+
+	// Sets the value of a key in a MemTable, recording the set operation to
+	//	the WAL, only if its current value in the MemTable is `expected`,
+	//	where None expects the key to be missing or deleted.
+	//
+	// If the value differs nothing is recorded and None is returned, so a
+	//	failed comparison isn't replayed on recovery. A write the MemTable
+	//	would reject as Full or Stale isn't recorded either, and its outcome
+	//	is returned. Holding both the WAL and the MemTable mutably borrowed
+	//	keeps the comparison and the write from being interleaved with other
+	//	writes
+	pub fn compare_and_set(
+		&mut self,
+		table: &mut MemTable,
+		key: &[u8],
+		expected: Option<&[u8]>,
+		value: &[u8],
+		timestamp: u128,
+	) -> io::Result<Option<SetOutcome>> {
+		if !table.value_matches(key, expected) {
+			return Ok(None);
+		}
+		match table.would_accept(key, value, None, timestamp) {
+			SetOutcome::Inserted | SetOutcome::Updated => self.set(key, value, timestamp)?,
+			outcome => return Ok(Some(outcome)),
+		};
+		Ok(Some(table.set(key, value, timestamp)))
+	}
+
+	// Records an operation to the WAL
+	pub fn append(&mut self, record: &WalRecord) -> io::Result<CommitToken> {
+		if let Some(policy) = self.write_policy.as_mut() {
+			policy.check(record)?;
+		}
+
+		let mut buf = Vec::with_capacity(record.encoded_len());
+		record.encode(&mut buf);
+
+		self.write_record(&buf, std::slice::from_ref(record))
+	}
+
+	// Records a batch of operations to the WAL as a single frame.
+	//
+	// The batch is encoded into one buffer and written at once. Since the
+	//	frame is checksummed as a whole, recovery replays either all of the
+	//	operations in the batch or none of them. If the WritePolicy rejects
+	//	any of the operations, none of them are written
+	pub fn append_many(&mut self, records: &[WalRecord]) -> io::Result<CommitToken> {
+		if let Some(policy) = self.write_policy.as_mut() {
+			records.iter().try_for_each(|record| policy.check(record))?;
+		}
+
+		let len = records.iter().map(|r| r.encoded_len() + 8).sum::<usize>() + 9;
+		let mut buf = Vec::with_capacity(len);
+		put_len(&mut buf, records.len());
+		buf.extend_from_slice(&BATCH_KIND.to_le_bytes());
+		for record in records {
+			put_len(&mut buf, record.encoded_len());
+			record.encode(&mut buf);
+		}
+
+		self.write_record(&buf, records)
+	}
+
+	// Records the operation held in an entry read from another WAL, which
+	//	is not checked by the WritePolicy
+	pub fn append_entry(&mut self, entry: &WALEntry) -> io::Result<CommitToken> {
+		let record = WalRecord::from_entry(entry);
+		let mut buf = Vec::with_capacity(record.encoded_len());
+		record.encode(&mut buf);
+
+		self.write_record(&buf, &[record])
+	}
+
+	// Appends an encoded record holding operations to the log, holding them
+	//	to be delivered to any subscribers once they're durable
+	fn write_record(&mut self, record: &[u8], operations: &[WalRecord]) -> io::Result<CommitToken> {
+		let started = Instant::now();
+		self.file.add_record(record)?;
+		let mut subscriptions = self.sync_state.subscriptions.lock().unwrap();
+		if subscriptions.has_subscribers() {
+			for (sequence, operation) in (self.last_sequence + 1..).zip(operations) {
+				subscriptions.push(ChangeEvent { sequence, entry: operation.to_entry() });
 			}
 		}
-		new_wal.flush().unwrap();
-		wal_files.into_iter().for_each(|f| remove_file(f).unwrap());
+		drop(subscriptions);
+		self.last_sequence += operations.len() as u64;
+		let logical_len: usize = operations.iter().map(|r| r.logical_len()).sum();
+		self.io_stats.logical_bytes += logical_len as u64;
+		self.io_stats.record_bytes += record.len() as u64;
+		self.io_stats.log_bytes = self.file.bytes_written();
+		self.flush_if_due()?;
+		self.mark_written();
+		self.append_latency.record(started.elapsed());
+
+		Ok(CommitToken { sequence: self.last_sequence })
+	}
+
+	// Gets the sequence number of the last record written to the WAL.
+	//
+	// Records are numbered from 1 in the order they were written, so this is
+	//	also the number of records in the WAL. After recovery it is the
+	//	number of records replayed from the merged WALs
+	pub fn last_sequence(&self) -> u64 {
+		self.last_sequence
+	}
+
+	// Gets the sequence number of the last record known to be durable.
+	//
+	// Records are made durable by the background syncs of `fsync_interval`,
+	//	as they're written to the file with `use_odsync`, or by `sync` and
+	//	`close`. The records a WAL is opened with are taken to be durable
+	pub fn last_durable_sequence(&self) -> u64 {
+		self.sync_state.durable.load(Ordering::Acquire)
+	}
+
+	// Checks whether the write a token was returned for is durable
+	pub fn is_durable(&self, token: CommitToken) -> bool {
+		token.sequence <= self.last_durable_sequence()
+	}
+
+	// Subscribes to the writes to keys starting with a prefix, which are
+	//	received in the order they were written once they're durable.
+	//
+	// Writes made before subscribing aren't received. The receiver is
+	//	disconnected once the WAL is closed or dropped, and dropping the
+	//	receiver unsubscribes it
+	pub fn subscribe(&self, prefix: &[u8]) -> mpsc::Receiver<ChangeEvent> {
+		self.sync_state.subscriptions.lock().unwrap().subscribe(prefix)
+	}
+
+	// Writes the buffered writes to the file.
+	//
+	// If syncing in the background has failed since the last flush, the
+	//	error is returned
+	pub fn flush(&mut self) -> io::Result<()> {
+		let started = Instant::now();
+		self.file.get_mut().flush()?;
+		self.last_flush = Instant::now();
+		self.flush_latency.record(self.last_flush - started);
+		self.mark_written();
+
+		match self.sync_state.error.lock().unwrap().take() {
+			Some(e) => Err(e),
+			None => Ok(()),
+		}
+	}
+
+	// Flushes the buffered writes and syncs them to disk, making every
+	//	record written so far durable
+	pub fn sync(&mut self) -> io::Result<()> {
+		self.flush()?;
+		self.file.get_mut().get_mut().0.sync()?;
+		self.sync_state.mark_durable(self.last_sequence);
+		Ok(())
+	}
+
+	// Closes the WAL, flushing buffered writes and syncing them to disk.
+	//
+	// Unlike dropping the WAL, which discards any error from the final
+	//	flush, errors are returned. The directory lock is released once the
+	//	WAL is closed
+	pub fn close(mut self) -> io::Result<()> {
+		// Background syncs are stopped first, so an error from one is returned
+		drop(self.syncer.take());
+		self.flush()?;
+		let storage = &mut self.file.get_mut().get_mut().0;
+		storage.trim()?;
+		storage.sync()?;
+		self.sync_state.mark_durable(self.last_sequence);
+		Ok(())
+	}
+
+	// Re-reads the records written to the WAL and compares the MemTable they
+	//	replay into with a MemTable, such as the one returned by `from_dir`.
+	//
+	// Buffered writes are flushed first so every record is read back
+	pub fn verify(&mut self, mem_table: &MemTable) -> io::Result<VerifyReport> {
+		self.flush()?;
+
+		let mut replayed = MemTable::new();
+		let storage = &*self.file.get_ref().get_ref().0;
+		for entry in WALIterator::from_reader(StorageReader::new(storage)) {
+			replay_entry(&mut replayed, &entry);
+		}
+
+		Ok(VerifyReport::compare(&replayed, mem_table))
+	}
+
+	// Gets the path of the WAL file, which is empty for a WAL created with
+	//	`from_storage`
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	// Gets the length of the WAL in bytes, including the writes which are
+	//	buffered in memory
+	pub fn len_bytes(&self) -> io::Result<u64> {
+		let storage = &self.file.get_ref().get_ref().0;
+		Ok(storage.len()? + self.buffered_bytes() as u64)
+	}
+
+	// Lists the WAL files in the directory of the WAL, including its own, in
+	//	the order they were created.
+	//
+	// A WAL created with `from_storage` has no directory, so has no segments
+	pub fn segments(&self) -> io::Result<Vec<WalSegment>> {
+		match self.path.parent() {
+			Some(dir) if !dir.as_os_str().is_empty() => segment_catalog(dir),
+			_ => Ok(Vec::new()),
+		}
+	}
+
+	// Gets the bytes written to the WAL since it was opened
+	pub fn io_stats(&self) -> WalIoStats {
+		self.io_stats
+	}
+
+	// Gets the percentiles of the time taken by appends and flushes since
+	//	the WAL was opened
+	pub fn latency_report(&self) -> WalLatencyReport {
+		WalLatencyReport {
+			append: self.append_latency.summary(),
+			flush: self.flush_latency.summary(),
+		}
+	}
+
+	// Gets the number of bytes written to the WAL which are held in memory
+	//	and have not yet been written to the file
+	pub fn buffered_bytes(&self) -> usize {
+		self.file.get_ref().buffer().len()
+	}
+
+	// Records that every record has been written to the storage, once none
+	//	are left in the buffer
+	fn mark_written(&self) {
+		if self.buffered_bytes() == 0 {
+			self.sync_state.written.store(self.last_sequence, Ordering::Release);
+			if self.options.use_odsync && cfg!(unix) {
+				self.sync_state.mark_durable(self.last_sequence);
+			}
+		}
+	}
+
+	// Flushes the buffered writes if the flush interval has elapsed since the
+	//	last flush
+	fn flush_if_due(&mut self) -> io::Result<()> {
+		if let Some(interval) = self.options.flush_interval {
+			if self.last_flush.elapsed() >= interval {
+				return self.flush();
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl SyncState {
+	// Records that the records up to a sequence number are durable, and
+	//	delivers them to the subscribers
+	fn mark_durable(&self, sequence: u64) {
+		let durable = self.durable.fetch_max(sequence, Ordering::AcqRel).max(sequence);
+		self.subscriptions.lock().unwrap().publish(durable);
+	}
+}
+
+impl BackgroundSync {
+	// Starts a thread syncing the storage every interval, which marks the
+	//	records written before each sync as durable
+	fn start(mut handle: Box<dyn SyncHandle>, interval: Duration, state: Arc<SyncState>) -> BackgroundSync {
+		let (stop, stopped) = mpsc::channel::<()>();
+		let thread = thread::spawn(move || {
+			while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+				let written = state.written.load(Ordering::Acquire);
+				match handle.sync() {
+					Ok(()) => state.mark_durable(written),
+					Err(e) => { state.error.lock().unwrap().get_or_insert(e); },
+				}
+			}
+		});
+
+		BackgroundSync { stop: Some(stop), thread: Some(thread) }
+	}
+}
+
+impl Drop for BackgroundSync {
+	fn drop(&mut self) {
+		drop(self.stop.take());
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+
+impl RateLimit {
+	// Creates a RateLimit which rejects records beyond either limit, where a
+	//	limit of None is unlimited and a limit of zero rejects every record
+	pub fn new(max_writes_per_sec: Option<u64>, max_bytes_per_sec: Option<u64>) -> RateLimit {
+		RateLimit {
+			writes: max_writes_per_sec.map(TokenBucket::new),
+			bytes: max_bytes_per_sec.map(TokenBucket::new),
+			block: false,
+		}
+	}
+
+	// Makes records beyond the limits wait instead of being rejected
+	pub fn blocking(mut self) -> RateLimit {
+		self.block = true;
+		self
+	}
+}
+
+impl WritePolicy for RateLimit {
+	fn check(&mut self, record: &WalRecord) -> Result<(), WriteRejection> {
+		let bytes = record.logical_len() as f64;
+		let writes_wait = self.writes.as_mut().map_or(Some(Duration::ZERO), |b| b.shortfall(1.0));
+		let bytes_wait = self.bytes.as_mut().map_or(Some(Duration::ZERO), |b| b.shortfall(bytes));
+		// A limit is exceeded if the record must wait, unless blocking and
+		//	the bucket will ever hold enough tokens
+		let exceeded = |wait: Option<Duration>| match wait {
+			Some(wait) => !self.block && wait > Duration::ZERO,
+			None => true,
+		};
+		let exceeded = match (exceeded(writes_wait), exceeded(bytes_wait)) {
+			(true, _) => Some("writes"),
+			(false, true) => Some("bytes"),
+			(false, false) => None,
+		};
+		if let Some(limit) = exceeded {
+			return Err(WriteRejection { reason: format!("throttled: {} per second limit exceeded", limit) });
+		}
+
+		thread::sleep(writes_wait.max(bytes_wait).unwrap_or_default());
+		if let Some(bucket) = self.writes.as_mut() {
+			bucket.take(1.0);
+		}
+		if let Some(bucket) = self.bytes.as_mut() {
+			bucket.take(bytes);
+		}
+
+		Ok(())
+	}
+}
+
+
+impl TokenBucket {
+	fn new(rate: u64) -> TokenBucket {
+		TokenBucket { rate: rate as f64, tokens: rate as f64, last_refill: Instant::now() }
+	}
+
+	fn refill(&mut self) {
+		let now = Instant::now();
+		let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+		self.last_refill = now;
+	}
+
+	fn take(&mut self, tokens: f64) {
+		self.refill();
+		self.tokens -= tokens;
+	}
+
+	// Gets how long until the bucket holds enough tokens for a cost, or None
+	//	if it never will as its rate is zero. A cost larger than the bucket
+	//	only waits for it to be full, and leaves it in debt
+	fn shortfall(&mut self, cost: f64) -> Option<Duration> {
+		self.refill();
+		if self.rate == 0.0 {
+			return None;
+		}
+		let needed = cost.min(self.rate) - self.tokens;
+		match needed > 0.0 {
+			true => Some(Duration::from_secs_f64(needed / self.rate)),
+			false => Some(Duration::ZERO),
+		}
+	}
+}
+
+
+impl<'a> WalRecord<'a> {
+	// Creates a record of the operation held in an entry read from a WAL
+	pub fn from_entry(entry: &'a WALEntry) -> WalRecord<'a> {
+		if let Some(end) = entry.range_end.as_ref() {
+			WalRecord::DeleteRange { start: &entry.key, end, timestamp: entry.timestamp }
+		} else if entry.deleted {
+			WalRecord::Delete { key: &entry.key, timestamp: entry.timestamp }
+		} else {
+			WalRecord::Set {
+				key: &entry.key,
+				value: entry.value.as_ref().unwrap(),
+				meta: entry.meta.as_deref(),
+				timestamp: entry.timestamp,
+			}
+		}
+	}
+
+	// Copies the operation of the record into an entry, as it would be read
+	//	back from the WAL
+	pub fn to_entry(&self) -> WALEntry {
+		let (key, value, meta, range_end, timestamp) = match *self {
+			WalRecord::Set { key, value, meta, timestamp } => (key, Some(value), meta, None, timestamp),
+			WalRecord::Delete { key, timestamp } => (key, None, None, None, timestamp),
+			WalRecord::DeleteRange { start, end, timestamp } => (start, None, None, Some(end), timestamp),
+		};
+		WALEntry {
+			key: key.to_vec(),
+			value: value.map(<[u8]>::to_vec),
+			timestamp,
+			deleted: value.is_none(),
+			range_end: range_end.map(<[u8]>::to_vec),
+			meta: meta.map(<[u8]>::to_vec),
+		}
+	}
+
+	// Gets the number of bytes of keys, values and metadata held by the
+	//	record
+	fn logical_len(&self) -> usize {
+		match self {
+			WalRecord::Set { key, value, meta, .. } => key.len() + value.len() + meta.map_or(0, |m| m.len()),
+			WalRecord::Delete { key, .. } => key.len(),
+			WalRecord::DeleteRange { start, end, .. } => start.len() + end.len(),
+		}
+	}
+
+	// Gets the number of bytes the record is encoded into
+	fn encoded_len(&self) -> usize {
+		match self {
+			WalRecord::Set { key, value, meta: None, .. } => key.len() + value.len() + 33,
+			WalRecord::Set { key, value, meta: Some(meta), .. } => key.len() + value.len() + meta.len() + 41,
+			WalRecord::Delete { key, .. } => key.len() + 25,
+			WalRecord::DeleteRange { start, end, .. } => start.len() + end.len() + 33,
+		}
+	}
+
+	// Encodes the record into a buffer
+	fn encode(&self, buf: &mut Vec<u8>) {
+		match self {
+			WalRecord::Set { key, value, meta: None, timestamp } => {
+				put_len(buf, key.len());
+				buf.extend_from_slice(&SET_KIND.to_le_bytes());
+				put_len(buf, value.len());
+				buf.extend_from_slice(key);
+				buf.extend_from_slice(value);
+				buf.extend_from_slice(&timestamp.to_le_bytes());
+			},
+			WalRecord::Set { key, value, meta: Some(meta), timestamp } => {
+				put_len(buf, key.len());
+				buf.extend_from_slice(&SET_WITH_META_KIND.to_le_bytes());
+				put_len(buf, value.len());
+				put_len(buf, meta.len());
+				buf.extend_from_slice(key);
+				buf.extend_from_slice(value);
+				buf.extend_from_slice(meta);
+				buf.extend_from_slice(&timestamp.to_le_bytes());
+			},
+			WalRecord::Delete { key, timestamp } => {
+				put_len(buf, key.len());
+				buf.extend_from_slice(&DELETE_KIND.to_le_bytes());
+				buf.extend_from_slice(key);
+				buf.extend_from_slice(&timestamp.to_le_bytes());
+			},
+			WalRecord::DeleteRange { start, end, timestamp } => {
+				put_len(buf, start.len());
+				buf.extend_from_slice(&DELETE_RANGE_KIND.to_le_bytes());
+				put_len(buf, end.len());
+				buf.extend_from_slice(start);
+				buf.extend_from_slice(end);
+				buf.extend_from_slice(&timestamp.to_le_bytes());
+			},
+		}
+	}
+}
+
+
+impl fmt::Display for WriteRejection {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "write rejected: {}", self.reason)
+	}
+}
+
+impl Error for WriteRejection {}
+
+impl From<WriteRejection> for io::Error {
+	fn from(rejection: WriteRejection) -> io::Error {
+		io::Error::new(io::ErrorKind::PermissionDenied, rejection)
+	}
+}
+
+
+impl SegmentReport {
+	// Checks whether every record of the file was read without corruption
+	pub fn is_clean(&self) -> bool {
+		self.corruptions.is_empty()
+	}
+}
+
+
+impl WalIoStats {
+	// Gets the bytes written to the file for each logical byte recorded, or
+	//	0 if nothing has been recorded
+	pub fn write_amplification(&self) -> f64 {
+		match self.logical_bytes {
+			0 => 0.0,
+			logical_bytes => self.log_bytes as f64 / logical_bytes as f64,
+		}
+	}
+}
+
+
+impl VerifyReport {
+	// Compares a MemTable with the MemTable replayed from a WAL, such as one
+	//	returned by `from_dir_read_only`
+	pub fn compare(replayed: &MemTable, mem_table: &MemTable) -> VerifyReport {
+		let mut mismatched_keys = Vec::new();
+		let mut expected = replayed.iter_with_tombstones().peekable();
+		let mut actual = mem_table.iter_with_tombstones().peekable();
+		loop {
+			let key = match (expected.peek(), actual.peek()) {
+				(None, None) => break,
+				(Some(e), Some(a)) if e.key == a.key => {
+					let key = (e != a).then(|| e.key.clone());
+					expected.next();
+					actual.next();
+					key
+				},
+				(Some(e), Some(a)) if e.key < a.key => expected.next().map(|e| e.key.clone()),
+				(Some(_), Some(_)) | (None, Some(_)) => actual.next().map(|a| a.key.clone()),
+				(Some(_), None) => expected.next().map(|e| e.key.clone()),
+			};
+			mismatched_keys.extend(key);
+		}
+
+		VerifyReport {
+			wal_entries: replayed.len(),
+			mem_table_entries: mem_table.len(),
+			mismatched_keys,
+			range_tombstones_match: replayed.range_tombstones() == mem_table.range_tombstones(),
+		}
+	}
+
+	// Returns true if the MemTables hold the same records
+	pub fn is_consistent(&self) -> bool {
+		self.wal_entries == self.mem_table_entries
+			&& self.mismatched_keys.is_empty()
+			&& self.range_tombstones_match
+	}
+}
+
+
+impl MemWAL {
+	// Creates a new MemWAL holding no records
+	pub fn new() -> MemWAL {
+		MemWAL::default()
+	}
+
+	// Opens a WAL appending to the log, returning it with the MemTable 
+	//	recovered from the records already written
+	pub fn open(&self, options: WalOptions) -> io::Result<(WAL, MemTable)> {
+		let mut mem_table = MemTable::new();
+		for entry in WALIterator::from_reader(StorageReader::new(&self.storage as &dyn LogStorage)) {
+			replay_entry(&mut mem_table, &entry);
+		}
+
+		let wal = WAL::from_storage(Box::new(self.storage.clone()), options)?;
+		Ok((wal, mem_table))
+	}
+
+	// Gets the number of bytes held in the log
+	pub fn len(&self) -> usize {
+		self.storage.len().unwrap() as usize
+	}
+
+	// Returns true if nothing has been written to the log
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+
+// Lists the WAL files within a directory in the order they were created.
+//
+// Files are ordered by the timestamp parsed from their name, rather than
+//	by the name itself which would put `10.wal` before `9.wal`. Files
+//	without the `wal` extension, such as partially written `.tmp` files, are
+//	ignored. An error of kind `InvalidData` is returned if a WAL file isn't
+//	named by a timestamp, or two files are named by the same timestamp
+pub fn segment_catalog(dir: &Path) -> io::Result<Vec<WalSegment>> {
+	let mut segments = Vec::new();
+	for path in files_with_ext(dir, "wal")? {
+		let timestamp = path.file_stem()
+			.and_then(|stem| stem.to_str())
+			.and_then(|stem| stem.parse::<u128>().ok())
+			.ok_or_else(|| io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("{} is not named by a timestamp", path.display()),
+			))?;
+		segments.push(WalSegment { timestamp, path });
+	}
+
+	segments.sort_by_key(|s| s.timestamp);
+	if let Some(pair) = segments.windows(2).find(|pair| pair[0].timestamp == pair[1].timestamp) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("{} and {} have the same timestamp", pair[0].path.display(), pair[1].path.display()),
+		));
+	}
+
+	Ok(segments)
+}
+
+// Gets the path of the WAL file in a directory named by a timestamp
+fn segment_path(dir: &Path, timestamp: u128) -> PathBuf {
+	dir.join(timestamp.to_string() + ".wal")
+}
+
+
+// Iterates over the entries of the WAL files within a directory, merged in
+//	timestamp order, without taking the directory lock or creating,
+//	rewriting or deleting any files.
+//
+// This allows auditing a directory which is open in another process.
+//	Entries duplicated across files, such as by an interrupted merge, are
+//	only yielded once, and corrupt records are skipped as in recovery
+pub fn open_dir_readonly(dir: &Path) -> io::Result<WalDirIterator> {
+	let iters = open_segments(&segment_catalog(dir)?)?;
+	Ok(WalDirIterator {
+		merge: WalMergeIterator::new(iters).skip_duplicates(),
+		failed: false,
+	})
+}
+
+impl Iterator for WalDirIterator {
+	type Item = io::Result<WALEntry>;
+
+	fn next(&mut self) -> Option<io::Result<WALEntry>> {
+		if self.failed {
+			return None;
+		}
+		let entry = self.merge.next();
+		// A file which couldn't be fully read fails the iteration rather
+		//	than being taken as complete
+		if let Err(e) = check_read_errors(self.merge.iters_mut()) {
+			self.failed = true;
+			return Some(Err(e));
+		}
+		entry.map(Ok)
+	}
+}
+
+
+// Merges the entries of WAL files into a new WAL, replaying them into a 
+//	MemTable.
+//
+// Entries are replayed in timestamp order across the WAL files, so a file
+//	holding older writes can't overwrite newer ones
+fn merge_segments<T: MemTableBackend>(
+	segments: &[WalSegment],
+	recovery: &RecoveryOptions,
+	new_wal: &mut WAL,
+	new_mem_table: &mut T,
+) -> io::Result<()> {
+	let mut replay = |entry: WALEntry| -> io::Result<()> {
+		replay_entry(new_mem_table, &entry);
+		new_wal.append_entry(&entry)?;
+		Ok(())
+	};
+	if recovery.threads > 1 {
+		let decoded = decode_segments(segments, recovery.threads)?;
+		let iters = decoded.into_iter().map(Vec::into_iter).collect();
+		WalMergeIterator::new(iters).skip_duplicates().try_for_each(&mut replay)?;
+	} else {
+		let mut iters = open_segments(segments)?;
+		WalMergeIterator::new(iters.iter_mut().collect()).skip_duplicates().try_for_each(&mut replay)?;
+		check_read_errors(&mut iters)?;
+	}
+	new_wal.flush()
+}
+
+// Opens an iterator over each of the WAL files.
+//
+// A file which can't be opened is an error rather than skipped, as it
+//	would be deleted once the others are merged
+fn open_segments(segments: &[WalSegment]) -> io::Result<Vec<WALIterator>> {
+	segments.iter().map(|s| WALIterator::new(s.path.clone())).collect()
+}
+
+// Fails with the first error which ended reading one of the WAL files
+//	early, so a file which couldn't be fully read isn't taken as complete
+fn check_read_errors(iters: &mut [WALIterator]) -> io::Result<()> {
+	match iters.iter_mut().find_map(|iter| iter.take_read_error()) {
+		Some(e) => Err(e),
+		None => Ok(()),
+	}
+}
+
+// Decodes the entries of WAL files using up to `threads` threads, returning
+//	the entries of each file in the order of the files
+fn decode_segments(segments: &[WalSegment], threads: usize) -> io::Result<Vec<Vec<WALEntry>>> {
+	let next = AtomicUsize::new(0);
+	let mut decoded: Vec<Vec<WALEntry>> = segments.iter().map(|_| Vec::new()).collect();
+
+	thread::scope(|scope| -> io::Result<()> {
+		let workers: Vec<_> = (0..threads.min(segments.len()))
+			.map(|_| scope.spawn(|| -> io::Result<Vec<(usize, Vec<WALEntry>)>> {
+				let mut done = Vec::new();
+				loop {
+					let idx = next.fetch_add(1, Ordering::Relaxed);
+					let Some(segment) = segments.get(idx) else {
+						return Ok(done);
+					};
+					let mut iter = WALIterator::new(segment.path.clone())?;
+					let entries = iter.by_ref().collect();
+					check_read_errors(&mut [iter])?;
+					done.push((idx, entries));
+				}
+			}))
+			.collect();
+
+		for worker in workers {
+			let done = worker.join()
+				.map_err(|_| io::Error::other("a thread decoding WAL files panicked"))??;
+			for (idx, entries) in done {
+				decoded[idx] = entries;
+			}
+		}
+		Ok(())
+	})?;
+
+	Ok(decoded)
+}
+
+
+// Archives or deletes the WAL files which have been merged, according to
+//	the retention policy
+fn retire_segments(
+	dir: &Path,
+	segments: Vec<WalSegment>,
+	retention: &RetentionPolicy,
+	mut sink: Option<&mut dyn ArchiveSink>,
+	clock: &dyn Clock,
+	listeners: &[Arc<dyn EventListener>],
+) -> io::Result<()> {
+	let mut retire = |segment: &WalSegment| -> io::Result<()> {
+		if let Some(sink) = sink.as_deref_mut() {
+			sink.archive(segment)?;
+		}
+		remove_file(&segment.path)?;
+		for listener in listeners {
+			listener.on_segment_retired(segment);
+		}
+		Ok(())
+	};
+
+	if retention.keep_segments == Some(0) {
+		segments.iter().try_for_each(retire)?;
+		return sync_dir(dir);
+	}
+
+	let archive_dir = dir.join("archive");
+	create_dir_all(&archive_dir)?;
+	for segment in segments {
+		let name = segment.path.file_name().ok_or_else(|| io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("{} has no file name", segment.path.display()),
+		))?;
+		rename(&segment.path, archive_dir.join(name))?;
+	}
+
+	// Archived files are ordered from oldest to newest
+	let archived = segment_catalog(&archive_dir)?;
+	let excess = retention.keep_segments.map_or(0, |keep| archived.len().saturating_sub(keep));
+	let now = clock.physical_micros();
+	for (idx, segment) in archived.iter().enumerate() {
+		let expired = retention.keep_for
+			.is_some_and(|keep_for| now.saturating_sub(segment.timestamp) > keep_for.as_micros());
+		if idx < excess || expired {
+			retire(segment)?;
+		}
+	}
+
+	sync_dir(&archive_dir)?;
+	sync_dir(dir)
+}
+
+
+// Appends a length to an encoded record as a fixed width u64, so WALs can
+//	be read on platforms with a different width of usize
+fn put_len(buf: &mut Vec<u8>, len: usize) {
+	buf.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+
+// Gets the options used to open a WAL file for reading and appending, or
+//	for writing at its logical end if it is preallocated
+#[cfg(unix)]
+fn open_options(options: &WalOptions) -> OpenOptions {
+	use std::os::unix::fs::OpenOptionsExt;
+
+	let mut open_options = OpenOptions::new();
+	open_options.read(true).create(true);
+	match options.preallocate_bytes {
+		Some(_) => open_options.write(true),
+		None => open_options.append(true),
+	};
+	if options.use_odsync {
+		open_options.custom_flags(libc::O_DSYNC);
+	}
+	open_options
+}
+
+#[cfg(not(unix))]
+fn open_options(options: &WalOptions) -> OpenOptions {
+	let mut open_options = OpenOptions::new();
+	open_options.read(true).create(true);
+	match options.preallocate_bytes {
+		Some(_) => open_options.write(true),
+		None => open_options.append(true),
+	};
+	open_options
+}
+
+
+// Applies the operation held in a WAL entry to a MemTable or other backend
+fn replay_entry<T: MemTableBackend + ?Sized>(mem_table: &mut T, entry: &WALEntry) {
+	if let Some(end) = entry.range_end.as_ref() {
+		mem_table.delete_range(entry.key.as_slice(), end, entry.timestamp);
+	} else if entry.deleted {
+		mem_table.delete(entry.key.as_slice(), entry.timestamp);
+	} else {
+		mem_table.set_with_meta(entry.key.as_slice(), 
+														entry.value.as_ref().unwrap().as_slice(), 
+														entry.meta.as_deref(),
+														entry.timestamp);
+	}
+}
+
+impl IntoIterator for WAL {
+	type IntoIter = WALIterator<StorageReader<Box<dyn LogStorage>>>;
+	type Item = WALEntry;
+
+	// Transform a WAL into it's iterator form to iterate over WALEntrys 
+	fn into_iter(mut self) -> Self::IntoIter {
+		self.flush().unwrap();
+		let (writer, _) = self.file.into_inner().into_parts();
+		WALIterator::from_reader(StorageReader::new(writer.0))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::assert_eq;
+	use std::fs::{create_dir, remove_dir_all, remove_file, metadata, read, read_dir, write};
+	use std::io::{Cursor, ErrorKind};
+	use std::path::PathBuf;
+	use std::thread;
+	use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+	use rand::Rng;
+	
+	use crate::clock::{Clock, HybridLogicalClock, MockClock, SystemClock};
+	use crate::log_format::LogWriter;
+	use crate::log_storage::MemoryStorage;
+	use crate::mem_table::{MemTable, SetOutcome, StaleWritePolicy};
+	use crate::mem_table_backend::{BTreeMemTable, MemTableBackend};
+	use crate::wal::{open_dir_readonly, segment_catalog, segment_path, ArchiveSink, CommitToken, MemWAL, RateLimit, RecoveryOptions, RetentionPolicy, WalLatencyReport, WalOptions, WalRecord, WalIoStats, WalSegment, WritePolicy, WriteRejection, WAL};
+	use crate::wal_iterator::{WALEntry, WALIterator, WalLimits, WalMergeIterator};
+	
+	// Checks a given WAL entry against the data it is expected to contain
+	fn check_entry(
+		entry: &WALEntry,
+		key: &[u8],
+		value: Option<&[u8]>,
+		timestamp: u128,
+		deleted: bool,
+	) {
+		assert_eq!(entry.key.len(), key.len());
+		assert_eq!(entry.key, key);
+		assert_eq!(entry.timestamp, timestamp);
+		assert_eq!(entry.deleted, deleted);
+
+		if deleted {
+			assert_eq!(entry.value, None)
+		} else {
+			assert_eq!(entry.value.as_ref().unwrap().len(), value.unwrap().len());
+			assert_eq!(entry.value.as_ref().unwrap(), value.unwrap());
+		}
+	}
+
+	#[test]
+	fn test_write_one() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_micros();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", timestamp).unwrap();
+		wal.flush().unwrap();
+
+		if let Ok(wal) = WAL::from_path(wal.path()) {
+			for entry in wal.into_iter() {
+				check_entry(&entry, b"Monday", Some(b"Rejoice"), timestamp, false);
+			}
+		}
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_write_many() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let entries: Vec<(&[u8], Option<&[u8]>)> = vec![
+			(b"Monday", Some(b"Rejoice")),
+			(b"Tuesday", Some(b"Celebrate")),
+			(b"Friday", Some(b"Party"))
+		];
+
+		let timestamp = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.unwrap()
+				.as_micros();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		for e in entries.iter() {
+			wal.set(e.0, e.1.unwrap(), timestamp).unwrap();
+		}
+		wal.flush().unwrap();
+
+		match WAL::from_path(wal.path()) {
+			Err(e) => panic!("can't reopen the WAL: {}", e),
+			Ok(wal) => for (wal_entry, e) in wal.into_iter().zip(entries.iter()) {
+				check_entry(&wal_entry, e.0, e.1, timestamp, false);
+			}
+		}
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_write_delete() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let entries: Vec<(&[u8], Option<&[u8]>)> = vec![
+			(b"Monday", Some(b"Rejoice")),
+			(b"Tuesday", Some(b"Celebrate")),
+			(b"Friday",	Some(b"Party"))
+		];
+
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_micros();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		// Insert
+		for e in entries.iter() {
+			wal.set(e.0, e.1.unwrap(), timestamp).unwrap();
+		}
+		wal.flush().unwrap();
+		// Delete
+		for e in entries.iter() {
+			wal.delete(e.0, timestamp).unwrap();
+		}
+		wal.flush().unwrap();
+
+		match WAL::from_path(wal.path()) {
+			Err(e) => panic!("can't reopen the WAL: {}", e),
+			Ok(wal) => {
+				let double_entries = [&entries[..], &entries[..]].concat();
+				for (idx, (wal_entry, e)) in wal.into_iter().zip(double_entries).enumerate() {
+					if idx < 3 {
+						// First three entries are insertions
+						check_entry(&wal_entry, e.0, e.1, timestamp, false);
+					} else {
+						// Next three entries are deletions
+						check_entry(&wal_entry, e.0, None, timestamp, true);
+					}
+				}
+			}
+		}
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_load_wal_empty() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(mem_table.len(), 0);
+
+		let m = metadata(wal.path()).unwrap();
+		assert_eq!(m.len(), 0);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_load_wal_many() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let entries: Vec<(&[u8], Option<&[u8]>)> = vec![
+			(b"Monday", Some(b"Rejoice")),
+			(b"Tuesday", Some(b"Celebrate")),
+			(b"Friday", Some(b"Party"))
+		];
+
+		let mut wal = WAL::new(&dir).unwrap();
+		for (idx, e) in entries.iter().enumerate() {
+			wal.set(e.0, e.1.unwrap(), idx as u128).unwrap();
+		}
+		wal.flush().unwrap();
+
+		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(mem_table.len(), 3);
+
+		for (idx, (wal_entry, e)) in wal.into_iter().zip(entries.iter()).enumerate() {
+			check_entry(&wal_entry, e.0, e.1, idx as u128, false);
+
+			let table_e = mem_table.get(e.0).unwrap();
+			assert_eq!(table_e.key, e.0);
+			assert_eq!(table_e.value.as_ref().unwrap().as_slice(), e.1.unwrap());
+			assert_eq!(table_e.timestamp, idx as u128);
+		}
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_write_buffered() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let options = WalOptions { buffer_bytes: 64 * 1024, flush_interval: None, use_odsync: false, fsync_interval: None, preallocate_bytes: None };
+		let mut wal = WAL::new_with_options(&dir, options).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+
+		// A set of 6 + 7 byte key and value is framed by 33 bytes of metadata
+		//	and a 7 byte fragment header
+		assert_eq!(wal.buffered_bytes(), 53);
+		assert_eq!(metadata(wal.path()).unwrap().len(), 0);
+
+		wal.flush().unwrap();
+		assert_eq!(wal.buffered_bytes(), 0);
+		assert_eq!(metadata(wal.path()).unwrap().len(), 53);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_wal_accessors() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::from_path(&dir.join("1.wal")).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.close().unwrap();
+
+		let mut wal = WAL::from_path(&dir.join("2.wal")).unwrap();
+		assert_eq!(wal.path(), dir.join("2.wal"));
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		// Buffered writes are counted
+		assert_eq!(wal.len_bytes().unwrap(), 53);
+		let timestamps: Vec<u128> = wal.segments().unwrap().iter().map(|s| s.timestamp).collect();
+		assert_eq!(timestamps, vec![1, 2]);
+
+		let wal = WAL::from_storage(Box::new(MemoryStorage::new()), WalOptions::default()).unwrap();
+		assert_eq!(wal.len_bytes().unwrap(), 0);
+		assert!(wal.segments().unwrap().is_empty());
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_write_flush_interval() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let options = WalOptions { buffer_bytes: 64 * 1024, flush_interval: Some(Duration::ZERO), use_odsync: false, fsync_interval: None, preallocate_bytes: None };
+		let mut wal = WAL::new_with_options(&dir, options).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+
+		assert_eq!(wal.buffered_bytes(), 0);
+		assert_eq!(metadata(wal.path()).unwrap().len(), 53);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_load_wal_delete_range() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		wal.delete_range(b"M", b"N", 20).unwrap();
+		wal.flush().unwrap();
+
+		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert!(mem_table.get(b"Monday").unwrap().deleted);
+		assert!(!mem_table.get(b"Tuesday").unwrap().deleted);
+		assert_eq!(mem_table.range_tombstones().len(), 1);
+
+		let entry = wal.into_iter().nth(2).unwrap();
+		check_entry(&entry, b"M", None, 20, true);
+		assert_eq!(entry.range_end.unwrap(), b"N");
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_load_wal_read_only() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		wal.delete(b"Monday", 20).unwrap();
+		wal.flush().unwrap();
+
+		let mem_table = WAL::from_dir_read_only(&dir).unwrap();
+		assert_eq!(mem_table.len(), 2);
+		assert!(mem_table.get(b"Monday").unwrap().deleted);
+		assert_eq!(mem_table.get(b"Tuesday").unwrap().value.as_ref().unwrap(), b"Celebrate");
+
+		// The original WAL is left in place and no new WAL is created
+		let files: Vec<PathBuf> = read_dir(&dir).unwrap().map(|f| f.unwrap().path()).collect();
+		assert_eq!(files.len(), 1);
+		assert_eq!(files[0].file_name(), wal.path().file_name());
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_load_wal_locked() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let (wal, _) = WAL::from_dir(&dir).unwrap();
+		match WAL::from_dir(&dir) {
+			Err(e) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+			Ok(_) => panic!("the directory should be locked"),
+		}
+
+		// Closing the WAL releases the lock
+		drop(wal);
+		assert!(WAL::from_dir(&dir).is_ok());
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_new_with_clock() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let clock = MockClock::new(1234);
+		let wal = WAL::new_with_clock(&dir, WalOptions::default(), &clock).unwrap();
+		assert_eq!(wal.path(), dir.join("1234.wal"));
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_load_wal_corrupt_block() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		// Enough entries to fill three blocks
+		let mut wal = WAL::new(&dir).unwrap();
+		for i in 0..3000u32 {
+			wal.set(format!("key{:05}", i).as_bytes(), b"value", i as u128).unwrap();
+		}
+		wal.flush().unwrap();
+
+		// Corrupt a byte in the middle of the first block
+		let mut data = read(wal.path()).unwrap();
+		data[1000] ^= 0xFF;
+		write(wal.path(), data).unwrap();
+
+		let (_, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert!(mem_table.len() < 3000);
+		assert!(mem_table.len() > 2000);
+		assert!(mem_table.get(b"key00000").is_some());
+		assert!(mem_table.get(b"key02999").is_some());
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_last_sequence() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		assert_eq!(wal.last_sequence(), 0);
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.delete(b"Monday", 10).unwrap();
+		assert_eq!(wal.last_sequence(), 2);
+		wal.flush().unwrap();
+
+		// Reopening the file continues its sequence
+		let mut wal = WAL::from_path(wal.path()).unwrap();
+		assert_eq!(wal.last_sequence(), 2);
+		wal.set(b"Tuesday", b"Celebrate", 20).unwrap();
+		assert_eq!(wal.last_sequence(), 3);
+		wal.flush().unwrap();
+
+		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(wal.last_sequence(), 3);
+		assert_eq!(mem_table.max_timestamp(), Some(20));
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_load_wal_meta() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set_with_meta(b"Monday", b"Rejoice", b"node-1", 0).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		wal.flush().unwrap();
+
+		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(mem_table.get(b"Monday").unwrap().meta.as_ref().unwrap(), b"node-1");
+		assert_eq!(mem_table.get(b"Tuesday").unwrap().meta, None);
+
+		let entries: Vec<WALEntry> = wal.into_iter().collect();
+		check_entry(&entries[0], b"Monday", Some(b"Rejoice"), 0, false);
+		assert_eq!(entries[0].meta.as_ref().unwrap(), b"node-1");
+		assert_eq!(entries[1].meta, None);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_append_many() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.append_many(&[
+			WalRecord::Set { key: b"Tuesday", value: b"Celebrate", meta: None, timestamp: 10 },
+			WalRecord::Set { key: b"Friday", value: b"Party", meta: Some(b"v1"), timestamp: 21 },
+			WalRecord::Delete { key: b"Monday", timestamp: 30 },
+			WalRecord::DeleteRange { start: b"A", end: b"B", timestamp: 40 },
+		]).unwrap();
+		assert_eq!(wal.last_sequence(), 5);
+		wal.flush().unwrap();
+
+		let entries: Vec<WALEntry> = WAL::from_path(wal.path()).unwrap().into_iter().collect();
+		assert_eq!(entries.len(), 5);
+		check_entry(&entries[0], b"Monday", Some(b"Rejoice"), 0, false);
+		check_entry(&entries[1], b"Tuesday", Some(b"Celebrate"), 10, false);
+		check_entry(&entries[2], b"Friday", Some(b"Party"), 21, false);
+		assert_eq!(entries[2].meta.as_ref().unwrap(), b"v1");
+		check_entry(&entries[3], b"Monday", None, 30, true);
+		check_entry(&entries[4], b"A", None, 40, true);
+		assert_eq!(entries[4].range_end.as_ref().unwrap(), b"B");
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_append_many_torn() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.append_many(&[
+			WalRecord::Set { key: b"Tuesday", value: b"Celebrate", meta: None, timestamp: 10 },
+			WalRecord::Set { key: b"Friday", value: b"Party", meta: None, timestamp: 21 },
+		]).unwrap();
+		wal.flush().unwrap();
+
+		// Cut the batch short, as if the process crashed while writing it
+		let data = read(wal.path()).unwrap();
+		write(wal.path(), &data[..data.len() - 20]).unwrap();
+
+		let (_, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(mem_table.len(), 1);
+		assert!(mem_table.get(b"Monday").is_some());
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_close() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let (mut wal, _) = WAL::from_dir(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		assert!(wal.buffered_bytes() > 0);
+		wal.close().unwrap();
+
+		// The lock is released so the directory can be opened again
+		let (_, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(mem_table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Rejoice");
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_odsync() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let options = WalOptions { use_odsync: true, ..WalOptions::default() };
+		let mut wal = WAL::new_with_options(&dir, options).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.flush().unwrap();
+
+		let entries: Vec<WALEntry> = WAL::from_path(wal.path()).unwrap().into_iter().collect();
+		assert_eq!(entries.len(), 1);
+		check_entry(&entries[0], b"Monday", Some(b"Rejoice"), 0, false);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_preallocate() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let options = WalOptions { preallocate_bytes: Some(64 * 1024), ..WalOptions::default() };
+		let mut wal = WAL::new_with_options(&dir, options.clone()).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.flush().unwrap();
+		let path = wal.path().to_owned();
+		assert_eq!(metadata(&path).unwrap().len(), 64 * 1024);
+
+		// Records reaching the end of the file have more allocated after them
+		wal.set(b"Tuesday", &[0; 70 * 1024], 10).unwrap();
+		wal.flush().unwrap();
+		let len = wal.len_bytes().unwrap();
+		assert_eq!(metadata(&path).unwrap().len(), len + 64 * 1024);
+
+		// Without closing the WAL its file keeps the zeros after the records,
+		//	which end the log
+		drop(wal);
+		let entries: Vec<WALEntry> = WAL::from_path(&path).unwrap().into_iter().collect();
+		assert_eq!(entries.len(), 2);
+
+		// Reopening it continues after the last record
+		let mut wal = WAL::from_path_with_options(&path, options).unwrap();
+		assert_eq!((wal.last_sequence(), wal.len_bytes().unwrap()), (2, len));
+		wal.delete(b"Monday", 20).unwrap();
+		wal.close().unwrap();
+		assert!(metadata(&path).unwrap().len() < len + 64);
+
+		let table = WAL::from_dir_read_only(&dir).unwrap();
+		assert!(table.get(b"Monday").unwrap().deleted);
+		assert_eq!(table.get(b"Tuesday").unwrap().value.as_deref(), Some(&[0; 70 * 1024][..]));
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_memory_storage() {
+		let storage = MemoryStorage::new();
+
+		let mut wal = WAL::from_storage(Box::new(storage.clone()), WalOptions::default()).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.delete(b"Tuesday", 10).unwrap();
+		wal.close().unwrap();
+		assert_eq!(storage.contents().len(), 53 + 39);
+
+		// Reopening the storage continues the sequence of its records
+		let mut wal = WAL::from_storage(Box::new(storage.clone()), WalOptions::default()).unwrap();
+		assert_eq!(wal.last_sequence(), 2);
+		wal.set(b"Friday", b"Party", 21).unwrap();
+
+		let entries: Vec<WALEntry> = wal.into_iter().collect();
+		assert_eq!(entries.len(), 3);
+		check_entry(&entries[0], b"Monday", Some(b"Rejoice"), 0, false);
+		check_entry(&entries[1], b"Tuesday", None, 10, true);
+		check_entry(&entries[2], b"Friday", Some(b"Party"), 21, false);
+	}
+
+	#[test]
+	fn test_io_stats() {
+		let storage = MemoryStorage::new();
+
+		let mut wal = WAL::from_storage(Box::new(storage.clone()), WalOptions::default()).unwrap();
+		assert_eq!(wal.io_stats().write_amplification(), 0.0);
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.delete(b"Tuesday", 10).unwrap();
+
+		// Each record is written with a 7 byte fragment header
+		let stats = wal.io_stats();
+		assert_eq!(stats, WalIoStats { logical_bytes: 20, record_bytes: 78, log_bytes: 92 });
+		assert_eq!(stats.write_amplification(), 4.6);
+		wal.close().unwrap();
+		assert_eq!(storage.contents().len() as u64, stats.log_bytes);
+	}
+
+	#[test]
+	fn test_latency_report() {
+		let mut wal = WAL::from_storage(Box::new(MemoryStorage::new()), WalOptions::default()).unwrap();
+		assert_eq!(wal.latency_report(), WalLatencyReport::default());
+
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.append_many(&[
+			WalRecord::Set { key: b"Tuesday", value: b"Celebrate", meta: None, timestamp: 10 },
+			WalRecord::Delete { key: b"Friday", timestamp: 21 },
+		]).unwrap();
+		wal.flush().unwrap();
+
+		// A batch is timed as one append
+		let report = wal.latency_report();
+		assert_eq!(report.append.count, 2);
+		assert_eq!(report.flush.count, 1);
+		assert!(report.append.p50 <= report.append.p99 && report.append.p99 <= report.append.max);
+	}
+
+	#[test]
+	fn test_fsync_interval() {
+		let options = WalOptions {
+			flush_interval: Some(Duration::ZERO),
+			fsync_interval: Some(Duration::from_millis(1)),
+			..WalOptions::default()
+		};
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new_with_options(&dir, options).unwrap();
+		assert_eq!(wal.last_durable_sequence(), 0);
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+
+		let started = Instant::now();
+		while wal.last_durable_sequence() < 2 {
+			assert!(started.elapsed() < Duration::from_secs(10), "the WAL was never synced");
+			thread::sleep(Duration::from_millis(1));
+		}
+		assert_eq!(wal.last_durable_sequence(), 2);
+		wal.close().unwrap();
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_empty_value() {
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let (mut wal, _) = WAL::from_dir(&dir).unwrap();
+		wal.set(b"Monday", b"", 0).unwrap();
+		wal.append(&WalRecord::Set { key: b"Tuesday", value: b"", meta: Some(b""), timestamp: 10 }).unwrap();
+		wal.delete(b"Friday", 21).unwrap();
+		wal.close().unwrap();
+
+		// Empty values are recovered as values rather than tombstones
+		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
+		drop(wal);
+		assert_eq!(mem_table.get(b"Monday").unwrap().value, Some(Vec::new()));
+		assert_eq!(mem_table.get(b"Tuesday").unwrap().meta, Some(Vec::new()));
+		assert_eq!(mem_table.get(b"Friday").unwrap().value, None);
+		assert_eq!(mem_table.iter().count(), 2);
+
+		// And survive a snapshot
+		let path = dir.join("snapshot");
+		mem_table.export(&path).unwrap();
+		let imported = MemTable::import(&path).unwrap();
+		assert!(imported.iter_with_tombstones().eq(mem_table.iter_with_tombstones()));
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_delete_prefix() {
+		let mem_wal = MemWAL::new();
+		let (mut wal, _) = mem_wal.open(WalOptions::default()).unwrap();
+		wal.set(b"users/Monday", b"Rejoice", 0).unwrap();
+		wal.delete_prefix(b"users/", 10).unwrap();
+		assert_eq!(wal.delete_prefix(b"", 21).unwrap_err().kind(), ErrorKind::InvalidInput);
+		assert_eq!(wal.last_sequence(), 2);
+		wal.close().unwrap();
+
+		let (_, mem_table) = mem_wal.open(WalOptions::default()).unwrap();
+		assert!(mem_table.get(b"users/Monday").unwrap().deleted);
+		assert_eq!(mem_table.range_tombstones()[0].end, b"users0");
+	}
+
+	#[test]
+	fn test_mem_wal() {
+		let mem_wal = MemWAL::new();
+
+		let (mut wal, mem_table) = mem_wal.open(WalOptions::default()).unwrap();
+		assert_eq!(mem_table.len(), 0);
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.delete_range(b"A", b"B", 5).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		wal.delete(b"Monday", 21).unwrap();
+		// Writes are buffered until flushed, as with a file
+		assert!(mem_wal.is_empty());
+		wal.close().unwrap();
+
+		let (wal, mem_table) = mem_wal.open(WalOptions::default()).unwrap();
+		assert_eq!(wal.last_sequence(), 4);
+		assert_eq!(mem_table.len(), 2);
+		assert!(mem_table.get(b"Monday").unwrap().deleted);
+		assert_eq!(mem_table.get(b"Tuesday").unwrap().value.as_ref().unwrap(), b"Celebrate");
+		assert_eq!(mem_table.range_tombstones().len(), 1);
+	}
+
+	#[test]
+	fn test_segment_catalog() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		for name in ["100.wal", "9.wal", "10.wal", "5.wal.tmp", "LOCK"] {
+			write(dir.join(name), b"").unwrap();
+		}
+		let timestamps: Vec<u128> = segment_catalog(&dir).unwrap().iter().map(|s| s.timestamp).collect();
+		assert_eq!(timestamps, vec![9, 10, 100]);
+
+		write(dir.join("010.wal"), b"").unwrap();
+		assert_eq!(segment_catalog(&dir).unwrap_err().kind(), ErrorKind::InvalidData);
+		remove_file(dir.join("010.wal")).unwrap();
+
+		write(dir.join("latest.wal"), b"").unwrap();
+		assert_eq!(segment_catalog(&dir).unwrap_err().kind(), ErrorKind::InvalidData);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_merge_segments_by_timestamp() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::from_path(&dir.join("1.wal")).unwrap();
+		wal.set(b"Monday", b"Blues", 20).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 30).unwrap();
+		wal.close().unwrap();
+
+		// A later file holding an older write of the same key
+		let mut wal = WAL::from_path(&dir.join("2.wal")).unwrap();
+		wal.set(b"Monday", b"Rejoice", 10).unwrap();
+		wal.set(b"Friday", b"Party", 25).unwrap();
+		wal.set(b"Sunday", b"Rest", 5).unwrap();
+		wal.close().unwrap();
+
+		let iters = vec![
+			WALIterator::new(dir.join("1.wal")).unwrap(),
+			WALIterator::new(dir.join("2.wal")).unwrap(),
+		];
+		let mut merge = WalMergeIterator::new(iters);
+		let timestamps: Vec<u128> = merge.by_ref().map(|e| e.timestamp).collect();
+		assert_eq!(timestamps, vec![10, 20, 25, 5, 30]);
+		assert_eq!(merge.out_of_order(), 1);
+
+		let (_, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(mem_table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Blues");
+		assert_eq!(mem_table.len(), 4);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_recovery_skips_duplicates() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::from_path(&dir.join("1.wal")).unwrap();
+		wal.set(b"Monday", b"Rejoice", 10).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 20).unwrap();
+		wal.delete(b"Monday", 30).unwrap();
+		wal.close().unwrap();
+
+		// A merge which crashed part way, having copied the first two records
+		let mut wal = WAL::from_path(&dir.join("2.wal")).unwrap();
+		wal.set(b"Monday", b"Rejoice", 10).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 20).unwrap();
+		// A different write with the same timestamp is kept
+		wal.set(b"Friday", b"Party", 20).unwrap();
+		wal.close().unwrap();
+
+		let iters = vec![
+			WALIterator::new(dir.join("1.wal")).unwrap(),
+			WALIterator::new(dir.join("2.wal")).unwrap(),
+		];
+		let mut merge = WalMergeIterator::new(iters).skip_duplicates();
+		assert_eq!(merge.by_ref().count(), 4);
+		assert_eq!(merge.duplicates(), 2);
+
+		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(wal.last_sequence(), 4);
+		assert_eq!(mem_table.len(), 3);
+		assert!(mem_table.get(b"Monday").unwrap().deleted);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_parallel_recovery() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		for segment in 1..=5u128 {
+			let mut wal = WAL::from_path(&dir.join(format!("{}.wal", segment))).unwrap();
+			wal.set(b"Monday", format!("Rejoice {}", segment).as_bytes(), segment * 10).unwrap();
+			wal.set(format!("Day {}", segment).as_bytes(), b"Celebrate", segment * 10 + 1).unwrap();
+			wal.delete(b"Friday", 100 - segment).unwrap();
+			wal.close().unwrap();
+		}
+		let expected = WAL::from_dir_read_only(&dir).unwrap();
 
-		Ok((new_wal, new_mem_table))
+		let recovery = RecoveryOptions { threads: 3, ..RecoveryOptions::default() };
+		let (wal, mem_table) = WAL::from_dir_with_recovery(&dir, &recovery, &RetentionPolicy::default(), None, &SystemClock).unwrap();
+		let entries: Vec<_> = mem_table.iter_with_tombstones().collect();
+		let expected_entries: Vec<_> = expected.iter_with_tombstones().collect();
+		assert_eq!(entries, expected_entries);
+		assert_eq!(mem_table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"Rejoice 5");
+		assert_eq!(wal.into_iter().count(), 15);
+		assert_eq!(segment_catalog(&dir).unwrap().len(), 1);
+
+		remove_dir_all(&dir).unwrap();
 	}
 
-	// Creates a new WAL timestamped with the current time in the directory
-	pub fn new(dir: &Path) -> io::Result<WAL> {
-		let timestamp = SystemTime::now()
-			.duration_since(UNIX_EPOCH)
-			.unwrap()
-			.as_micros();
+	#[test]
+	fn test_recover_into_backend() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
 
-		let path = Path::new(dir).join(timestamp.to_string() + ".wal");
-		WAL::from_path(&path)
-	}
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set_with_meta(b"Tuesday", b"Celebrate", b"v1", 10).unwrap();
+		wal.set(b"Friday", b"Party", 21).unwrap();
+		wal.delete_range(b"F", b"G", 25).unwrap();
+		wal.close().unwrap();
 
-	// Creates a WAL using the provided file path
-	pub fn from_path(path: &Path) -> io::Result<WAL> {
-		let file = OpenOptions::new().append(true).create(true).open(path)?;
-		let file = BufWriter::new(file);
+		let (_, table) = WAL::from_dir_into(
+			&dir,
+			BTreeMemTable::new(),
+			&RecoveryOptions::default(),
+			&RetentionPolicy::default(),
+			None,
+			&SystemClock,
+		).unwrap();
+		let table = table.freeze();
+		let keys: Vec<&[u8]> = table.iter().map(|e| e.key.as_slice()).collect();
+		assert_eq!(keys, vec![b"Monday" as &[u8], b"Tuesday"]);
+		assert_eq!(table.get(b"Tuesday").unwrap().meta.as_ref().unwrap(), b"v1");
+		assert!(table.get(b"Friday").unwrap().deleted);
 
-		Ok(WAL {
-			path: path.to_owned(),
-			file: file,
-		})
+		remove_dir_all(&dir).unwrap();
 	}
 
-	// Records the set operation on a key-value pair to the WAL
-	pub fn set(&mut self, key: &[u8], value: &[u8], timestamp: u128) -> io::Result<()> {
-		self.file.write_all(&key.len().to_le_bytes())?;
-		self.file.write_all(&(false as u8).to_le_bytes())?;
-		self.file.write_all(&value.len().to_le_bytes())?;
-		self.file.write_all(&key)?;
-		self.file.write_all(&value)?;
-		self.file.write_all(&timestamp.to_le_bytes())?;
+	#[test]
+	fn test_verify() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
 
-		Ok(())
-	}
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		wal.delete(b"Friday", 21).unwrap();
+		wal.delete_range(b"A", b"B", 25).unwrap();
+		wal.close().unwrap();
 
-	// Record a delete operation on a key to the WAL
-	pub fn delete(&mut self, key: &[u8], timestamp: u128) -> io::Result<()> {
-		self.file.write_all(&key.len().to_le_bytes())?;
-		self.file.write_all(&(true as u8).to_le_bytes())?;
-		self.file.write_all(&key)?;
-		self.file.write_all(&timestamp.to_le_bytes())?;
+		let (mut wal, mut mem_table) = WAL::from_dir(&dir).unwrap();
+		assert!(wal.verify(&mem_table).unwrap().is_consistent());
 
-		Ok(())
-	}
+		// Lose one record and change another, as a faulty recovery might
+		mem_table.set(b"Monday", b"Blues", 30);
+		mem_table.set(b"Sunday", b"Rest", 30);
+		let report = wal.verify(&mem_table).unwrap();
+		assert!(!report.is_consistent());
+		assert_eq!(report.wal_entries, 3);
+		assert_eq!(report.mem_table_entries, 4);
+		assert_eq!(report.mismatched_keys, vec![b"Monday".to_vec(), b"Sunday".to_vec()]);
+		assert!(report.range_tombstones_match);
 
-	pub fn flush(&mut self) -> io::Result<()> {
-		self.file.flush()
+		remove_dir_all(&dir).unwrap();
 	}
-}
 
-impl IntoIterator for WAL {
-	type IntoIter = WALIterator;
-	type Item = WALEntry;
+	#[test]
+	fn test_absurd_lengths() {
+		// Records claiming lengths far beyond their size are skipped without
+		//	allocating
+		let mut record = u64::MAX.to_le_bytes().to_vec();
+		record.push(0);
+		record.extend_from_slice(&u64::MAX.to_le_bytes());
+		record.extend_from_slice(b"Monday");
 
-	// Transform a WAL into it's iterator form to iterate over WALEntrys 
-	fn into_iter(self) -> WALIterator {
-		WALIterator::new(self.path).unwrap()
+		let mut batch = 2u64.to_le_bytes().to_vec();
+		batch.push(4);
+		batch.extend_from_slice(&u64::MAX.to_le_bytes());
+
+		let mut writer = LogWriter::new(Vec::new(), 0);
+		writer.add_record(&record).unwrap();
+		writer.add_record(&batch).unwrap();
+		let mut valid = Vec::new();
+		WalRecord::Set { key: b"Monday", value: b"Rejoice", meta: None, timestamp: 0 }.encode(&mut valid);
+		writer.add_record(&valid).unwrap();
+
+		let mut iter = WALIterator::from_reader(writer.get_ref().as_slice());
+		let entries: Vec<WALEntry> = iter.by_ref().collect();
+		assert_eq!(entries.len(), 1);
+		check_entry(&entries[0], b"Monday", Some(b"Rejoice"), 0, false);
+
+		let corruptions = iter.corruptions();
+		assert_eq!(corruptions.len(), 2);
+		assert_eq!(corruptions[0].offset, 0);
+		assert_eq!(corruptions[0].reason, "key length exceeds the limit");
+		assert_eq!(corruptions[1].offset, 7 + record.len() as u64);
+		assert_eq!(corruptions[1].reason, "record is truncated");
 	}
-}
 
+	#[test]
+	fn test_configured_limits() {
+		let storage = MemoryStorage::new();
+		let mut wal = WAL::from_storage(Box::new(storage.clone()), WalOptions::default()).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Tuesday", &[b'x'; 100], 10).unwrap();
+		wal.close().unwrap();
 
-#[cfg(test)]
-mod tests {
-	use std::assert_eq;
-	use std::fs::{create_dir, remove_dir_all, metadata};
-	use std::path::PathBuf;
-	use std::time::{SystemTime, UNIX_EPOCH};
-	use rand::Rng;
-	
-	use crate::wal::WAL;
-	use crate::wal_iterator::WALEntry;
-	
-	// Checks a given WAL entry against the data it is expected to contain
-	fn check_entry(
-		entry: &WALEntry,
-		key: &[u8],
-		value: Option<&[u8]>,
-		timestamp: u128,
-		deleted: bool,
-	) {
-		assert_eq!(entry.key.len(), key.len());
-		assert_eq!(entry.key, key);
-		assert_eq!(entry.timestamp, timestamp);
-		assert_eq!(entry.deleted, deleted);
+		let limits = WalLimits { max_key_len: 16, max_value_len: 64 };
+		let contents = storage.contents();
+		let mut iter = WALIterator::from_reader(contents.as_slice()).with_limits(limits);
+		assert_eq!(iter.by_ref().count(), 1);
+		assert_eq!(iter.corruptions()[0].offset, 53);
 
-		if deleted {
-			assert_eq!(entry.value, None)
-		} else {
-			assert_eq!(entry.value.as_ref().unwrap().len(), value.unwrap().len());
-			assert_eq!(entry.value.as_ref().unwrap(), value.unwrap());
+		let error: std::io::Error = iter.corruptions()[0].clone().into();
+		assert_eq!(error.kind(), ErrorKind::InvalidData);
+	}
+
+	// Records the timestamps of the WAL files it archives
+	struct RecordingSink(Vec<u128>);
+
+	impl ArchiveSink for RecordingSink {
+		fn archive(&mut self, segment: &WalSegment) -> std::io::Result<()> {
+			self.0.push(segment.timestamp);
+			Ok(())
 		}
 	}
 
 	#[test]
-	fn test_write_one() {
+	fn test_retention() {
 		let mut rng = rand::thread_rng();
 		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
 		create_dir(&dir).unwrap();
 
-		let timestamp = SystemTime::now()
-			.duration_since(UNIX_EPOCH)
-			.unwrap()
-			.as_micros();
+		let retention = RetentionPolicy { keep_segments: Some(2), keep_for: None };
+		let mut sink = RecordingSink(Vec::new());
+		let mut merged = Vec::new();
+		for day in [b"Monday" as &[u8], b"Tuesday", b"Friday", b"Sunday"] {
+			let (mut wal, _) = WAL::from_dir_with_retention(&dir, &retention, Some(&mut sink), &SystemClock).unwrap();
+			merged.push(segment_catalog(&dir).unwrap()[0].timestamp);
+			wal.set(day, b"Rejoice", 0).unwrap();
+			wal.close().unwrap();
+		}
+		let (_, mem_table) = WAL::from_dir_with_retention(&dir, &retention, Some(&mut sink), &SystemClock).unwrap();
+		assert_eq!(mem_table.len(), 4);
 
-		let mut wal = WAL::new(&dir).unwrap();
-		wal.set(b"Monday", b"Rejoice", timestamp).unwrap();
-		wal.flush().unwrap();
+		// The two newest merged files are kept, the older ones are archived
+		let archived: Vec<u128> = segment_catalog(&dir.join("archive")).unwrap().iter().map(|s| s.timestamp).collect();
+		assert_eq!(archived, merged[2..]);
+		assert_eq!(sink.0, merged[..2]);
+
+		// A policy keeping files for no time archives everything
+		let retention = RetentionPolicy { keep_segments: None, keep_for: Some(Duration::ZERO) };
+		let _ = WAL::from_dir_with_retention(&dir, &retention, Some(&mut sink), &SystemClock).unwrap();
+		assert_eq!(segment_catalog(&dir.join("archive")).unwrap().len(), 0);
+		assert_eq!(sink.0.len(), 5);
 
-		if let Ok(wal) = WAL::from_path(&wal.path) {
-			for entry in wal.into_iter() {
-				check_entry(&entry, b"Monday", Some(b"Rejoice"), timestamp, false);
-			}
-		}
 		remove_dir_all(&dir).unwrap();
 	}
 
 	#[test]
-	fn test_write_many() {
+	fn test_recovery_with_clock() {
 		let mut rng = rand::thread_rng();
 		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
 		create_dir(&dir).unwrap();
 
-		let entries: Vec<(&[u8], Option<&[u8]>)> = vec![
-			(b"Monday", Some(b"Rejoice")),
-			(b"Tuesday", Some(b"Celebrate")),
-			(b"Friday", Some(b"Party"))
-		];
+		let clock = MockClock::new(1_000_000);
+		let mut wal = WAL::new_with_clock(&dir, WalOptions::default(), &clock).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.close().unwrap();
 
-		let timestamp = SystemTime::now()
-				.duration_since(UNIX_EPOCH)
-				.unwrap()
-				.as_micros();
+		// The merged WAL is named after the clock, unless it is behind the
+		//	file being merged
+		clock.set(5);
+		let retention = RetentionPolicy { keep_segments: None, keep_for: Some(Duration::from_secs(10)) };
+		let (wal, _) = WAL::from_dir_with_retention(&dir, &retention, None, &clock).unwrap();
+		assert_eq!(wal.path(), dir.join("1000001.wal"));
+		wal.close().unwrap();
+		clock.set(3_000_000);
+		let (wal, _) = WAL::from_dir_with_retention(&dir, &retention, None, &clock).unwrap();
+		assert_eq!(wal.path(), dir.join("3000000.wal"));
+		wal.close().unwrap();
 
-		let mut wal = WAL::new(&dir).unwrap();
-		for e in entries.iter() {
-			wal.set(e.0, e.1.unwrap(), timestamp).unwrap();
-		}
-		wal.flush().unwrap();
+		// Archived files expire by the time of the clock
+		let archived = |dir: &PathBuf| -> Vec<u128> {
+			segment_catalog(&dir.join("archive")).unwrap().iter().map(|s| s.timestamp).collect()
+		};
+		assert_eq!(archived(&dir), vec![1_000_000, 1_000_001]);
+		clock.set(11_500_000);
+		let (wal, table) = WAL::from_dir_with_retention(&dir, &retention, None, &clock).unwrap();
+		assert_eq!(archived(&dir), vec![3_000_000]);
+		assert_eq!(table.get(b"Monday").unwrap().value.as_deref(), Some(&b"Rejoice"[..]));
+		wal.close().unwrap();
 
-		match WAL::from_path(&wal.path) {
-			Err(_) => assert!(false),
-			Ok(wal) => for (wal_entry, e) in wal.into_iter().zip(entries.iter()) {
-				check_entry(&wal_entry, e.0, e.1, timestamp, false);
-			}
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_recovery_with_hlc() {
+		let mut rng = rand::thread_rng();
+		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		// A WAL file written an hour ago, and one written now
+		let clock = HybridLogicalClock::new();
+		let now = SystemClock.now();
+		for timestamp in [now - 3_600_000_000, now] {
+			let mut wal = WAL::from_path(&segment_path(&dir, timestamp)).unwrap();
+			wal.set(b"Monday", b"Rejoice", clock.now()).unwrap();
+			wal.close().unwrap();
 		}
 
+		// The merged WAL is named by the physical time of the clock, and only
+		//	the archived file older than the retention expires
+		let retention = RetentionPolicy { keep_segments: None, keep_for: Some(Duration::from_secs(600)) };
+		let (wal, table) = WAL::from_dir_with_retention(&dir, &retention, None, &clock).unwrap();
+		let merged = segment_catalog(&dir).unwrap()[0].timestamp;
+		assert!((now + 1..=SystemClock.now() + 1).contains(&merged));
+		let archived: Vec<u128> = segment_catalog(&dir.join("archive")).unwrap().iter().map(|s| s.timestamp).collect();
+		assert_eq!(archived, vec![now]);
+		assert!(table.get(b"Monday").is_some());
+		wal.close().unwrap();
+
 		remove_dir_all(&dir).unwrap();
 	}
 
 	#[test]
-	fn test_write_delete() {
+	fn test_recovery_surfaces_read_errors() {
 		let mut rng = rand::thread_rng();
 		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
 		create_dir(&dir).unwrap();
 
-		let entries: Vec<(&[u8], Option<&[u8]>)> = vec![
-			(b"Monday", Some(b"Rejoice")),
-			(b"Tuesday", Some(b"Celebrate")),
-			(b"Friday",	Some(b"Party"))
-		];
-
-		let timestamp = SystemTime::now()
-			.duration_since(UNIX_EPOCH)
-			.unwrap()
-			.as_micros();
+		let mut wal = WAL::from_path(&dir.join("1.wal")).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.close().unwrap();
+		// A segment which opens but can't be read
+		create_dir(dir.join("2.wal")).unwrap();
 
-		let mut wal = WAL::new(&dir).unwrap();
-		// Insert
-		for e in entries.iter() {
-			wal.set(e.0, e.1.unwrap(), timestamp).unwrap();
-		}
-		wal.flush().unwrap();
-		// Delete
-		for e in entries.iter() {
-			wal.delete(e.0, timestamp).unwrap();
-		}
-		wal.flush().unwrap();
+		assert!(WAL::from_dir(&dir).is_err());
+		let recovery = RecoveryOptions { threads: 2, ..RecoveryOptions::default() };
+		assert!(WAL::from_dir_with_recovery(&dir, &recovery, &RetentionPolicy::default(), None, &SystemClock).is_err());
+		assert!(WAL::from_dir_read_only(&dir).is_err());
 
-		match WAL::from_path(&wal.path) {
-			Err(_) => assert!(false),
-			Ok(wal) => {
-				let double_entries = [&entries[..], &entries[..]].concat();
-				for (idx, (wal_entry, e)) in wal.into_iter().zip(double_entries).enumerate() {
-					if idx < 3 {
-						// First three entries are insertions
-						check_entry(&wal_entry, e.0, e.1, timestamp, false);
-					} else {
-						// Next three entries are deletions
-						check_entry(&wal_entry, e.0, None, timestamp, true);
-					}
-				}
-			}
-		}
+		// Nothing is merged or deleted
+		assert_eq!(segment_catalog(&dir).unwrap().len(), 2);
+		assert_eq!(WALIterator::new(dir.join("1.wal")).unwrap().count(), 1);
 
 		remove_dir_all(&dir).unwrap();
 	}
 
 	#[test]
-	fn test_load_wal_empty() {
+	fn test_verify_dir() {
 		let mut rng = rand::thread_rng();
 		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
 		create_dir(&dir).unwrap();
 
-		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
-		assert_eq!(mem_table.len(), 0);
+		let mut wal = WAL::from_path(&dir.join("1.wal")).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.delete(b"Tuesday", 10).unwrap();
+		wal.close().unwrap();
 
-		let m = metadata(wal.path).unwrap();
-		assert_eq!(m.len(), 0);
+		let mut wal = WAL::from_path(&dir.join("2.wal")).unwrap();
+		wal.set(b"Monday", b"Blues", 15).unwrap();
+		wal.set(b"Friday", b"Party", 21).unwrap();
+		wal.close().unwrap();
+		let mut data = read(dir.join("2.wal")).unwrap();
+		let last = data.len() - 1;
+		data[last] ^= 0xFF;
+		write(dir.join("2.wal"), &data).unwrap();
+
+		let reports = WAL::verify_dir(&dir).unwrap();
+		assert_eq!(reports.len(), 2);
+		assert!(reports[0].is_clean());
+		assert_eq!((reports[0].entries, reports[0].first_timestamp, reports[0].last_timestamp), (2, Some(0), Some(10)));
+		assert_eq!((reports[1].entries, reports[1].first_timestamp, reports[1].last_timestamp), (1, Some(15), Some(15)));
+		assert_eq!(reports[1].corruptions[0].offset, 51);
+		assert_eq!(reports[1].corruptions[0].reason, "fragment checksum mismatch");
+
+		// Nothing is merged or deleted
+		assert_eq!(segment_catalog(&dir).unwrap().len(), 2);
 
 		remove_dir_all(&dir).unwrap();
 	}
 
 	#[test]
-	fn test_load_wal_many() {
+	fn test_restore_to() {
 		let mut rng = rand::thread_rng();
 		let dir = PathBuf::from(format!("./{}/", rng.gen::<u32>()));
 		create_dir(&dir).unwrap();
 
-		let entries: Vec<(&[u8], Option<&[u8]>)> = vec![
-			(b"Monday", Some(b"Rejoice")),
-			(b"Tuesday", Some(b"Celebrate")),
-			(b"Friday", Some(b"Party"))
-		];
+		let retention = RetentionPolicy { keep_segments: None, keep_for: None };
+		let writes = [(b"Monday" as &[u8], 0), (b"Tuesday", 10), (b"Monday", 21), (b"Friday", 30)];
+		for (key, timestamp) in writes {
+			let (mut wal, _) = WAL::from_dir_with_retention(&dir, &retention, None, &SystemClock).unwrap();
+			wal.set(key, format!("{}", timestamp).as_bytes(), timestamp).unwrap();
+			wal.close().unwrap();
+		}
+		let (mut wal, _) = WAL::from_dir_with_retention(&dir, &retention, None, &SystemClock).unwrap();
+		wal.delete(b"Tuesday", 40).unwrap();
+		wal.close().unwrap();
 
-		let mut wal = WAL::new(&dir).unwrap();
-		for (idx, e) in entries.iter().enumerate() {
-			wal.set(e.0, e.1.unwrap(), idx as u128).unwrap();
+		let mem_table = WAL::restore_to(&dir, 15).unwrap();
+		assert_eq!(mem_table.len(), 2);
+		assert_eq!(mem_table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"0");
+		assert_eq!(mem_table.get(b"Tuesday").unwrap().value.as_ref().unwrap(), b"10");
+
+		let mem_table = WAL::restore_to(&dir, 40).unwrap();
+		assert_eq!(mem_table.len(), 3);
+		assert_eq!(mem_table.get(b"Monday").unwrap().value.as_ref().unwrap(), b"21");
+		assert!(mem_table.get(b"Tuesday").unwrap().deleted);
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	// Limits the total bytes of the keys and values written under a prefix
+	struct QuotaPolicy {
+		prefix: &'static [u8],
+		remaining: usize,
+	}
+
+	impl WritePolicy for QuotaPolicy {
+		fn check(&mut self, record: &WalRecord) -> Result<(), WriteRejection> {
+			let (key, len) = match record {
+				WalRecord::Set { key, value, .. } => (*key, key.len() + value.len()),
+				WalRecord::Delete { key, .. } => (*key, key.len()),
+				WalRecord::DeleteRange { start, end, .. } => (*start, start.len() + end.len()),
+			};
+			if !key.starts_with(self.prefix) {
+				return Ok(());
+			}
+			if len > self.remaining {
+				return Err(WriteRejection { reason: format!("quota exceeded by {} bytes", len - self.remaining) });
+			}
+			self.remaining -= len;
+			Ok(())
 		}
-		wal.flush().unwrap();
+	}
 
-		let (wal, mem_table) = WAL::from_dir(&dir).unwrap();
+	#[test]
+	fn test_write_policy() {
+		let mem_wal = MemWAL::new();
+		let (wal, _) = mem_wal.open(WalOptions::default()).unwrap();
+		let mut wal = wal.with_write_policy(Box::new(QuotaPolicy { prefix: b"b/", remaining: 20 }));
+
+		wal.set(b"a/Monday", b"Rejoice, rejoice, rejoice", 0).unwrap();
+		wal.set(b"b/Monday", b"Rejoice", 5).unwrap();
+		let error = wal.set(b"b/Tuesday", b"Celebrate", 10).unwrap_err();
+		assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+		let rejection = error.get_ref().and_then(|e| e.downcast_ref::<WriteRejection>()).unwrap();
+		assert_eq!(rejection.reason, "quota exceeded by 13 bytes");
+
+		// A batch is rejected as a whole
+		assert!(wal.append_many(&[
+			WalRecord::Delete { key: b"a/Monday", timestamp: 21 },
+			WalRecord::Delete { key: b"b/Friday", timestamp: 21 },
+		]).is_err());
+		wal.delete(b"b/Mon", 21).unwrap();
+		assert_eq!(wal.last_sequence(), 3);
+		wal.close().unwrap();
+
+		let (_, mem_table) = mem_wal.open(WalOptions::default()).unwrap();
 		assert_eq!(mem_table.len(), 3);
+		assert!(mem_table.get(b"b/Tuesday").is_none());
+	}
 
-		for (idx, (wal_entry, e)) in wal.into_iter().zip(entries.iter()).enumerate() {
-			check_entry(&wal_entry, e.0, e.1, idx as u128, false);
+	#[test]
+	fn test_compare_and_set() {
+		let mem_wal = MemWAL::new();
+		let (mut wal, mut table) = mem_wal.open(WalOptions::default()).unwrap();
 
-			let table_e = mem_table.get(e.0).unwrap();
-			assert_eq!(table_e.key, e.0);
-			assert_eq!(table_e.value.as_ref().unwrap().as_slice(), e.1.unwrap());
-			assert_eq!(table_e.timestamp, idx as u128);
+		let outcome = wal.compare_and_set(&mut table, b"Monday", None, b"Rejoice", 0).unwrap();
+		assert_eq!(outcome, Some(SetOutcome::Inserted));
+		let outcome = wal.compare_and_set(&mut table, b"Monday", Some(b"Blues"), b"Blues", 5).unwrap();
+		assert_eq!(outcome, None);
+		assert_eq!(wal.last_sequence(), 1);
+		let outcome = wal.compare_and_set(&mut table, b"Monday", Some(b"Rejoice"), b"Celebrate", 10).unwrap();
+		assert_eq!(outcome, Some(SetOutcome::Updated));
+		wal.close().unwrap();
+
+		// Only the successful writes are recovered
+		let (wal, recovered) = mem_wal.open(WalOptions::default()).unwrap();
+		assert_eq!(wal.last_sequence(), 2);
+		assert_eq!(recovered.get(b"Monday").unwrap().value.as_deref(), Some(&b"Celebrate"[..]));
+		assert_eq!(recovered.get(b"Monday").unwrap().timestamp, 10);
+	}
+
+	#[test]
+	fn test_compare_and_set_rejected() {
+		let mem_wal = MemWAL::new();
+		let (mut wal, _) = mem_wal.open(WalOptions::default()).unwrap();
+
+		// A write to a full MemTable isn't recorded
+		let mut full = MemTable::with_capacity(usize::MAX, 1);
+		full.set(b"Sunday", b"Rest", 0);
+		let outcome = wal.compare_and_set(&mut full, b"Monday", None, b"Blues", 5).unwrap();
+		assert_eq!(outcome, Some(SetOutcome::Full));
+		assert_eq!(wal.last_sequence(), 0);
+
+		// Nor is a write rejected by the StaleWritePolicy
+		let mut table = MemTable::new().with_stale_write_policy(StaleWritePolicy::Reject);
+		let outcome = wal.compare_and_set(&mut table, b"Monday", None, b"Rejoice", 10).unwrap();
+		assert_eq!(outcome, Some(SetOutcome::Inserted));
+		let outcome = wal.compare_and_set(&mut table, b"Monday", Some(b"Rejoice"), b"Blues", 5).unwrap();
+		assert_eq!(outcome, Some(SetOutcome::Stale));
+		assert_eq!(wal.last_sequence(), 1);
+		wal.close().unwrap();
+
+		let (wal, recovered) = mem_wal.open(WalOptions::default()).unwrap();
+		assert_eq!(wal.last_sequence(), 1);
+		assert_eq!(recovered.get(b"Monday").unwrap().value.as_deref(), Some(&b"Rejoice"[..]));
+	}
+
+	#[test]
+	fn test_commit_token() {
+		let mem_wal = MemWAL::new();
+		let (mut wal, _) = mem_wal.open(WalOptions::default()).unwrap();
+
+		let first = wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		let batch = wal.append_many(&[
+			WalRecord::Delete { key: b"Monday", timestamp: 5 },
+			WalRecord::Set { key: b"Tuesday", value: b"Celebrate", meta: None, timestamp: 10 },
+		]).unwrap();
+		assert_eq!(first, CommitToken { sequence: 1 });
+		assert_eq!(batch, CommitToken { sequence: 3 });
+		assert!(first < batch);
+
+		assert!(!wal.is_durable(batch));
+		wal.sync().unwrap();
+		assert!(wal.is_durable(first) && wal.is_durable(batch));
+	}
+
+	#[test]
+	fn test_subscribe() {
+		let mem_wal = MemWAL::new();
+		let (mut wal, _) = mem_wal.open(WalOptions::default()).unwrap();
+		wal.set(b"users/Sunday", b"Rest", 0).unwrap();
+		let users = wal.subscribe(b"users/");
+
+		wal.set(b"users/Monday", b"Rejoice", 5).unwrap();
+		wal.set(b"orders/Monday", b"Blues", 5).unwrap();
+		wal.append_many(&[
+			WalRecord::Delete { key: b"users/Tuesday", timestamp: 10 },
+			WalRecord::DeleteRange { start: b"users/A", end: b"users/Z", timestamp: 15 },
+		]).unwrap();
+		// Writes are received once durable
+		assert!(users.try_recv().is_err());
+		wal.sync().unwrap();
+
+		let events: Vec<(u64, Vec<u8>)> = users.try_iter().map(|e| (e.sequence, e.entry.key)).collect();
+		assert_eq!(events, vec![(2, b"users/Monday".to_vec()), (4, b"users/Tuesday".to_vec()), (5, b"users/A".to_vec())]);
+
+		wal.delete(b"users/Monday", 20).unwrap();
+		wal.close().unwrap();
+		let event = users.recv().unwrap();
+		assert_eq!((event.sequence, event.entry.deleted), (6, true));
+		assert!(users.recv().is_err());
+	}
+
+	#[test]
+	fn test_rate_limit() {
+		let mem_wal = MemWAL::new();
+		let (wal, _) = mem_wal.open(WalOptions::default()).unwrap();
+		let mut wal = wal.with_write_policy(Box::new(RateLimit::new(Some(2), Some(1000))));
+
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		let error = wal.delete(b"Friday", 21).unwrap_err();
+		let rejection = error.get_ref().and_then(|e| e.downcast_ref::<WriteRejection>()).unwrap();
+		assert_eq!(rejection.reason, "throttled: writes per second limit exceeded");
+		assert_eq!(wal.last_sequence(), 2);
+
+		// A blocking limit waits for the bucket to refill
+		let mut wal = wal.with_write_policy(Box::new(RateLimit::new(None, Some(100)).blocking()));
+		let started = Instant::now();
+		wal.set(b"Monday", &[0; 90], 30).unwrap();
+		wal.set(b"Tuesday", &[0; 43], 40).unwrap();
+		assert!(started.elapsed() >= Duration::from_millis(400));
+		assert_eq!(wal.last_sequence(), 4);
+
+		// A limit of zero rejects every record, even when blocking
+		let mut wal = wal.with_write_policy(Box::new(RateLimit::new(Some(0), None)));
+		let error = wal.set(b"Monday", b"Rejoice", 50).unwrap_err();
+		let rejection = error.get_ref().and_then(|e| e.downcast_ref::<WriteRejection>()).unwrap();
+		assert_eq!(rejection.reason, "throttled: writes per second limit exceeded");
+		let mut wal = wal.with_write_policy(Box::new(RateLimit::new(None, Some(0)).blocking()));
+		let error = wal.set(b"Monday", b"Rejoice", 50).unwrap_err();
+		let rejection = error.get_ref().and_then(|e| e.downcast_ref::<WriteRejection>()).unwrap();
+		assert_eq!(rejection.reason, "throttled: bytes per second limit exceeded");
+		assert_eq!(wal.last_sequence(), 4);
+	}
+
+	#[test]
+	fn test_repair() {
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		wal.set(b"Wednesday", b"Work", 15).unwrap();
+		let path = wal.path().to_owned();
+		wal.close().unwrap();
+
+		// An intact WAL is left as it is
+		let report = WAL::repair(&path).unwrap();
+		assert_eq!((report.entries_kept, report.bytes_dropped), (3, 0));
+
+		// Corrupt the second record, and cut the file short mid record
+		let mut contents = read(&path).unwrap();
+		contents[53 + 10] ^= 0xff;
+		contents.extend_from_within(..20);
+		write(&path, &contents).unwrap();
+
+		let report = WAL::repair(&path).unwrap();
+		assert_eq!(report.entries_kept, 1);
+		assert_eq!(report.bytes_kept, 53);
+		assert_eq!(report.bytes_dropped, contents.len() as u64 - 53);
+		assert_eq!(report.corruptions.len(), 1);
+		assert_eq!(metadata(&path).unwrap().len(), 53);
+
+		let (_, mem_table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(mem_table.len(), 1);
+		assert!(mem_table.get(b"Monday").is_some());
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_read_unframed_wal() {
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		// Write records back to back, as before the block format
+		let records = [
+			WalRecord::Set { key: b"Monday", value: b"Rejoice", meta: None, timestamp: 0 },
+			WalRecord::Delete { key: b"Tuesday", timestamp: 10 },
+			WalRecord::DeleteRange { start: b"F", end: b"G", timestamp: 15 },
+		];
+		let mut contents = Vec::new();
+		for record in records.iter() {
+			record.encode(&mut contents);
 		}
+		let path = dir.join("1.wal");
+		write(&path, &contents).unwrap();
+
+		let mut iter = WALIterator::new(path.clone()).unwrap().with_offsets();
+		let offsets: Vec<(u64, WALEntry)> = iter.by_ref().map(|(offset, _, entry)| (offset, entry)).collect();
+		let expected: Vec<WALEntry> = records.iter().map(|r| r.to_entry()).collect();
+		assert_eq!(offsets.iter().map(|(_, e)| e.clone()).collect::<Vec<_>>(), expected);
+		let mut iter = iter.into_inner();
+		assert!(iter.is_unframed());
+		iter.seek_to_offset(offsets[1].0).unwrap();
+		assert_eq!(iter.next(), Some(expected[1].clone()));
+
+		// The file can't be appended to, but recovery rewrites it framed
+		let error = WAL::from_path(&path).err().unwrap();
+		assert_eq!(error.kind(), ErrorKind::InvalidData);
+		let (wal, table) = WAL::from_dir(&dir).unwrap();
+		assert_eq!(table.get(b"Monday").unwrap().value.as_deref(), Some(&b"Rejoice"[..]));
+		assert!(table.get(b"Tuesday").unwrap().deleted);
+		assert_eq!(table.range_tombstones().len(), 1);
+		let path = wal.path().to_owned();
+		wal.close().unwrap();
+		let mut iter = WALIterator::new(path).unwrap();
+		assert_eq!(iter.by_ref().collect::<Vec<_>>(), expected);
+		assert!(!iter.is_unframed());
+
+		remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_entry_offsets() {
+		let storage = MemoryStorage::new();
+		let mut wal = WAL::from_storage(Box::new(storage.clone()), WalOptions::default()).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.append_many(&[
+			WalRecord::Set { key: b"Tuesday", value: b"Celebrate", meta: None, timestamp: 10 },
+			WalRecord::Delete { key: b"Monday", timestamp: 11 },
+		]).unwrap();
+		wal.set(b"Wednesday", b"Work", 15).unwrap();
+		wal.close().unwrap();
+		let contents = storage.contents();
+
+		let offsets: Vec<(u64, u64, u128)> = WALIterator::from_reader(Cursor::new(contents.clone()))
+			.with_offsets()
+			.map(|(offset, len, entry)| (offset, len, entry.timestamp))
+			.collect();
+		// The entries of the batch share its record
+		assert_eq!(offsets[0], (0, 53, 0));
+		assert_eq!((offsets[1].0, offsets[2].0), (53, 53));
+		assert_eq!(offsets[1].1, offsets[2].1);
+		assert_eq!(offsets[3].0, offsets[1].0 + offsets[1].1);
+		assert_eq!(offsets[3].0 + offsets[3].1, contents.len() as u64);
+
+		let mut iter = WALIterator::from_reader(Cursor::new(contents));
+		iter.next().unwrap();
+		iter.seek_to_offset(offsets[3].0).unwrap();
+		let entry = iter.next().unwrap();
+		assert_eq!(entry.key, b"Wednesday");
+		assert!(iter.next().is_none());
+		iter.seek_to_offset(53).unwrap();
+		let keys: Vec<Vec<u8>> = iter.map(|e| e.key).collect();
+		assert_eq!(keys, vec![b"Tuesday".to_vec(), b"Monday".to_vec(), b"Wednesday".to_vec()]);
+	}
+
+	#[test]
+	fn test_open_dir_readonly() {
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Wednesday", b"Work", 15).unwrap();
+		wal.close().unwrap();
+		let mut wal = WAL::new(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		wal.set(b"Tuesday", b"Celebrate", 10).unwrap();
+		wal.flush().unwrap();
+
+		// The directory is read while open, and left as it was
+		let files = read_dir(&dir).unwrap().count();
+		let entries: Vec<(Vec<u8>, u128)> = open_dir_readonly(&dir).unwrap()
+			.map(|e| e.unwrap())
+			.map(|e| (e.key, e.timestamp))
+			.collect();
+		assert_eq!(entries, vec![
+			(b"Monday".to_vec(), 0),
+			(b"Tuesday".to_vec(), 10),
+			(b"Wednesday".to_vec(), 15),
+		]);
+		assert_eq!(read_dir(&dir).unwrap().count(), files);
+		assert_eq!(wal.segments().unwrap().len(), 2);
+		wal.close().unwrap();
 
 		remove_dir_all(&dir).unwrap();
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_destroy_dir() {
+		let dir = PathBuf::from(format!("./{}/", rand::thread_rng().gen::<u32>()));
+		create_dir(&dir).unwrap();
+
+		let (mut wal, _) = WAL::from_dir(&dir).unwrap();
+		wal.set(b"Monday", b"Rejoice", 0).unwrap();
+		assert_eq!(WAL::destroy_dir(&dir).unwrap_err().kind(), ErrorKind::WouldBlock);
+		wal.close().unwrap();
+		let retention = RetentionPolicy { keep_segments: Some(1), keep_for: None };
+		let (wal, _) = WAL::from_dir_with_retention(&dir, &retention, None, &SystemClock).unwrap();
+		wal.close().unwrap();
+
+		// A file which doesn't belong to the WAL stops anything being deleted
+		write(dir.join("notes.txt"), b"Celebrate").unwrap();
+		let error = WAL::destroy_dir(&dir).unwrap_err();
+		assert_eq!(error.kind(), ErrorKind::InvalidInput);
+		assert_eq!(segment_catalog(&dir).unwrap().len(), 1);
+		assert_eq!(segment_catalog(&dir.join("archive")).unwrap().len(), 1);
+
+		remove_file(dir.join("notes.txt")).unwrap();
+		WAL::destroy_dir(&dir).unwrap();
+		assert!(!dir.exists());
+	}
+}
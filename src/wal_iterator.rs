@@ -1,126 +1,512 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
 use std::io::BufReader;
 use std::io::Read;
+use std::io::Seek;
 use std::path::PathBuf;
 
+use crate::log_format::LogReader;
+use crate::wal::BATCH_KIND;
+
 
 /// WAL Entry mirrors the MemTable entry in the mem_table module
+///
+/// A deleted entry with a `range_end` records the deletion of every key in
+///   the range `[key, range_end)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct WALEntry {
 	pub key: Vec<u8>,
 	pub value: Option<Vec<u8>>,
 	pub timestamp: u128,
 	pub deleted: bool,
+	pub range_end: Option<Vec<u8>>,
+	pub meta: Option<Vec<u8>>,
+}
+
+
+// WAL Iterator allows iterating over the entries in a WAL file, or any
+// other source of the bytes of a WAL
+//
+// Each entry in the WAL is stored as a record of the log format, with 
+// enough metadata to recover the keys and values of the records.
+pub struct WALIterator<R: Read = BufReader<File>> {
+	reader: LogReader<R>,
+	// Entries decoded from a batch which are yet to be returned
+	pending: VecDeque<WALEntry>,
+	// The offset and length of the record holding the pending entries
+	pending_position: (u64, u64),
+	limits: WalLimits,
+	// The records skipped so far
+	corruptions: Vec<Corruption>,
+	// The length of the WAL up to the end of the last record decoded before
+	//	any corruption
+	valid_len: u64,
+}
+
+
+/// An iterator over the entries of a WAL alongside the offset and length
+///   in bytes of the record holding each, created with
+///   `WALIterator::with_offsets`.
+///
+/// The entries of a batch share the offset and length of their record.
+pub struct WalOffsetIterator<R: Read = BufReader<File>> {
+	iter: WALIterator<R>,
+}
+
+
+/// WalLimits bound the lengths of the keys and values read from a WAL.
+///
+/// A record claiming a longer key or value is treated as corrupt and
+///   skipped. Metadata and range end keys are bound by the value and key
+///   limits respectively.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalLimits {
+	pub max_key_len: usize,
+	pub max_value_len: usize,
+}
+
+
+/// A Corruption describes a record of a WAL which couldn't be decoded, or
+///   a fragment of the log which failed its checksum, and was skipped, at
+///   an offset in bytes from the start of the WAL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corruption {
+	pub offset: u64,
+	pub reason: &'static str,
 }
 
 
-// WAL Iterator allows iterating over the entries in a WAL file
+// The reason given for records which end before all their fields
+const TRUNCATED: &str = "record is truncated";
+
+
+// WAL Merge Iterator merges the entries of several WALs into the order of
+// their timestamps.
+//
+// Each WAL is expected to hold its entries in timestamp order, so only the
+// next entry of each needs to be held. Entries with the same timestamp are
+// yielded in the order of the WALs, so later WALs are replayed last.
+//
+// An entry older than the entry yielded before it is yielded as it is 
+// found, and counted as out of order. Any iterator of entries can be 
+// merged, such as the entries of WALs which have already been decoded.
 //
-// Each entry in the WAL will be stored back-to-back with enough metadata
-// to recover the keys and values of the records.
-pub struct WALIterator {
-	reader: BufReader<File>,
+// If `skip_duplicates` is set, an entry identical to one already yielded
+// from another WAL with the same timestamp is skipped, so WALs which hold
+// copies of the same records, such as after a crash while they were being
+// merged, are replayed once.
+pub struct WalMergeIterator<I: Iterator<Item = WALEntry> = WALIterator> {
+	iters: Vec<I>,
+	// The next entry of each WAL
+	heads: Vec<Option<WALEntry>>,
+	// The timestamp and index of each WAL with a next entry
+	heap: BinaryHeap<Reverse<(u128, usize)>>,
+	last_timestamp: Option<u128>,
+	out_of_order: usize,
+	skip_duplicates: bool,
+	// The entries yielded with the last timestamp and the index of their WAL
+	recent: Vec<(usize, WALEntry)>,
+	duplicates: usize,
 }
 
 
 impl WALIterator {
 	pub fn new(path: PathBuf) -> io::Result<WALIterator> {
 		let file = OpenOptions::new().read(true).open(path)?;
-		let reader = BufReader::new(file);
-		Ok(WALIterator { reader })
+		Ok(WALIterator::from_reader(BufReader::new(file)))
 	}
+}
 
-	fn read_key(&mut self, key_len: usize) -> Option<Vec<u8>> {
-		let mut key = vec![0; key_len];
-		if self.reader.read_exact(&mut key).is_err() {
-			return None;
+impl<R: Read> WALIterator<R> {
+	// Creates a WALIterator over the bytes of a WAL read from a source
+	pub fn from_reader(src: R) -> WALIterator<R> {
+		WALIterator {
+			reader: LogReader::new(src),
+			pending: VecDeque::new(),
+			pending_position: (0, 0),
+			limits: WalLimits::default(),
+			corruptions: Vec::new(),
+			valid_len: 0,
 		}
-		Some(key)
 	}
 
-	fn read_value(&mut self, value_len: usize) -> Option<Vec<u8>> {
-		let mut value = vec![0; value_len];
-		if self.reader.read_exact(&mut value).is_err() {
-			return None;
+	// Sets the limits on the lengths of the keys and values read
+	pub fn with_limits(mut self, limits: WalLimits) -> WALIterator<R> {
+		self.limits = limits;
+		self
+	}
+
+	// Iterates over the entries alongside the offset and length of the
+	//	record holding each, so the records can be referred to by their
+	//	position in the WAL
+	pub fn with_offsets(self) -> WalOffsetIterator<R> {
+		WalOffsetIterator { iter: self }
+	}
+
+	// Moves the iterator to the record at an offset within the WAL, such as
+	//	one yielded by `with_offsets`, so reading can resume from it.
+	//
+	// An offset which isn't the start of a record is read as corruption up
+	//	to the end of its block
+	pub fn seek_to_offset(&mut self, offset: u64) -> io::Result<()>
+	where
+		R: Seek,
+	{
+		self.pending.clear();
+		self.reader.seek(offset)
+	}
+
+	// Reads the next entry alongside the offset and length of its record
+	fn next_with_offset(&mut self) -> Option<(u64, u64, WALEntry)> {
+		// Records which can't be decoded are skipped and recorded as corrupt
+		loop {
+			if let Some(entry) = self.pending.pop_front() {
+				let (offset, len) = self.pending_position;
+				return Some((offset, len, entry));
+			}
+			let record = self.reader.read_record();
+			let skipped = self.reader.take_corruptions().into_iter();
+			self.corruptions.extend(skipped.map(|(offset, reason)| Corruption { offset, reason }));
+			let record = record?;
+			let offset = self.reader.last_record_offset();
+			self.pending_position = (offset, self.reader.position() - offset);
+			let decoded = if record.get(8) == Some(&BATCH_KIND) {
+				decode_batch(&record, &self.limits).map(|entries| self.pending.extend(entries))
+			} else {
+				decode_entry(&record, &self.limits).map(|entry| self.pending.push_back(entry))
+			};
+			match decoded {
+				Ok(()) if self.corruptions.is_empty() => self.valid_len = self.reader.position(),
+				Ok(()) => {},
+				Err(reason) => self.corruptions.push(Corruption { offset, reason }),
+			}
 		}
-		Some(value)
 	}
 
-	fn read_timestamp(&mut self) -> Option<u128> {
-		let mut timestamp = [0; 16];
-		if self.reader.read_exact(&mut timestamp).is_err() {
-			return None
+	// Gets the records which were skipped because they couldn't be decoded,
+	//	in the order they were read
+	pub fn corruptions(&self) -> &[Corruption] {
+		&self.corruptions
+	}
+
+	// Gets the length in bytes of the WAL up to the end of the last record
+	//	read before the first corruption, so every record within it is valid.
+	//
+	// Records cut short by the end of the WAL are not included
+	pub fn valid_len(&self) -> u64 {
+		self.valid_len
+	}
+
+	// Takes the error which ended reading the WAL before its end, if any.
+	//
+	// The iterator ends on an error reading its source, so this should be
+	//	checked once it is exhausted to tell a failed read from the end of
+	//	the WAL
+	pub fn take_read_error(&mut self) -> Option<io::Error> {
+		self.reader.take_read_error()
+	}
+
+	// Gets whether the WAL was written without framing, by a version before
+	//	the block format, which is known once an entry has been read
+	pub fn is_unframed(&self) -> bool {
+		self.reader.is_unframed()
+	}
+}
+
+impl Default for WalLimits {
+	// Keys of up to 1MB and values of up to 1GB are accepted
+	fn default() -> WalLimits {
+		WalLimits {
+			max_key_len: 1 << 20,
+			max_value_len: 1 << 30,
 		}
-		Some(u128::from_le_bytes(timestamp))
 	}
 }
 
-impl Iterator for WALIterator {
+impl From<Corruption> for io::Error {
+	fn from(corruption: Corruption) -> io::Error {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("corrupt WAL record at offset {}: {}", corruption.offset, corruption.reason),
+		)
+	}
+}
+
+// Decodes a length stored as a u64. Lengths which don't fit in a usize
+//	are saturated, so they are caught by the limits on lengths
+fn decode_len(buf: [u8; 8]) -> usize {
+	usize::try_from(u64::from_le_bytes(buf)).unwrap_or(usize::MAX)
+}
+
+// The lengths read from a record are checked against the bytes left in it
+//	before allocating, so a corrupt length can't exhaust memory
+fn read_key(reader: &mut &[u8], key_len: usize) -> Option<Vec<u8>> {
+	if reader.len() < key_len {
+		return None;
+	}
+	let (key, rest) = reader.split_at(key_len);
+	*reader = rest;
+	Some(key.to_vec())
+}
+
+fn read_value(reader: &mut &[u8], value_len: usize) -> Option<Vec<u8>> {
+	read_key(reader, value_len)
+}
+
+fn read_timestamp(reader: &mut &[u8]) -> Option<u128> {
+	let mut timestamp = [0; 16];
+	if reader.read_exact(&mut timestamp).is_err() {
+		return None
+	}
+	Some(u128::from_le_bytes(timestamp))
+}
+
+impl<R: Read> Iterator for WALIterator<R> {
 	type Item = WALEntry;
 
+	// Each record of the log holds a single entry
+	//
 	// +---------------+---------------+-----------------+-...-+--...--+-----------------+
 	// | Key Size (8B) | Tombstone(1B) | Value Size (8B) | Key | Value | Timestamp (16B) |
 	// +---------------+---------------+-----------------+-...-+--...--+-----------------+
 	//
+	// Sizes and Counts are little-endian u64s, regardless of the width of
+	//	usize on the platform which wrote the WAL
+	//
 	// Key Size = Length of the Key data
-	// Tombstone = If this record was deleted and has a value
-	// Value Size = Length of the Value data
-	// Key = Key data
-	// Value = Value data
+	// Tombstone = 0 if the record is a set, 1 if it is a delete which has no
+	//	Value Size or Value, 2 if it is a range delete, 3 if it is a set with
+	//	metadata
+	// Value Size = Length of the Value data, or of the range end key data
+	// Key = Key data, or the range start key data
+	// Value = Value data, or the range end key data
 	// Timestamp = Timestamp of the operation in microseconds
+	//
+	// Sets with metadata add the length of the metadata after the Value Size,
+	//	and the metadata itself after the Value
+	//
+	// +---------------+---------------+-----------------+----------------+-...-+--...--+--...--+-----------------+
+	// | Key Size (8B) | Tombstone(1B) | Value Size (8B) | Meta Size (8B) | Key | Value | Meta  | Timestamp (16B) |
+	// +---------------+---------------+-----------------+----------------+-...-+--...--+--...--+-----------------+
+	//
+	// A batch of entries written together is held in a single record, with
+	//	a Tombstone of 4, whose entries are each prefixed by their length
+	//
+	// +-----------+---------------+----------------+---------+-----+----------------+---------+
+	// | Count (8B)| Tombstone(1B) | Entry Size (8B)| Entry   | ... | Entry Size (8B)| Entry   |
+	// +-----------+---------------+----------------+---------+-----+----------------+---------+
 
 	fn next(&mut self) -> Option<WALEntry> {
-		let mut len_buffer = [0; 8];
-		
-		// First attempt to read the size of the key -- 8 bytes
-		if self.reader.read_exact(&mut len_buffer).is_err() {
-			return None;
-		}
-		let key_len = usize::from_le_bytes(len_buffer);
+		self.next_with_offset().map(|(_, _, entry)| entry)
+	}
+}
+
+impl<R: Read> WalOffsetIterator<R> {
+	// Unwraps the WalOffsetIterator, returning the WALIterator
+	pub fn into_inner(self) -> WALIterator<R> {
+		self.iter
+	}
+}
+
+impl<R: Read> Iterator for WalOffsetIterator<R> {
+	type Item = (u64, u64, WALEntry);
+
+	fn next(&mut self) -> Option<(u64, u64, WALEntry)> {
+		self.iter.next_with_offset()
+	}
+}
+
+// Decodes the entries from a record holding a batch, failing if any of
+//	them are malformed
+fn decode_batch(mut reader: &[u8], limits: &WalLimits) -> Result<Vec<WALEntry>, &'static str> {
+	let mut len_buffer = [0; 8];
+	reader.read_exact(&mut len_buffer).map_err(|_| TRUNCATED)?;
+	let count = decode_len(len_buffer);
+	reader = reader.get(1..).ok_or(TRUNCATED)?;
 
-		// Next attempt to read if the entry is deleted of not -- 1 byte
-		let mut bool_buffer = [0; 1];
-		if self.reader.read_exact(&mut bool_buffer).is_err() {
-			return None;
+	let mut entries = Vec::new();
+	for _ in 0..count {
+		reader.read_exact(&mut len_buffer).map_err(|_| TRUNCATED)?;
+		let len = decode_len(len_buffer);
+		if reader.len() < len {
+			return Err(TRUNCATED);
 		}
-		let deleted = bool_buffer[0] != 0;
+		let (record, rest) = reader.split_at(len);
+		entries.push(decode_entry(record, limits)?);
+		reader = rest;
+	}
+	Ok(entries)
+}
+
+// Decodes an entry from a record read from the log
+fn decode_entry(mut reader: &[u8], limits: &WalLimits) -> Result<WALEntry, &'static str> {
+	let mut len_buffer = [0; 8];
+	
+	// First attempt to read the size of the key -- 8 bytes
+	if reader.read_exact(&mut len_buffer).is_err() {
+		return Err(TRUNCATED);
+	}
+	let key_len = decode_len(len_buffer);
+	if key_len > limits.max_key_len {
+		return Err("key length exceeds the limit");
+	}
+
+	// Next attempt to read the kind of the entry -- 1 byte
+	let mut bool_buffer = [0; 1];
+	if reader.read_exact(&mut bool_buffer).is_err() {
+		return Err(TRUNCATED);
+	}
+	let tombstone = bool_buffer[0];
+	let deleted = tombstone == 1 || tombstone == 2;
 
-		let mut key = None;
-		let mut value = None;
-		if deleted {
+	let key;
+	let mut value = None;
+	let mut range_end = None;
+	let mut meta = None;
+	match tombstone {
+		0 | 2 => {
+			// If it's not a deleted entry, read length of the value -- 8 bytes
+			//	then read the key and value. Range deletes store their end key
+			//	in place of the value
+			if reader.read_exact(&mut len_buffer).is_err() {
+				return Err(TRUNCATED);
+			}
+			let value_len = decode_len(len_buffer);
+			let limit = if deleted { limits.max_key_len } else { limits.max_value_len };
+			if value_len > limit {
+				return Err("value length exceeds the limit");
+			}
+
+			key = read_key(&mut reader, key_len).ok_or(TRUNCATED)?;
+			let data = read_value(&mut reader, value_len).ok_or(TRUNCATED)?;
+			if deleted {
+				range_end = Some(data);
+			} else {
+				value = Some(data);
+			}
+		},
+		1 => {
 			// If it's a deleted entry, immediately read the key since there's no
 			//	value len to read.
-			key = self.read_key(key_len);
-			if !key.is_some() {
-				return None;
+			key = read_key(&mut reader, key_len).ok_or(TRUNCATED)?;
+		},
+		3 => {
+			// If it's a set with metadata, read the length of the value and of
+			//	the metadata -- 8 bytes each, then the key, value and metadata
+			if reader.read_exact(&mut len_buffer).is_err() {
+				return Err(TRUNCATED);
 			}
-		} else {
-			// If it's not a deleted entry, read length of the value -- 8 bytes
-			//	then read the key and value
-			if self.reader.read_exact(&mut len_buffer).is_err() {
-        return None;
-      }
-			let value_len = usize::from_le_bytes(len_buffer);
-			
-			key = self.read_key(key_len);
-			value = self.read_value(value_len);
-			if !key.is_some() || !value.is_some() {
-				return None;
+			let value_len = decode_len(len_buffer);
+			if reader.read_exact(&mut len_buffer).is_err() {
+				return Err(TRUNCATED);
+			}
+			let meta_len = decode_len(len_buffer);
+			if value_len > limits.max_value_len || meta_len > limits.max_value_len {
+				return Err("value length exceeds the limit");
 			}
+
+			key = read_key(&mut reader, key_len).ok_or(TRUNCATED)?;
+			value = Some(read_value(&mut reader, value_len).ok_or(TRUNCATED)?);
+			meta = Some(read_value(&mut reader, meta_len).ok_or(TRUNCATED)?);
+		},
+		_ => return Err("unknown record kind"),
+	}
+
+	// Finally read the timestamp
+	let timestamp = read_timestamp(&mut reader).ok_or(TRUNCATED)?;
+
+	Ok(WALEntry{
+		key,
+		value,
+		timestamp,
+		deleted,
+		range_end,
+		meta,
+	})
+}
+
+
+impl<I: Iterator<Item = WALEntry>> WalMergeIterator<I> {
+	// Creates a WalMergeIterator over WALs ordered from oldest to newest
+	pub fn new(iters: Vec<I>) -> WalMergeIterator<I> {
+		let mut merge = WalMergeIterator {
+			heads: iters.iter().map(|_| None).collect(),
+			iters,
+			heap: BinaryHeap::new(),
+			last_timestamp: None,
+			out_of_order: 0,
+			skip_duplicates: false,
+			recent: Vec::new(),
+			duplicates: 0,
+		};
+		for idx in 0..merge.iters.len() {
+			merge.advance(idx);
 		}
+		merge
+	}
+
+	// Skips entries identical to one already yielded from another WAL with
+	//	the same timestamp
+	pub fn skip_duplicates(mut self) -> WalMergeIterator<I> {
+		self.skip_duplicates = true;
+		self
+	}
+
+	// Gets the number of entries yielded so far which were older than the
+	//	entry yielded before them
+	pub fn out_of_order(&self) -> usize {
+		self.out_of_order
+	}
+
+	// Gets the number of entries skipped as duplicates so far
+	pub fn duplicates(&self) -> usize {
+		self.duplicates
+	}
 
-		// Finally read the timestamp
-		let timestamp = self.read_timestamp();
-		if !timestamp.is_some() {
-			return None
+	// Gets the iterators being merged, such as to check them for errors
+	pub fn iters_mut(&mut self) -> &mut [I] {
+		&mut self.iters
+	}
+
+	// Reads the next entry of a WAL into its head
+	fn advance(&mut self, idx: usize) {
+		if let Some(entry) = self.iters[idx].next() {
+			self.heap.push(Reverse((entry.timestamp, idx)));
+			self.heads[idx] = Some(entry);
 		}
+	}
+}
 
-		Some(WALEntry{
-			key: key.unwrap(),
-			value: value,
-			timestamp: timestamp.unwrap(),
-			deleted: deleted,
-		})
+impl<I: Iterator<Item = WALEntry>> Iterator for WalMergeIterator<I> {
+	type Item = WALEntry;
+
+	fn next(&mut self) -> Option<WALEntry> {
+		loop {
+			let Reverse((timestamp, idx)) = self.heap.pop()?;
+			let entry = self.heads[idx].take()?;
+			self.advance(idx);
+
+			if self.last_timestamp.is_some_and(|last| timestamp < last) {
+				self.out_of_order += 1;
+			} else {
+				if self.last_timestamp != Some(timestamp) {
+					self.recent.clear();
+				}
+				self.last_timestamp = Some(timestamp);
+			}
+
+			if self.skip_duplicates {
+				if self.recent.iter().any(|(i, e)| *i != idx && *e == entry) {
+					self.duplicates += 1;
+					continue;
+				}
+				self.recent.push((idx, entry.clone()));
+			}
+			return Some(entry);
+		}
 	}
-}
\ No newline at end of file
+}